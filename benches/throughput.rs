@@ -0,0 +1,54 @@
+//! Runs the default throughput benchmarks, compares them with the previous
+//! `cargo bench` run, and fails when any got slower than the default
+//! threshold. `cargo run --release -- bench` picks other cases and baselines.
+
+use std::{fs, path::Path};
+
+use blossim::{bench, Error};
+
+fn main() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("target/bench");
+    let path = dir.join("throughput.json");
+
+    let report = match pollster::block_on(bench::run(&bench::Options::default())) {
+        Ok(report) => report,
+        Err(Error::NoAdapter) => {
+            eprintln!("No graphics adapter was found, skipping the benchmarks");
+            return;
+        }
+        Err(error) => panic!("{error}"),
+    };
+    print!("{report}");
+
+    let baseline = path.exists().then(|| bench::Report::load(&path));
+    fs::create_dir_all(&dir).expect("create the benchmark directory");
+    report.save(&path).unwrap_or_else(|error| panic!("{error}"));
+
+    let Some(baseline) = baseline
+        .transpose()
+        .unwrap_or_else(|error| panic!("{error}"))
+    else {
+        return;
+    };
+    if !baseline.comparable(&report) {
+        eprintln!("The previous run was on another adapter or trail format, not comparing");
+        return;
+    }
+
+    println!("Compared with the previous run:");
+    let changes = bench::compare(&baseline, &report);
+    for change in &changes {
+        println!("  {change}");
+    }
+
+    let slower: Vec<_> = changes
+        .iter()
+        .filter(|change| change.regressed(bench::DEFAULT_THRESHOLD))
+        .collect();
+    assert!(
+        slower.is_empty(),
+        "{} benchmarks are more than {}% slower than the previous run",
+        slower.len(),
+        bench::DEFAULT_THRESHOLD
+    );
+}
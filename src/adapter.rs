@@ -0,0 +1,184 @@
+//! Choosing a graphics adapter and opening a device on it.
+
+use crate::{
+    error::{Error, Result},
+    profiler,
+    trail::{self, TrailSupport},
+};
+
+/// Graphics APIs adapters can be picked from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backend {
+    Vulkan,
+    Metal,
+    Dx12,
+    Dx11,
+    Gl,
+}
+
+/// Which adapter to open the device on. Without a name, the highest
+/// performance adapter is picked, and a software one only when there is
+/// nothing else.
+#[derive(Clone, Debug, Default)]
+pub struct AdapterOptions {
+    /// Only consider adapters of this backend
+    pub backend: Option<Backend>,
+    /// Part of the adapter's name, ignoring case
+    pub name: Option<String>,
+}
+
+impl Backend {
+    pub fn backends(&self) -> wgpu::Backends {
+        match self {
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Metal => wgpu::Backends::METAL,
+            Backend::Dx12 => wgpu::Backends::DX12,
+            Backend::Dx11 => wgpu::Backends::DX11,
+            Backend::Gl => wgpu::Backends::GL,
+        }
+    }
+}
+
+impl AdapterOptions {
+    pub fn backends(&self) -> wgpu::Backends {
+        self.backend
+            .map_or(wgpu::Backends::all(), |backend| backend.backends())
+    }
+
+    /// Instance on the backends adapters are picked from.
+    pub fn instance(&self) -> wgpu::Instance {
+        wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: self.backends(),
+            dx12_shader_compiler: Default::default(),
+        })
+    }
+
+    /// Every adapter matching the options.
+    pub fn adapters(&self, instance: &wgpu::Instance) -> Vec<wgpu::Adapter> {
+        instance
+            .enumerate_adapters(self.backends())
+            .filter(|adapter| self.matches(&adapter.get_info()))
+            .collect()
+    }
+
+    /// Whether the adapter described by `info` is of the backend and has
+    /// the name asked for.
+    pub fn matches(&self, info: &wgpu::AdapterInfo) -> bool {
+        let backend = wgpu::Backends::from(info.backend);
+        let name = info.name.to_lowercase();
+        self.backends().contains(backend)
+            && self
+                .name
+                .as_deref()
+                .is_none_or(|filter| name.contains(&filter.to_lowercase()))
+    }
+
+    /// Adapter to open the device on, able to present to `surface` if given.
+    pub async fn select(
+        &self,
+        instance: &wgpu::Instance,
+        surface: Option<&wgpu::Surface>,
+    ) -> Result<wgpu::Adapter> {
+        if let Some(name) = &self.name {
+            return self
+                .adapters(instance)
+                .into_iter()
+                .find(|adapter| surface.is_none_or(|surface| adapter.is_surface_supported(surface)))
+                .ok_or_else(|| Error::AdapterNotFound(name.clone()));
+        }
+
+        let request = |force_fallback_adapter| {
+            instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: surface,
+                force_fallback_adapter,
+            })
+        };
+
+        match request(false).await {
+            Some(adapter) => Ok(adapter),
+            None => request(true).await.ok_or(Error::NoAdapter),
+        }
+    }
+}
+
+/// Optional features used when the adapter has them.
+pub fn optional_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+    trail::optional_features(adapter) | profiler::optional_features(adapter)
+}
+
+/// Opens a device on `adapter` with its optional features and `limits`, and
+/// works out how trail maps can be accessed on it.
+pub async fn request_device(
+    adapter: &wgpu::Adapter,
+    limits: wgpu::Limits,
+) -> Result<(wgpu::Device, wgpu::Queue, TrailSupport)> {
+    let features = optional_features(adapter);
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                features,
+                limits,
+                label: None,
+            },
+            None,
+        )
+        .await?;
+
+    let trail_support = TrailSupport::new(adapter, features);
+    if trail_support.formats().is_empty() {
+        return Err(Error::NoTrailFormat);
+    }
+
+    Ok((device, queue, trail_support))
+}
+
+/// One line description of an adapter.
+pub fn describe(info: &wgpu::AdapterInfo) -> String {
+    let mut description = format!("{} ({:?}, {:?}", info.name, info.backend, info.device_type);
+    if !info.driver.is_empty() {
+        description += &format!(", {} {}", info.driver, info.driver_info);
+    }
+    description.trim_end().to_owned() + ")"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str, backend: wgpu::Backend) -> wgpu::AdapterInfo {
+        wgpu::AdapterInfo {
+            name: name.to_owned(),
+            vendor: 0,
+            device: 0,
+            device_type: wgpu::DeviceType::DiscreteGpu,
+            driver: String::new(),
+            driver_info: String::new(),
+            backend,
+        }
+    }
+
+    #[test]
+    fn names_match_in_part_ignoring_case() {
+        let options = AdapterOptions {
+            backend: None,
+            name: Some("GeForce".to_owned()),
+        };
+        assert!(options.matches(&info("NVIDIA GeForce RTX 3070", wgpu::Backend::Vulkan)));
+        assert!(options.matches(&info("nvidia geforce gtx 1060", wgpu::Backend::Gl)));
+        assert!(!options.matches(&info("llvmpipe (LLVM 15.0.7, 256 bits)", wgpu::Backend::Gl)));
+
+        let any = AdapterOptions::default();
+        assert!(any.matches(&info("llvmpipe (LLVM 15.0.7, 256 bits)", wgpu::Backend::Gl)));
+    }
+
+    #[test]
+    fn backends_must_match() {
+        let options = AdapterOptions {
+            backend: Some(Backend::Vulkan),
+            name: Some("geforce".to_owned()),
+        };
+        assert!(options.matches(&info("NVIDIA GeForce RTX 3070", wgpu::Backend::Vulkan)));
+        assert!(!options.matches(&info("NVIDIA GeForce RTX 3070", wgpu::Backend::Gl)));
+    }
+}
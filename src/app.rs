@@ -1,73 +1,62 @@
-use std::time::{Duration, Instant};
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
 
-use rand::Rng;
-use wgpu::util::DeviceExt;
-use winit::{event::WindowEvent, window::Window};
+use winit::{
+    event::{ElementState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent},
+    window::Window,
+};
 
-use crate::{
-    gui, pipeline,
-    storage::{self, Agent, Storable},
+use crate::gui;
+use blossim::{
+    adapter::AdapterOptions,
+    camera::Camera,
+    error::{DeviceErrors, Error, Result},
+    profiler::{Pass, Profiler},
+    renderer::Renderer,
+    scene::Scene,
+    screenshot,
+    simulation::{Simulation, Timing},
 };
 
-const APPROXIMATE_NUM_AGENTS: usize = 600000;
-
-const QUAD_VERTICIES: &[storage::Vertex] = &[
-    storage::Vertex {
-        position: glam::f32::vec3(-1.0, 1.0, 0.0),
-        uvs: glam::f32::vec2(0.0, 1.0),
-    },
-    storage::Vertex {
-        position: glam::f32::vec3(-1.0, -1.0, 0.0),
-        uvs: glam::f32::vec2(0.0, 0.0),
-    },
-    storage::Vertex {
-        position: glam::f32::vec3(1.0, -1.0, 0.0),
-        uvs: glam::f32::vec2(1.0, 0.0),
-    },
-    storage::Vertex {
-        position: glam::f32::vec3(1.0, 1.0, 0.0),
-        uvs: glam::f32::vec2(1.0, 1.0),
-    },
-];
-
-const QUAD_INDICES: &[u32] = &[0, 1, 2, 2, 3, 0];
+/// Zoom factor of one line of mouse wheel scrolling.
+const ZOOM_STEP: f32 = 1.1;
+
+/// Pixels of touchpad scrolling that count as one line.
+const PIXELS_PER_LINE: f32 = 40.0;
+
+/// How often the scene file is checked for changes.
+const SCENE_POLL_INTERVAL: Duration = Duration::from_millis(500);
 
 pub struct State {
-    globals: Globals,
     surface: wgpu::Surface,
     device: wgpu::Device,
     queue: wgpu::Queue,
+    /// Errors the device reports while running, shown in the GUI
+    errors: DeviceErrors,
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
-    pipelines: Pipelines,
-    pipeline_data: PipelineData,
+    simulation: Simulation,
+    renderer: Renderer,
+    /// Draws screenshots, which may not share the surface's format
+    screenshot_renderer: Renderer,
+    camera: Camera,
+    profiler: Profiler,
     gui_layer: GuiLayer,
+    scene_file: Option<SceneFile>,
+    cursor: Option<winit::dpi::PhysicalPosition<f64>>,
+    /// Whether the left button is held down over the simulation
+    brushing: bool,
+    /// Whether the middle button is held down, dragging the camera
+    panning: bool,
 }
 
-pub struct Timing {
-    pub time: Instant,
-    pub time_since_last_frame: Duration,
-    pub start_time: Instant,
-    pub frame: usize,
-}
-
-pub struct Globals {
-    pub timing: Timing,
-    pub work_groups: glam::UVec3,
-}
-
-pub struct Pipelines {
-    diffuse: pipeline::compute::ComputePipeline,
-    simulation: pipeline::compute::ComputePipeline,
-    render: pipeline::render::RenderPipeline,
-}
-
-pub struct PipelineData {
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
-    globals_buffer: wgpu::Buffer,
-    agents_buffer: wgpu::Buffer,
-    render_texture: wgpu::TextureView,
+/// Scene file that is reloaded whenever it changes on disk.
+struct SceneFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
 }
 
 pub struct GuiLayer {
@@ -79,72 +68,23 @@ pub struct GuiLayer {
 }
 
 impl State {
-    pub async fn new(window: &Window) -> Self {
+    pub async fn new(
+        window: &Window,
+        scene_path: Option<PathBuf>,
+        adapter: &AdapterOptions,
+    ) -> Result<Self> {
         let size = window.inner_size();
 
-        let globals = Globals {
-            timing: {
-                let now = std::time::Instant::now();
-                Timing {
-                    time: now,
-                    start_time: now,
-                    time_since_last_frame: Duration::ZERO,
-                    frame: 0,
-                }
-            },
-            work_groups: {
-                let x = (APPROXIMATE_NUM_AGENTS as f32).sqrt().ceil() as u32;
-                glam::UVec3::new(x, x, 1)
-            },
-        };
-
-        let num_work_groups = globals.work_groups.x * globals.work_groups.y * globals.work_groups.z;
-
-        println!("Agents: {}, size: {}", num_work_groups, globals.work_groups);
-
-        let agents: Vec<storage::Agent> = (0..num_work_groups)
-            .map(|_| Agent {
-                position: glam::f32::Vec2 {
-                    x: size.width as f32,
-                    y: size.height as f32,
-                } * 0.5
-                    + random_in_unit_circle()
-                        * 0.33
-                        * f32::min(size.width as f32, size.height as f32),
-                velocity: random_unit_circle() * 24.0,
-            })
-            .collect();
-
-        // Backends::all => Vulkan + Metal + DX12 + Browser WebGPU
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            dx12_shader_compiler: Default::default(),
-        });
+        let instance = adapter.instance();
 
         // The surface needs to live as long as the window that created it.
         // State owns the window so this should be safe.
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
-
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            })
-            .await
-            .unwrap();
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
-                    limits: wgpu::Limits::default(),
-                    label: None,
-                },
-                None,
-            )
-            .await
-            .unwrap();
+        let surface = unsafe { instance.create_surface(&window) }?;
+
+        let adapter = adapter.select(&instance, Some(&surface)).await?;
+        let (device, queue, trail_support) =
+            blossim::adapter::request_device(&adapter, wgpu::Limits::default()).await?;
+        let errors = DeviceErrors::install(&device);
 
         let surface_caps = surface.get_capabilities(&adapter);
 
@@ -154,7 +94,8 @@ impl State {
             .iter()
             .copied()
             .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
+            .or(surface_caps.formats.first().copied())
+            .ok_or(Error::UnsupportedSurface)?;
 
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -168,71 +109,29 @@ impl State {
 
         surface.configure(&device, &config);
 
-        let pipelines = Pipelines {
-            diffuse: pipeline::compute::ComputePipeline::diffuse(&device),
-            simulation: pipeline::compute::ComputePipeline::simulation(&device),
-            render: pipeline::render::RenderPipeline::new(&device, surface_format),
+        let scene_file = scene_path.map(SceneFile::new);
+        let mut scene = match &scene_file {
+            Some(file) => Scene::load(&file.path)?,
+            None => Scene::default(),
         };
 
-        let pipeline_data = PipelineData {
-            vertex_buffer: {
-                let bytes = bytemuck::cast_slice(QUAD_VERTICIES);
-                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Vertex Buffer"),
-                    contents: &bytes,
-                    usage: wgpu::BufferUsages::VERTEX,
-                })
-            },
-            index_buffer: {
-                let bytes = bytemuck::cast_slice(QUAD_INDICES);
-                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Index Buffer"),
-                    contents: &bytes,
-                    usage: wgpu::BufferUsages::INDEX,
-                })
-            },
-            globals_buffer: {
-                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Globals buffer"),
-                    contents: {
-                        let storage: storage::Globals = (&globals).into();
-                        let uniform = storage::Uniform(&storage);
-                        &uniform.into_bytes()
-                    },
-                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-                })
-            },
-            agents_buffer: {
-                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Agents buffer"),
-                    contents: {
-                        let buffer = storage::Buffer(&agents);
-                        &buffer.into_bytes()
-                    },
-                    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-                })
-            },
-            render_texture: {
-                let texture = device.create_texture(&wgpu::TextureDescriptor {
-                    label: Some("Output texture"),
-                    size: wgpu::Extent3d {
-                        width: size.width,
-                        height: size.height,
-                        depth_or_array_layers: 1,
-                    },
-                    mip_level_count: 1,
-                    sample_count: 1,
-                    dimension: wgpu::TextureDimension::D2,
-                    format: wgpu::TextureFormat::Rgba32Float,
-                    usage: wgpu::TextureUsages::STORAGE_BINDING,
-                    view_formats: &[wgpu::TextureFormat::Rgba32Float],
-                });
+        let fallback = scene.resolve_trail_format(&trail_support.formats())?;
 
-                texture.create_view(&wgpu::TextureViewDescriptor::default())
-            },
-        };
+        let renderer = Renderer::new(&device, surface_format, scene.trail_format)?;
+        let screenshot_renderer = Renderer::new(&device, screenshot::FORMAT, scene.trail_format)?;
+
+        let simulation = Simulation::new(
+            &device,
+            &queue,
+            glam::UVec2::new(size.width, size.height),
+            Timing::new(None),
+            scene,
+            trail_support.clone(),
+        )?;
 
-        let gui_layer = {
+        let profiler = Profiler::new(&device, &queue);
+
+        let mut gui_layer = {
             let ctx = egui::Context::default();
             let state = egui_winit::State::new(window);
             let renderer = egui_wgpu::Renderer::new(&device, surface_format, None, 1);
@@ -241,22 +140,36 @@ impl State {
                 ctx,
                 state,
                 renderer,
-                interface: gui::Interface::new(),
+                interface: gui::Interface::new(
+                    adapter.get_info(),
+                    device.features(),
+                    trail_support,
+                ),
                 enabled: true,
             }
         };
+        if let Some(fallback) = fallback {
+            gui_layer.interface.report(fallback);
+        }
 
-        Self {
-            globals,
+        Ok(Self {
             surface,
             device,
             queue,
+            errors,
             config,
             size,
-            pipelines,
-            pipeline_data,
+            simulation,
+            renderer,
+            screenshot_renderer,
+            camera: Camera::default(),
+            profiler,
             gui_layer,
-        }
+            scene_file,
+            cursor: None,
+            brushing: false,
+            panning: false,
+        })
     }
 
     pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
@@ -283,38 +196,150 @@ impl State {
             .on_event(&self.gui_layer.ctx, event)
             .consumed;
 
+        if let WindowEvent::KeyboardInput {
+            input:
+                winit::event::KeyboardInput {
+                    virtual_keycode: Some(winit::event::VirtualKeyCode::Space),
+                    state: winit::event::ElementState::Released,
+                    ..
+                },
+            ..
+        } = event
+        {
+            self.gui_layer.enabled = !self.gui_layer.enabled;
+            handled = true;
+        }
+
         match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                if let (true, Some(cursor)) = (self.panning, self.cursor) {
+                    let delta = glam::vec2(
+                        (position.x - cursor.x) as f32 / self.size.width as f32,
+                        (cursor.y - position.y) as f32 / self.size.height as f32,
+                    );
+                    self.camera.pan(delta);
+                }
+                self.cursor = Some(*position);
+            }
+            WindowEvent::CursorLeft { .. } => self.cursor = None,
+            WindowEvent::MouseWheel { delta, .. } if !handled => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+                };
+                if let Some(position) = self.view_position() {
+                    self.camera.zoom_at(position, ZOOM_STEP.powf(lines));
+                    handled = true;
+                }
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Middle,
+                ..
+            } if !handled || *state == ElementState::Released => {
+                self.panning = *state == ElementState::Pressed;
+                handled = true;
+            }
             WindowEvent::KeyboardInput {
                 input:
                     winit::event::KeyboardInput {
-                        virtual_keycode: Some(winit::event::VirtualKeyCode::Space),
-                        state,
+                        virtual_keycode: Some(key @ (VirtualKeyCode::R | VirtualKeyCode::F12)),
+                        state: ElementState::Released,
                         ..
                     },
                 ..
-            } => {
-                if *state == winit::event::ElementState::Released {
-                    self.gui_layer.enabled = !self.gui_layer.enabled;
+            } if !handled => {
+                match key {
+                    VirtualKeyCode::R => self.camera = Camera::default(),
+                    _ => self.gui_layer.interface.request_screenshot(),
+                }
+                handled = true;
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: button @ (MouseButton::Left | MouseButton::Right),
+                ..
+            } if !handled => {
+                self.brushing = *button == MouseButton::Left;
+                if let Some(position) = self.domain_position() {
+                    let domain = self.simulation.domain().as_vec2();
+                    self.gui_layer.interface.edit_environment(
+                        &mut self.simulation.globals.scene.environment,
+                        position,
+                        domain / domain.min_element(),
+                        *button == MouseButton::Right,
+                    );
                     handled = true;
                 }
             }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } => self.brushing = false,
             _ => {}
         }
 
         handled
     }
 
-    pub fn update(&mut self) {}
+    /// Cursor position as a fraction of the window, from its bottom left.
+    fn view_position(&self) -> Option<glam::Vec2> {
+        let cursor = self.cursor?;
+        Some(glam::vec2(
+            cursor.x as f32 / self.size.width as f32,
+            1.0 - cursor.y as f32 / self.size.height as f32,
+        ))
+    }
 
-    pub fn render(&mut self, window: &Window) -> Result<(), wgpu::SurfaceError> {
-        {
-            let now = std::time::Instant::now();
-            let prev_time = self.globals.timing.time;
-            self.globals.timing.time = now;
-            self.globals.timing.time_since_last_frame = now - prev_time;
-            self.globals.timing.frame += 1;
+    /// Cursor position as a fraction of the simulation domain, from its bottom left.
+    fn domain_position(&self) -> Option<glam::Vec2> {
+        Some(self.camera.domain_point(self.view_position()?))
+    }
+
+    pub fn update(&mut self) {
+        self.profiler.poll(&self.device, false);
+        self.simulation.poll(&self.device);
+
+        for error in self.errors.take() {
+            if error.is_fatal() {
+                eprintln!("{}", error);
+            }
+            self.gui_layer.interface.report(error);
+        }
+        for error in self.simulation.take_errors() {
+            self.gui_layer.interface.report(error);
         }
 
+        if let Some(file) = &mut self.scene_file {
+            let interface = &mut self.gui_layer.interface;
+            match file.poll() {
+                Some(Ok(mut scene)) => {
+                    match scene.resolve_trail_format(interface.trail_formats()) {
+                        Ok(fallback) => {
+                            if let Some(fallback) = fallback {
+                                interface.report(fallback);
+                            }
+                            self.simulation.globals.scene = scene;
+                        }
+                        Err(e) => interface.report(e),
+                    }
+                }
+                Some(Err(e)) => interface.report(e),
+                None => {}
+            }
+        }
+    }
+
+    pub fn render(&mut self, window: &Window) -> Result<(), wgpu::SurfaceError> {
+        self.simulation.globals.timing.tick();
+
+        let brush = self
+            .domain_position()
+            .filter(|_| self.brushing)
+            .and_then(|position| self.gui_layer.interface.brush(position));
+        self.simulation.set_brush(brush);
+
         let output = self.surface.get_current_texture()?;
         let view = output
             .texture
@@ -328,155 +353,47 @@ impl State {
 
         let mut cmd_buffer = Vec::new();
 
-        // Copy globals to GPU
-        {
-            let bytes = {
-                let storage: storage::Globals = (&self.globals).into();
-                storage::Uniform(&storage).into_bytes()
-            };
-
-            let globals_buffer =
-                self.device
-                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        label: None,
-                        contents: &bytes,
-                        usage: wgpu::BufferUsages::COPY_SRC,
-                    });
-
-            encoder.copy_buffer_to_buffer(
-                &globals_buffer,
-                0,
-                &self.pipeline_data.globals_buffer,
-                0,
-                bytes.len() as wgpu::BufferAddress,
-            );
-        }
-
-        // Diffuse pass
-        {
-            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("Diffuse bind group"),
-                layout: &self.pipelines.diffuse.bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: self.pipeline_data.globals_buffer.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: wgpu::BindingResource::TextureView(
-                            &self.pipeline_data.render_texture,
-                        ),
-                    },
-                ],
-            });
-
-            {
-                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                    label: Some("Diffuse pass"),
-                });
-
-                compute_pass.set_pipeline(&self.pipelines.diffuse.pipeline);
-                compute_pass.set_bind_group(0, &bind_group, &[]);
-                compute_pass.dispatch_workgroups(self.size.width, self.size.height, 1)
-            }
-        }
-
-        // Simulation pass
-        {
-            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("Simulation bind group"),
-                layout: &self.pipelines.simulation.bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: self.pipeline_data.globals_buffer.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: self.pipeline_data.agents_buffer.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: wgpu::BindingResource::TextureView(
-                            &self.pipeline_data.render_texture,
-                        ),
-                    },
-                ],
+        self.profiler
+            .set_config(self.simulation.globals.scene.trail_format.name());
+        self.profiler.begin_frame();
+
+        // Errors are shown by the GUI, which is still drawn over what was recorded
+        let recorded = self
+            .simulation
+            .step(&self.device, &self.queue, &mut encoder, &mut self.profiler)
+            .and_then(|()| {
+                self.renderer.render(
+                    &self.device,
+                    &mut encoder,
+                    &output.texture,
+                    &self.simulation,
+                    &self.camera,
+                    &mut self.profiler,
+                )
             });
-
-            {
-                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                    label: Some("Simulation pass"),
-                });
-
-                compute_pass.set_pipeline(&self.pipelines.simulation.pipeline);
-                compute_pass.set_bind_group(0, &bind_group, &[]);
-                compute_pass.dispatch_workgroups(
-                    self.globals.work_groups.x,
-                    self.globals.work_groups.y,
-                    self.globals.work_groups.z,
-                );
-            }
-        }
-
-        // Render pass
-        {
-            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("Render bind group"),
-                layout: &self.pipelines.render.bind_group_layout,
-                entries: &[wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(
-                        &self.pipeline_data.render_texture,
-                    ),
-                }],
-            });
-
-            {
-                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                    label: Some("Render Pass"),
-                    color_attachments: &[
-                        // This is what @location(0) in the fragment shader targets
-                        Some(wgpu::RenderPassColorAttachment {
-                            view: &view,
-                            resolve_target: None,
-                            ops: wgpu::Operations {
-                                load: wgpu::LoadOp::Clear(wgpu::Color {
-                                    r: 1.0,
-                                    g: 0.0,
-                                    b: 0.5,
-                                    a: 1.0,
-                                }),
-                                store: true,
-                            },
-                        }),
-                    ],
-                    depth_stencil_attachment: None,
-                });
-
-                render_pass.set_pipeline(&self.pipelines.render.pipeline);
-                render_pass.set_bind_group(0, &bind_group, &[]);
-                render_pass.set_vertex_buffer(0, self.pipeline_data.vertex_buffer.slice(..));
-                render_pass.set_index_buffer(
-                    self.pipeline_data.index_buffer.slice(..),
-                    wgpu::IndexFormat::Uint32,
-                );
-
-                render_pass.draw_indexed(0..6, 0, 0..1);
-            }
+        if let Err(e) = recorded {
+            self.gui_layer.interface.report(e);
         }
 
         // GUI Pass
         if self.gui_layer.enabled {
+            self.profiler.begin_pass(&mut encoder, Pass::Gui);
+
             let screen_descriptor = egui_wgpu::renderer::ScreenDescriptor {
-                size_in_pixels: [self.size.width as u32, self.size.height as u32],
+                size_in_pixels: [self.size.width, self.size.height],
                 pixels_per_point: self.gui_layer.state.pixels_per_point(),
             };
 
             let input = self.gui_layer.state.take_egui_input(window);
+            let live_agents = self.simulation.live_agents();
             let output = self.gui_layer.ctx.run(input, |ctx| {
-                self.gui_layer.interface.ui(ctx, &mut self.globals);
+                self.gui_layer.interface.ui(
+                    ctx,
+                    &mut self.simulation.globals,
+                    &mut self.camera,
+                    live_agents,
+                    &self.profiler,
+                );
             });
 
             self.gui_layer.state.handle_platform_output(
@@ -506,26 +423,32 @@ impl State {
                 &screen_descriptor,
             );
 
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("GUI Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("GUI Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: true,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                });
+
+                self.gui_layer
+                    .renderer
+                    .render(&mut render_pass, &paint_jobs, &screen_descriptor);
+            }
 
-            self.gui_layer
-                .renderer
-                .render(&mut render_pass, &paint_jobs, &screen_descriptor);
+            self.profiler.end_pass(&mut encoder, Pass::Gui);
 
-            cmd_buffer.extend(gui_commands.into_iter());
+            cmd_buffer.extend(gui_commands);
         }
 
+        self.profiler.end_frame(&mut encoder);
+
         // submit will accept anything that implements IntoIter
         self.queue.submit(
             cmd_buffer
@@ -534,35 +457,73 @@ impl State {
         );
         output.present();
 
+        self.profiler.after_submit();
+        self.simulation.after_submit();
+
+        if let Some(view) = self.gui_layer.interface.take_screenshot() {
+            self.save_screenshot(view);
+        }
+
         Ok(())
     }
-}
 
-fn random_unit_circle() -> glam::f32::Vec2 {
-    let mut rng = rand::thread_rng();
-    let theta = rng.gen_range(0.0..std::f32::consts::TAU);
-    glam::f32::vec2(theta.cos(), theta.sin())
-}
-
-fn random_in_unit_circle() -> glam::f32::Vec2 {
-    let mut rng = rand::thread_rng();
-    let theta = rng.gen_range(0.0..std::f32::consts::TAU);
-    let r = rng.gen_range(0.0..1.0);
-    glam::f32::vec2(r * theta.cos(), r * theta.sin())
-}
+    /// Captures the trail map and writes it next to the working directory.
+    fn save_screenshot(&mut self, view: screenshot::View) {
+        let (camera, size) = match view {
+            screenshot::View::Camera => {
+                (self.camera, glam::uvec2(self.size.width, self.size.height))
+            }
+            screenshot::View::Domain => (Camera::default(), self.simulation.domain()),
+        };
 
-impl Timing {
-    pub fn dt(&self) -> f32 {
-        self.time_since_last_frame.as_secs_f32()
+        let seconds = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = PathBuf::from(format!("screenshot-{}.png", seconds));
+        let saved = screenshot::capture(
+            &self.device,
+            &self.queue,
+            &mut self.screenshot_renderer,
+            &self.simulation,
+            &camera,
+            size,
+        )
+        .and_then(|image| screenshot::save(&image, &path));
+        match saved {
+            Ok(()) => eprintln!("Saved {}", path.display()),
+            Err(e) => self.gui_layer.interface.report(e),
+        }
     }
 }
 
-impl From<&Globals> for storage::Globals {
-    fn from(globals: &Globals) -> Self {
+impl SceneFile {
+    fn new(path: PathBuf) -> Self {
         Self {
-            dt: globals.timing.dt(),
-            time: (globals.timing.time - globals.timing.start_time).as_secs_f32(),
-            work_group_size: globals.work_groups.x,
+            modified: modified_time(&path),
+            path,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Reloads the scene if the file has changed since it was last read.
+    fn poll(&mut self) -> Option<Result<Scene>> {
+        if self.last_poll.elapsed() < SCENE_POLL_INTERVAL {
+            return None;
+        }
+
+        self.last_poll = Instant::now();
+
+        let modified = modified_time(&self.path);
+        if modified == self.modified {
+            return None;
         }
+
+        self.modified = modified;
+        Some(Scene::load(&self.path))
     }
 }
+
+fn modified_time(path: &std::path::Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,503 @@
+//! Throughput benchmarks: agents updated and trail pixels diffused per
+//! second across agent counts and domain sizes.
+//!
+//! Passes are timed with GPU timestamps when the adapter has them. Otherwise
+//! every frame is waited on, diffusion is timed by the median frame without
+//! agents, and the agent update is what the agents add on top. Reports are written
+//! as JSON and compared with an earlier one to catch regressions.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    adapter::{self, AdapterOptions},
+    error::{DeviceErrors, Error, Result},
+    profiler::{Pass, Profiler},
+    scene::Scene,
+    simulation::{Simulation, Timing},
+    trail::{TrailFormat, TrailSupport},
+};
+
+/// Timestep every case is simulated with.
+const DT: f32 = 1.0 / 60.0;
+
+/// Percentage by which throughput may drop before it counts as a regression.
+pub const DEFAULT_THRESHOLD: f32 = 5.0;
+
+/// Profiler configuration of the frames that are measured.
+const MEASURED: &str = "Measured";
+
+/// Profiler configuration of the frames run before measuring.
+const WARMUP: &str = "Warmup";
+
+/// Benchmarks that got slower than the baseline allows.
+#[derive(Debug, thiserror::Error)]
+#[error("{count} benchmarks are more than {threshold}% slower than the baseline")]
+pub struct Regression {
+    pub count: usize,
+    pub threshold: f32,
+}
+
+pub struct Options {
+    pub adapter: AdapterOptions,
+    /// Scene the cases are based on with their own agent counts, the
+    /// default scene when not set
+    pub scene: Option<PathBuf>,
+    /// Trail format to benchmark, defaults to the most precise supported format
+    pub trail_format: Option<TrailFormat>,
+    pub agents: Vec<u32>,
+    pub resolutions: Vec<glam::UVec2>,
+    /// Frames measured per case
+    pub frames: usize,
+    /// Frames run before measuring each case, so caches and clocks settle
+    pub warmup: usize,
+}
+
+/// How passes were timed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Clock {
+    /// GPU timestamps around each pass
+    Timestamps,
+    /// Wall-clock time of frames waited on one by one
+    Wall,
+}
+
+/// Results of a whole run, which can be compared with those of another.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    /// Description of the adapter the run was on
+    pub adapter: String,
+    pub clock: Clock,
+    pub trail_format: TrailFormat,
+    pub frames: usize,
+    pub results: Vec<Measurement>,
+}
+
+/// Average timings of one case.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Measurement {
+    /// Agents simulated, fewer than asked for when the device can't hold them
+    pub agents: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Milliseconds per frame spent diffusing the trail map
+    pub diffuse_ms: f64,
+    /// Milliseconds per frame spent updating agents
+    pub agents_ms: f64,
+    pub agents_per_second: f64,
+    pub diffuse_megapixels_per_second: f64,
+}
+
+/// Throughput measured by a benchmark.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    Agents,
+    Diffuse,
+}
+
+/// Throughput of one case in a baseline run and in the current one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Change {
+    pub agents: u32,
+    pub width: u32,
+    pub height: u32,
+    pub metric: Metric,
+    pub baseline: f64,
+    pub current: f64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            adapter: AdapterOptions::default(),
+            scene: None,
+            trail_format: None,
+            agents: vec![65_536, 262_144, 1_048_576],
+            resolutions: vec![
+                glam::uvec2(1280, 720),
+                glam::uvec2(1920, 1080),
+                glam::uvec2(3840, 2160),
+            ],
+            frames: 120,
+            warmup: 30,
+        }
+    }
+}
+
+impl Clock {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Clock::Timestamps => "GPU timestamps",
+            Clock::Wall => "wall clock",
+        }
+    }
+}
+
+impl Report {
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        serde_json::from_str(&json).map_err(|source| Error::Json {
+            path: path.to_owned(),
+            source,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(|source| Error::Json {
+            path: path.to_owned(),
+            source,
+        })?;
+        fs::write(path, json + "\n").map_err(|e| Error::io(path, e))
+    }
+
+    /// Whether timings of the two reports can be meaningfully compared.
+    pub fn comparable(&self, other: &Report) -> bool {
+        self.adapter == other.adapter
+            && self.clock == other.clock
+            && self.trail_format == other.trail_format
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} on {}, {} over {} frames",
+            self.trail_format.name(),
+            self.adapter,
+            self.clock.name(),
+            self.frames
+        )?;
+        writeln!(
+            f,
+            "{:>9} {:>11} {:>11} {:>11} {:>12} {:>11}",
+            "agents", "domain", "agents ms", "diffuse ms", "Magents/s", "MP/s"
+        )?;
+        for m in &self.results {
+            writeln!(
+                f,
+                "{:>9} {:>11} {:>11.3} {:>11.3} {:>12.1} {:>11.1}",
+                m.agents,
+                format!("{}x{}", m.width, m.height),
+                m.agents_ms,
+                m.diffuse_ms,
+                m.agents_per_second / 1e6,
+                m.diffuse_megapixels_per_second
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Measurement {
+    fn new(agents: u32, domain: glam::UVec2, diffuse_ms: f64, agents_ms: f64) -> Self {
+        // Wall-clock differences of fast cases can come out at or below zero
+        let per_second = |ms: f64| 1000.0 / ms.max(1e-6);
+        let pixels = domain.x as f64 * domain.y as f64;
+
+        Self {
+            agents,
+            width: domain.x,
+            height: domain.y,
+            diffuse_ms,
+            agents_ms,
+            agents_per_second: agents as f64 * per_second(agents_ms),
+            diffuse_megapixels_per_second: pixels / 1e6 * per_second(diffuse_ms),
+        }
+    }
+
+    fn throughput(&self, metric: Metric) -> f64 {
+        match metric {
+            Metric::Agents => self.agents_per_second,
+            Metric::Diffuse => self.diffuse_megapixels_per_second,
+        }
+    }
+}
+
+impl Metric {
+    pub const ALL: [Metric; 2] = [Metric::Agents, Metric::Diffuse];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::Agents => "agents",
+            Metric::Diffuse => "diffuse",
+        }
+    }
+}
+
+impl Change {
+    /// Percentage by which throughput changed, negative when slower.
+    pub fn percent(&self) -> f64 {
+        (self.current / self.baseline - 1.0) * 100.0
+    }
+
+    /// Whether throughput dropped by more than `threshold` percent.
+    pub fn regressed(&self, threshold: f32) -> bool {
+        self.percent() < -(threshold as f64)
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<8} {:>9} agents {:>11} {:>+8.1}%",
+            self.metric.name(),
+            self.agents,
+            format!("{}x{}", self.width, self.height),
+            self.percent()
+        )
+    }
+}
+
+/// Runs every combination of agent count and resolution.
+pub async fn run(options: &Options) -> Result<Report> {
+    let instance = options.adapter.instance();
+    let adapter = options.adapter.select(&instance, None).await?;
+    let (device, queue, trail_support) =
+        adapter::request_device(&adapter, wgpu::Limits::default()).await?;
+    let errors = DeviceErrors::install(&device);
+
+    let mut scene = match &options.scene {
+        Some(path) => Scene::load(path)?,
+        None => Scene::default(),
+    };
+    if let Some(format) = options.trail_format {
+        if trail_support.access(format).is_none() {
+            return Err(Error::UnsupportedTrailFormat(format));
+        }
+
+        scene.trail_format = format;
+    }
+    if let Some(fallback) = scene.resolve_trail_format(&trail_support.formats())? {
+        eprintln!("{}", fallback);
+    }
+
+    let clock = if device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+        Clock::Timestamps
+    } else {
+        Clock::Wall
+    };
+
+    let bench = Bench {
+        device: &device,
+        queue: &queue,
+        errors: &errors,
+        trail_support: &trail_support,
+        clock,
+        frames: options.frames.max(1),
+        warmup: options.warmup,
+    };
+
+    let mut results = Vec::new();
+    for &domain in &options.resolutions {
+        // Without timestamps, diffusion is timed by a frame without agents
+        let empty = match clock {
+            Clock::Timestamps => None,
+            Clock::Wall => Some(bench.measure(&scene, 0, domain)?),
+        };
+
+        for &agents in &options.agents {
+            let (agents, timings) = bench.measure(&scene, agents, domain)?;
+            let measurement = match empty {
+                Some((_, empty)) => {
+                    Measurement::new(agents, domain, empty.frame, timings.frame - empty.frame)
+                }
+                None => Measurement::new(agents, domain, timings.diffuse, timings.agents),
+            };
+            eprintln!(
+                "{} agents at {}x{}: {:.3} ms agents, {:.3} ms diffuse",
+                agents, domain.x, domain.y, measurement.agents_ms, measurement.diffuse_ms
+            );
+            results.push(measurement);
+        }
+    }
+
+    Ok(Report {
+        adapter: adapter::describe(&adapter.get_info()),
+        clock,
+        trail_format: scene.trail_format,
+        frames: bench.frames,
+        results,
+    })
+}
+
+/// Throughput of every case in both reports, in the order of `current`.
+pub fn compare(baseline: &Report, current: &Report) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for m in &current.results {
+        let Some(b) = baseline
+            .results
+            .iter()
+            .find(|b| (b.agents, b.width, b.height) == (m.agents, m.width, m.height))
+        else {
+            continue;
+        };
+
+        for metric in Metric::ALL {
+            changes.push(Change {
+                agents: m.agents,
+                width: m.width,
+                height: m.height,
+                metric,
+                baseline: b.throughput(metric),
+                current: m.throughput(metric),
+            });
+        }
+    }
+
+    changes
+}
+
+/// Device and settings shared by every case of a run.
+struct Bench<'a> {
+    device: &'a wgpu::Device,
+    queue: &'a wgpu::Queue,
+    errors: &'a DeviceErrors,
+    trail_support: &'a TrailSupport,
+    clock: Clock,
+    frames: usize,
+    warmup: usize,
+}
+
+/// Milliseconds per measured frame, averaged for timestamps and the median
+/// for the wall clock, which stalls now and then.
+#[derive(Clone, Copy, Debug, Default)]
+struct Timings {
+    diffuse: f64,
+    agents: f64,
+    /// Whole frames including waiting for them, only measured by the wall clock
+    frame: f64,
+}
+
+impl Bench<'_> {
+    /// Simulates `agents` agents of `scene` in `domain`, returning how many
+    /// agents the device held and the average timings.
+    fn measure(&self, scene: &Scene, agents: u32, domain: glam::UVec2) -> Result<(u32, Timings)> {
+        let mut scene = scene.clone();
+        scene.agents = agents;
+
+        let timing = Timing::new(Some(Duration::from_secs_f32(DT)));
+        let mut simulation = Simulation::new(
+            self.device,
+            self.queue,
+            domain,
+            timing,
+            scene,
+            self.trail_support.clone(),
+        )?;
+        let mut profiler = Profiler::new(self.device, self.queue);
+
+        let mut wall = Vec::with_capacity(self.frames);
+        for frame in 0..self.warmup + self.frames {
+            let measured = frame >= self.warmup;
+            profiler.set_config(if measured { MEASURED } else { WARMUP });
+            simulation.globals.timing.tick();
+
+            let start = Instant::now();
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Bench encoder"),
+                });
+
+            profiler.begin_frame();
+            simulation.step(self.device, self.queue, &mut encoder, &mut profiler)?;
+            profiler.end_frame(&mut encoder);
+            self.queue.submit(std::iter::once(encoder.finish()));
+
+            profiler.after_submit();
+            simulation.after_submit();
+            self.device.poll(wgpu::Maintain::Wait);
+            if measured {
+                wall.push(start.elapsed().as_secs_f64() * 1000.0);
+            }
+
+            profiler.poll(self.device, false);
+            simulation.poll(self.device);
+            let recovered = simulation.take_errors();
+            if let Some(error) = self.errors.take().into_iter().chain(recovered).next() {
+                return Err(error);
+            }
+        }
+        profiler.poll(self.device, true);
+
+        let pass = |pass| profiler.config_average(MEASURED, pass).unwrap_or(0.0) as f64;
+        let timings = match self.clock {
+            Clock::Timestamps => Timings {
+                diffuse: pass(Pass::Diffuse),
+                agents: pass(Pass::Simulation),
+                frame: 0.0,
+            },
+            Clock::Wall => Timings {
+                frame: median(&mut wall),
+                ..Default::default()
+            },
+        };
+
+        Ok((simulation.globals.scene.agents, timings))
+    }
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(f64::total_cmp);
+    match values.len() {
+        0 => 0.0,
+        n if n % 2 == 0 => (values[n / 2 - 1] + values[n / 2]) / 2.0,
+        n => values[n / 2],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(results: Vec<Measurement>) -> Report {
+        Report {
+            adapter: "Test".to_owned(),
+            clock: Clock::Timestamps,
+            trail_format: TrailFormat::Rgba32Float,
+            frames: 1,
+            results,
+        }
+    }
+
+    #[test]
+    fn slower_cases_regress() {
+        let domain = glam::uvec2(1000, 1000);
+        let baseline = report(vec![
+            Measurement::new(1000, domain, 1.0, 1.0),
+            Measurement::new(2000, domain, 1.0, 1.0),
+        ]);
+        let current = report(vec![
+            Measurement::new(1000, domain, 1.0, 1.1),
+            Measurement::new(2000, domain, 0.5, 1.0),
+            Measurement::new(4000, domain, 1.0, 1.0),
+        ]);
+
+        let changes = compare(&baseline, &current);
+        assert_eq!(changes.len(), 4);
+
+        let regressed: Vec<_> = changes
+            .iter()
+            .filter(|c| c.regressed(DEFAULT_THRESHOLD))
+            .map(|c| (c.agents, c.metric))
+            .collect();
+        assert_eq!(regressed, [(1000, Metric::Agents)]);
+
+        assert!((changes[3].percent() - 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn reports_round_trip_through_json() {
+        let report = report(vec![Measurement::new(1000, glam::uvec2(64, 32), 0.25, 0.5)]);
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<Report>(&json).unwrap(), report);
+    }
+}
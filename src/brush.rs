@@ -0,0 +1,181 @@
+//! Brushes that paint into the trail map or push agents around for a frame.
+
+use rand::Rng;
+
+use crate::storage;
+
+/// Agents added per second by the spawn brush at full strength.
+const SPAWN_RATE: f32 = 20_000.0;
+
+/// What a brush does while the mouse button is held.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrushKind {
+    /// Adds trail intensity
+    Paint,
+    /// Removes trail intensity
+    Erase,
+    /// Moves agents away from the cursor
+    Push,
+    /// Moves agents towards the cursor
+    Pull,
+    /// Adds agents under the cursor
+    Spawn,
+}
+
+/// Brush applied for a single frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Brush {
+    pub kind: BrushKind,
+    /// Fraction of the domain from its bottom left corner
+    pub position: glam::Vec2,
+    /// Relative to the shorter side of the domain
+    pub radius: f32,
+    /// From 0 to 1
+    pub strength: f32,
+    /// Fraction of the radius over which the brush fades out
+    pub falloff: f32,
+}
+
+impl BrushKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BrushKind::Paint => "Paint",
+            BrushKind::Erase => "Erase",
+            BrushKind::Push => "Push",
+            BrushKind::Pull => "Pull",
+            BrushKind::Spawn => "Spawn",
+        }
+    }
+}
+
+impl Brush {
+    /// Centre and radius in pixels of the simulation domain.
+    pub fn pixels(&self, domain: glam::UVec2) -> (glam::Vec2, f32) {
+        (
+            self.position * domain.as_vec2(),
+            self.radius * domain.min_element() as f32,
+        )
+    }
+
+    /// Uniform for the brush shader, and the number of pixels along each side of the area it paints.
+    pub(crate) fn uniform(&self, domain: glam::UVec2, dt: f32) -> (storage::Brush, glam::UVec2) {
+        let (position, radius) = self.pixels(domain);
+        let min = (position - radius).max(glam::Vec2::ZERO).floor();
+        let max = (position + radius).ceil().min(domain.as_vec2());
+
+        let uniform = storage::Brush {
+            position,
+            origin: min.as_uvec2(),
+            radius: radius.max(1.0),
+            strength: self.strength,
+            falloff: self.falloff,
+            dt,
+            kind: self.kind as u32,
+        };
+
+        (uniform, (max - min).max(glam::Vec2::ZERO).as_uvec2())
+    }
+
+    /// Positions of the agents the spawn brush adds over `dt` seconds, in pixels.
+    pub fn spawn_positions(
+        &self,
+        domain: glam::UVec2,
+        dt: f32,
+        rng: &mut impl Rng,
+    ) -> Vec<glam::Vec2> {
+        let (center, radius) = self.pixels(domain);
+        let count = (self.strength * SPAWN_RATE * dt).round() as usize;
+
+        (0..count)
+            .map(|_| {
+                let theta = rng.gen_range(0.0..std::f32::consts::TAU);
+                let r = radius * rng.gen_range(0.0f32..1.0).sqrt();
+                center + glam::vec2(theta.cos(), theta.sin()) * r
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn brush(kind: BrushKind, position: glam::Vec2) -> Brush {
+        Brush {
+            kind,
+            position,
+            radius: 0.1,
+            strength: 0.5,
+            falloff: 0.5,
+        }
+    }
+
+    #[test]
+    fn kinds_match_the_shader() {
+        let source = crate::shader::builtin_file("brush.wgsl").unwrap();
+        for kind in [
+            BrushKind::Paint,
+            BrushKind::Erase,
+            BrushKind::Push,
+            BrushKind::Pull,
+        ] {
+            let constant = format!(
+                "const BRUSH_{} = {}u;",
+                kind.name().to_uppercase(),
+                kind as u32
+            );
+            assert!(source.contains(&constant), "{}", constant);
+        }
+    }
+
+    #[test]
+    fn sizes_are_relative_to_the_shorter_side() {
+        let brush = brush(BrushKind::Paint, glam::vec2(0.25, 0.5));
+        let (center, radius) = brush.pixels(glam::uvec2(400, 200));
+        assert_eq!(center, glam::vec2(100.0, 100.0));
+        assert_eq!(radius, 20.0);
+    }
+
+    #[test]
+    fn painted_area_is_cut_at_the_edges() {
+        let domain = glam::uvec2(400, 200);
+        let (uniform, size) = brush(BrushKind::Erase, glam::vec2(0.5, 0.5)).uniform(domain, 0.1);
+        assert_eq!(uniform.origin, glam::uvec2(180, 80));
+        assert_eq!(size, glam::uvec2(40, 40));
+        assert_eq!(uniform.kind, BrushKind::Erase as u32);
+
+        let (uniform, size) = brush(BrushKind::Paint, glam::vec2(0.01, 0.99)).uniform(domain, 0.1);
+        assert_eq!(uniform.origin, glam::uvec2(0, 178));
+        assert_eq!(size, glam::uvec2(24, 22));
+
+        let (_, size) = brush(BrushKind::Paint, glam::vec2(2.0, 2.0)).uniform(domain, 0.1);
+        assert_eq!(size, glam::UVec2::ZERO);
+    }
+
+    #[test]
+    fn tiny_brushes_still_cover_a_pixel() {
+        let mut brush = brush(BrushKind::Push, glam::vec2(0.5, 0.5));
+        brush.radius = 0.0;
+        let (uniform, _) = brush.uniform(glam::uvec2(400, 200), 0.1);
+        assert_eq!(uniform.radius, 1.0);
+    }
+
+    #[test]
+    fn spawned_agents_land_inside_the_brush() {
+        let brush = brush(BrushKind::Spawn, glam::vec2(0.5, 0.5));
+        let domain = glam::uvec2(400, 200);
+        let (center, radius) = brush.pixels(domain);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let positions = brush.spawn_positions(domain, 0.01, &mut rng);
+        assert_eq!(positions.len(), (0.5 * SPAWN_RATE * 0.01) as usize);
+        assert!(positions
+            .iter()
+            .all(|p| p.distance(center) <= radius + 1e-3));
+
+        let mut rng = StdRng::seed_from_u64(1);
+        assert_eq!(brush.spawn_positions(domain, 0.01, &mut rng), positions);
+    }
+}
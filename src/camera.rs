@@ -0,0 +1,146 @@
+//! View onto the simulation domain.
+
+use crate::storage;
+
+/// Furthest the camera can zoom in, in screen pixels per simulated pixel at 1:1.
+const MAX_ZOOM: f32 = 64.0;
+
+/// 2D view onto the simulation domain used when rendering. Positions are
+/// fractions of the domain or of the view, measured from the bottom left.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    /// Point of the domain at the centre of the view
+    center: glam::Vec2,
+    /// Fraction of the domain the view spans, 1 shows the whole domain
+    extent: glam::Vec2,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self {
+            center: glam::Vec2::splat(0.5),
+            extent: glam::Vec2::ONE,
+        }
+    }
+}
+
+impl Camera {
+    /// Camera showing the part of the domain between `min` and `max`, for
+    /// rendering it in tiles. Unlike views set by panning and zooming, the
+    /// region may be stretched.
+    pub fn region(min: glam::Vec2, max: glam::Vec2) -> Self {
+        Self {
+            center: (min + max) / 2.0,
+            extent: max - min,
+        }
+    }
+
+    pub fn zoom(&self) -> f32 {
+        1.0 / self.extent.x
+    }
+
+    /// Point of the domain shown at `p` in the view.
+    pub fn domain_point(&self, p: glam::Vec2) -> glam::Vec2 {
+        self.center + (p - 0.5) * self.extent
+    }
+
+    /// Moves the domain along with a drag of `delta` in the view.
+    pub fn pan(&mut self, delta: glam::Vec2) {
+        self.center -= delta * self.extent;
+        self.clamp();
+    }
+
+    /// Zooms by `factor`, keeping the point under `p` in the view in place.
+    pub fn zoom_at(&mut self, p: glam::Vec2, factor: f32) {
+        let anchor = self.domain_point(p);
+        self.extent =
+            (self.extent / factor).clamp(glam::Vec2::splat(1.0 / MAX_ZOOM), glam::Vec2::ONE);
+        self.center += anchor - self.domain_point(p);
+        self.clamp();
+    }
+
+    /// Transform from the view to the domain, applied to the quad's UVs in `render.wgsl`.
+    pub(crate) fn uniform(&self) -> storage::Camera {
+        storage::Camera {
+            offset: self.domain_point(glam::Vec2::ZERO),
+            scale: self.extent,
+        }
+    }
+
+    /// Keeps the view inside the domain.
+    fn clamp(&mut self) {
+        let half = 0.5 * self.extent;
+        self.center = self.center.clamp(half, 1.0 - half);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: glam::Vec2, b: glam::Vec2) -> bool {
+        a.abs_diff_eq(b, 1e-5)
+    }
+
+    #[test]
+    fn default_view_shows_the_whole_domain() {
+        let camera = Camera::default();
+        assert_eq!(camera.zoom(), 1.0);
+        assert_eq!(camera.domain_point(glam::Vec2::ZERO), glam::Vec2::ZERO);
+        assert_eq!(camera.domain_point(glam::Vec2::ONE), glam::Vec2::ONE);
+    }
+
+    #[test]
+    fn zooming_keeps_the_anchor_in_place() {
+        let mut camera = Camera::default();
+        let p = glam::vec2(0.3, 0.6);
+        let anchor = camera.domain_point(p);
+        camera.zoom_at(p, 4.0);
+
+        assert_eq!(camera.zoom(), 4.0);
+        assert!(close(camera.domain_point(p), anchor));
+    }
+
+    #[test]
+    fn zoom_is_limited() {
+        let mut camera = Camera::default();
+        camera.zoom_at(glam::Vec2::splat(0.5), 0.5);
+        assert_eq!(camera.zoom(), 1.0);
+
+        camera.zoom_at(glam::Vec2::splat(0.5), 1000.0);
+        assert_eq!(camera.zoom(), MAX_ZOOM);
+    }
+
+    #[test]
+    fn panning_stays_inside_the_domain() {
+        let mut camera = Camera::default();
+        camera.zoom_at(glam::Vec2::splat(0.5), 2.0);
+        camera.pan(glam::vec2(0.1, 0.0));
+        assert!(close(
+            camera.domain_point(glam::Vec2::splat(0.5)),
+            glam::vec2(0.45, 0.5)
+        ));
+
+        camera.pan(glam::vec2(10.0, -10.0));
+        assert!(close(
+            camera.domain_point(glam::Vec2::ZERO),
+            glam::vec2(0.0, 0.5)
+        ));
+        assert!(close(
+            camera.domain_point(glam::Vec2::ONE),
+            glam::vec2(0.5, 1.0)
+        ));
+    }
+
+    #[test]
+    fn regions_map_the_view_onto_them() {
+        let camera = Camera::region(glam::vec2(0.25, 0.5), glam::vec2(0.75, 0.625));
+        let uniform = camera.uniform();
+        assert!(close(uniform.offset, glam::vec2(0.25, 0.5)));
+        assert!(close(uniform.scale, glam::vec2(0.5, 0.125)));
+        assert!(close(
+            camera.domain_point(glam::Vec2::ONE),
+            glam::vec2(0.75, 0.625)
+        ));
+    }
+}
@@ -0,0 +1,1311 @@
+//! Reference implementation of the simulation on the CPU.
+//!
+//! Mirrors the agent update of `simulation.wgsl` and the diffusion of
+//! `diffuse.wgsl` one agent and one pixel at a time, to check the shaders
+//! against in tests and to simulate at all where there is no graphics
+//! adapter. It is orders of magnitude slower than the GPU.
+//!
+//! Only agents, trails and masks are simulated: environments, lifecycles
+//! and brushes have no effect, and [`Simulation::render`] leaves out
+//! post-processing and sprites. [`unsupported`] lists what a scene would
+//! lose. Trail maps are kept at full precision whatever their format.
+
+use glam::{UVec2, Vec2, Vec3, Vec4};
+use rand::rngs::StdRng;
+
+use crate::{
+    error::Result,
+    palette::{self, Gradient},
+    readback,
+    scene::{Boundary, ColorSource, MaskFit, MaskMode, Scene},
+    simulation::{self, spawn_agent, spawn_rng, Globals, Timing},
+    storage,
+    trail::{Trail, TrailAccess, TrailFormat},
+};
+
+pub use crate::storage::Agent;
+
+/// Pixels of the gradient lookup texture, as in `palette.rs`.
+const LUT_SIZE: usize = 256;
+
+/// Multiplier of agent velocities, before genome and mask.
+const SPEED_SCALE: f32 = 4.0;
+
+/// Radius in pixels trail density is measured over.
+const DENSITY_RADIUS: f32 = 2.0;
+
+/// Sensed where agents can't go, as in `environment.wgsl`.
+const OBSTACLE_SENSE: f32 = -1000.0;
+
+/// Radius in pixels trails are averaged over when diffusing.
+const DIFFUSE_RADIUS: i32 = 2;
+
+/// Species colours of two-species trails not coloured by species, as in `species_color.wgsl`.
+const SPECIES_A: Vec3 = Vec3::new(0.16, 0.8, 0.0);
+const SPECIES_B: Vec3 = Vec3::new(0.8, 0.0, 0.48);
+
+/// Trail map in memory, holding what the trail texture of its format would.
+/// Channels the format lacks read as they would from a texture, 0 for
+/// colour and 1 for alpha.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrailImage {
+    format: TrailFormat,
+    size: UVec2,
+    pixels: Vec<Vec4>,
+    /// Packed rgba8 colour of the latest deposit in each pixel, for formats
+    /// with a colour buffer
+    colors: Vec<u32>,
+}
+
+/// Brightness of the scene's mask from 0 to 1, as the mask texture holds
+/// it. Without a mask it is a single white pixel, like the texture.
+#[derive(Clone, Debug, PartialEq)]
+struct MaskImage {
+    size: UVec2,
+    values: Vec<f32>,
+}
+
+/// Agents, trail map and scene simulated on the CPU, advanced like a GPU
+/// [`simulation::Simulation`] one [`Simulation::step`] at a time.
+pub struct Simulation {
+    pub globals: Globals,
+    domain: UVec2,
+    /// Which trail map agents sense, matching the GPU's access to it
+    access: TrailAccess,
+    agents: Vec<Agent>,
+    trail: TrailImage,
+    /// Loaded once, with the scene the simulation starts from
+    mask: MaskImage,
+    rng: StdRng,
+}
+
+/// Trail an agent leaves behind in one step.
+struct Deposit {
+    pixel: UVec2,
+    color: Vec3,
+    amount: f32,
+    species: u32,
+}
+
+struct BoundaryPoint {
+    p: Vec2,
+    /// False for points outside a domain that doesn't wrap or reflect
+    inside: bool,
+}
+
+/// Position and velocity of an agent after moving.
+struct Motion {
+    position: Vec2,
+    velocity: Vec2,
+}
+
+/// Parts of `scene` the CPU simulation leaves out.
+pub fn unsupported(scene: &Scene) -> Vec<&'static str> {
+    let environment = &scene.environment;
+    let features = [
+        (
+            !environment.food.is_empty()
+                || !environment.obstacles.is_empty()
+                || !environment.attractors.is_empty()
+                || !environment.fields.is_empty(),
+            "environment",
+        ),
+        (scene.lifecycle.is_some(), "lifecycle"),
+        (
+            scene.post.iter().any(|stage| stage.enabled),
+            "post-processing",
+        ),
+        (scene.sprites.is_some(), "sprites"),
+    ];
+
+    features
+        .into_iter()
+        .filter_map(|(used, name)| used.then_some(name))
+        .collect()
+}
+
+impl TrailImage {
+    /// Empty trail map of `size` pixels.
+    pub fn new(format: TrailFormat, size: UVec2) -> Self {
+        let pixels = (size.x * size.y) as usize;
+        let empty = stored(format, Vec4::ZERO);
+        Self {
+            format,
+            size,
+            pixels: vec![empty; pixels],
+            colors: if format.has_color_buffer() {
+                vec![0; pixels]
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    /// Copies the latest trail map of `trail` back from the GPU.
+    pub fn read(device: &wgpu::Device, queue: &wgpu::Queue, trail: &Trail) -> Self {
+        let format = trail.format();
+        let texture = trail.texture();
+        let size = glam::uvec2(texture.width(), texture.height());
+
+        let texel_bytes = texel_bytes(format);
+        let bytes = readback::read_texture(device, queue, texture, texel_bytes);
+        let pixels = bytes
+            .chunks_exact(texel_bytes as usize)
+            .map(|pixel| stored(format, decode(format, pixel)))
+            .collect();
+
+        let colors = match trail.colors() {
+            Some(buffer) => readback::read_buffer(device, queue, buffer, buffer.size())
+                .chunks_exact(4)
+                .map(|color| u32::from_le_bytes([color[0], color[1], color[2], color[3]]))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        Self {
+            format,
+            size,
+            pixels,
+            colors,
+        }
+    }
+
+    pub fn format(&self) -> TrailFormat {
+        self.format
+    }
+
+    pub fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    /// Pixels row by row from the bottom left corner of the domain.
+    pub fn pixels(&self) -> &[Vec4] {
+        &self.pixels
+    }
+
+    pub fn load(&self, p: UVec2) -> Vec4 {
+        self.pixels[self.index(p)]
+    }
+
+    pub fn store(&mut self, p: UVec2, value: Vec4) {
+        let index = self.index(p);
+        self.pixels[index] = stored(self.format, value);
+    }
+
+    /// Total trail intensity of a pixel, over every species.
+    pub fn intensity(&self, p: UVec2) -> f32 {
+        let value = self.load(p);
+        match self.format {
+            TrailFormat::Rgba32Float | TrailFormat::Rgba16Float => value.w,
+            TrailFormat::R32Float => value.x,
+            TrailFormat::Rg16Float => value.x + value.y,
+        }
+    }
+
+    /// Trail intensity `species` senses at a pixel, as in `trail_sense`.
+    fn sense(&self, p: UVec2, species: u32) -> f32 {
+        let value = self.load(p);
+        match self.format {
+            TrailFormat::Rgba32Float | TrailFormat::Rgba16Float => value.w,
+            TrailFormat::R32Float => value.x,
+            TrailFormat::Rg16Float if species == 1 => value.y,
+            TrailFormat::Rg16Float => value.x,
+        }
+    }
+
+    /// Writes a deposit as in `trail_deposit`. `loaded` is what the shader
+    /// would read back from the pixel, which two-species trails keep the
+    /// other species of.
+    fn deposit(&mut self, deposit: &Deposit, loaded: Vec4) {
+        let Deposit {
+            pixel,
+            color,
+            amount,
+            species,
+        } = *deposit;
+
+        let value = match self.format {
+            TrailFormat::Rgba32Float | TrailFormat::Rgba16Float => (color * amount).extend(amount),
+            TrailFormat::R32Float => Vec4::splat(amount),
+            TrailFormat::Rg16Float if species == 1 => {
+                Vec4::new(loaded.x, amount, loaded.z, loaded.w)
+            }
+            TrailFormat::Rg16Float => Vec4::new(amount, loaded.y, loaded.z, loaded.w),
+        };
+
+        self.store(pixel, value);
+        if self.format.has_color_buffer() {
+            let index = self.index(pixel);
+            self.colors[index] = pack4x8unorm(color.extend(1.0));
+        }
+    }
+
+    /// Linear colour and alpha of a pixel, as in `trail_color`.
+    fn color(&self, p: UVec2, source: ColorSource, gradient: &[Vec3]) -> Vec4 {
+        let value = self.load(p);
+        match self.format {
+            TrailFormat::Rgba32Float | TrailFormat::Rgba16Float => value,
+            TrailFormat::R32Float => {
+                let color = unpack4x8unorm(self.colors[self.index(p)]);
+                (color.truncate() * value.x).extend(1.0)
+            }
+            TrailFormat::Rg16Float if source == ColorSource::Species => {
+                (lookup(gradient, 0.0) * value.x + lookup(gradient, 1.0) * value.y).extend(1.0)
+            }
+            TrailFormat::Rg16Float => (SPECIES_A * value.x + SPECIES_B * value.y).extend(1.0),
+        }
+    }
+
+    fn index(&self, p: UVec2) -> usize {
+        (p.x + p.y * self.size.x) as usize
+    }
+
+    fn contains(&self, p: UVec2) -> bool {
+        p.x < self.size.x && p.y < self.size.y
+    }
+}
+
+impl Default for MaskImage {
+    fn default() -> Self {
+        Self {
+            size: UVec2::ONE,
+            values: vec![1.0],
+        }
+    }
+}
+
+impl MaskImage {
+    fn new(scene: &Scene) -> Result<Self> {
+        let Some(mask) = &scene.environment.mask else {
+            return Ok(Self::default());
+        };
+
+        let image = mask.image()?;
+        Ok(Self {
+            size: UVec2::from(image.dimensions()),
+            values: image.pixels().map(|p| p.0[0] as f32 / 255.0).collect(),
+        })
+    }
+
+    fn load(&self, p: UVec2) -> f32 {
+        self.values[(p.x + p.y * self.size.x) as usize]
+    }
+}
+
+impl Simulation {
+    /// Spawns the agents of `scene` in an empty domain. Agents sense the
+    /// trail map after diffusion with read-write `access`, and before it
+    /// when ping-ponged, as they do on the GPU.
+    pub fn new(domain: UVec2, timing: Timing, scene: Scene, access: TrailAccess) -> Result<Self> {
+        let mut rng = spawn_rng(scene.seed);
+        let agents = (0..scene.agents)
+            .map(|i| spawn_agent(scene.spawn, domain, i, &mut rng))
+            .collect();
+
+        Ok(Self {
+            domain,
+            access,
+            agents,
+            trail: TrailImage::new(scene.trail_format, domain),
+            mask: MaskImage::new(&scene)?,
+            rng,
+            globals: Globals { timing, scene },
+        })
+    }
+
+    /// Continues `simulation` on the CPU from where it is on the GPU,
+    /// waiting for the GPU to finish.
+    pub fn read(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        simulation: &simulation::Simulation,
+    ) -> Result<Self> {
+        let scene = simulation.globals.scene.clone();
+        let (buffer, _) = simulation.agents();
+        let size = storage::Buffer::<Agent>::offset(scene.agents as usize);
+        let agents =
+            storage::Buffer::<Agent>::read(&readback::read_buffer(device, queue, buffer, size));
+
+        Ok(Self {
+            domain: simulation.domain(),
+            access: simulation.trail().access(),
+            agents,
+            trail: TrailImage::read(device, queue, simulation.trail()),
+            mask: MaskImage::new(&scene)?,
+            // Agents added later can't follow the GPU's spawns anyway
+            rng: spawn_rng(None),
+            globals: Globals {
+                timing: simulation.globals.timing.clone(),
+                scene,
+            },
+        })
+    }
+
+    /// Size of the simulation domain in pixels.
+    pub fn domain(&self) -> UVec2 {
+        self.domain
+    }
+
+    pub fn agents(&self) -> &[Agent] {
+        &self.agents
+    }
+
+    pub fn trail(&self) -> &TrailImage {
+        &self.trail
+    }
+
+    /// Diffuses the trail map and updates every agent for one frame, with
+    /// the timing's current timestep.
+    pub fn step(&mut self) {
+        let time = self.globals.timing.elapsed();
+        self.globals.scene.animate(time);
+
+        // Switching format starts over with an empty trail map
+        if self.globals.scene.trail_format != self.trail.format {
+            self.trail = TrailImage::new(self.globals.scene.trail_format, self.domain);
+        }
+
+        self.resize_agents();
+
+        let globals: storage::Globals = (&self.globals).into();
+        let gradient = gradient_lut(&self.globals.scene.palette.gradient);
+
+        let mut next = diffuse(&self.trail, &globals);
+        let deposits = match self.access {
+            TrailAccess::ReadWrite => {
+                update_agents(&mut self.agents, &next, &self.mask, &globals, &gradient)
+            }
+            TrailAccess::PingPong => update_agents(
+                &mut self.agents,
+                &self.trail,
+                &self.mask,
+                &globals,
+                &gradient,
+            ),
+        };
+
+        for deposit in &deposits {
+            let loaded = match self.access {
+                TrailAccess::ReadWrite => next.load(deposit.pixel),
+                TrailAccess::PingPong => self.trail.load(deposit.pixel),
+            };
+            next.deposit(deposit, loaded);
+        }
+
+        self.trail = next;
+    }
+
+    /// Draws the whole domain one pixel per simulated pixel, as
+    /// `render.wgsl` does with the default camera.
+    pub fn render(&self) -> image::RgbaImage {
+        let palette = &self.globals.scene.palette;
+        let gradient = gradient_lut(&palette.gradient);
+        let background = palette::to_linear(palette.background);
+        let range = palette.range.max(f32::EPSILON);
+
+        let size = self.domain;
+        image::RgbaImage::from_fn(size.x, size.y, |x, y| {
+            // Images start at the top, the domain at the bottom
+            let texel = glam::uvec2(x, size.y - 1 - y);
+
+            // How much of the background the trail covers
+            let coverage = (self.trail.intensity(texel) / range).clamp(0.0, 1.0);
+            let color = if palette.source == ColorSource::Intensity {
+                mix(background, lookup(&gradient, coverage), coverage).extend(1.0)
+            } else {
+                let color = self.trail.color(texel, palette.source, &gradient);
+                (color.truncate() + background * (1.0 - coverage)).extend(color.w)
+            };
+
+            let [r, g, b] = palette::to_srgb(color.truncate());
+            let a = (color.w.clamp(0.0, 1.0) * 255.0).round() as u8;
+            image::Rgba([r, g, b, a])
+        })
+    }
+
+    /// Grows or shrinks the population to match the scene, keeping the agents already simulated.
+    fn resize_agents(&mut self) {
+        let scene = &self.globals.scene;
+        let count = self.agents.len() as u32;
+        if scene.agents > count {
+            let rng = &mut self.rng;
+            self.agents.extend(
+                (count..scene.agents).map(|i| spawn_agent(scene.spawn, self.domain, i, rng)),
+            );
+        } else {
+            self.agents.truncate(scene.agents as usize);
+        }
+    }
+}
+
+/// Diffuses and fades `trail` by one step of `globals.dt`, as in `diffuse.wgsl`.
+fn diffuse(trail: &TrailImage, globals: &storage::Globals) -> TrailImage {
+    let size = trail.size.as_vec2();
+    let mut next = TrailImage {
+        pixels: Vec::with_capacity(trail.pixels.len()),
+        ..trail.clone()
+    };
+
+    for y in 0..trail.size.y {
+        for x in 0..trail.size.x {
+            let pixel = glam::uvec2(x, y);
+
+            // Nothing diffuses outside the arena
+            let center = boundary_point(pixel.as_vec2() + 0.5, size, globals.boundary);
+            if !center.inside {
+                next.pixels.push(stored(trail.format, Vec4::ZERO));
+                continue;
+            }
+
+            let mut color = trail.load(pixel);
+
+            let mut diffuse = Vec4::ZERO;
+            for i in -DIFFUSE_RADIUS..=DIFFUSE_RADIUS {
+                for j in -DIFFUSE_RADIUS..=DIFFUSE_RADIUS {
+                    let offset = Vec2::new(i as f32, j as f32);
+                    let point = boundary_point(center.p + offset, size, globals.boundary);
+                    if !point.inside {
+                        continue;
+                    }
+
+                    diffuse += trail.load(boundary_texel(&point, size));
+                }
+            }
+
+            let diffuse_dimension = 2.0 * DIFFUSE_RADIUS as f32 + 1.0;
+            diffuse /= diffuse_dimension * diffuse_dimension;
+
+            color = mix(color, diffuse, globals.dt * 48.0);
+
+            if globals.dt > 0.0 {
+                color = (color - globals.dt * globals.decay).max(Vec4::ZERO);
+            }
+
+            next.pixels.push(stored(trail.format, color));
+        }
+    }
+
+    next
+}
+
+/// Steers and moves every live agent sensing `trail`, as in the `update`
+/// of `simulation.wgsl`, and returns what they deposit in agent order.
+fn update_agents(
+    agents: &mut [Agent],
+    trail: &TrailImage,
+    mask: &MaskImage,
+    globals: &storage::Globals,
+    gradient: &[Vec3],
+) -> Vec<Deposit> {
+    let domain = trail.size.as_vec2();
+    let mut deposits = Vec::with_capacity(agents.len());
+    for (index, agent) in agents.iter_mut().enumerate() {
+        if agent.alive == 0 {
+            continue;
+        }
+
+        update(index as u32, agent, trail, mask, globals);
+
+        let pixel = agent.position.as_uvec2();
+        let color = lookup(
+            gradient,
+            color_position(index as u32, agent, trail, mask, globals),
+        );
+        agent.color = pack4x8unorm(color.extend(1.0));
+
+        let amount = mask_multiplier(mask, agent.position, domain, MaskMode::Deposit, globals);
+        if amount > 0.0 && trail.contains(pixel) {
+            deposits.push(Deposit {
+                pixel,
+                color,
+                amount,
+                species: agent.species,
+            });
+        }
+    }
+
+    deposits
+}
+
+fn update(
+    index: u32,
+    agent: &mut Agent,
+    trail: &TrailImage,
+    mask: &MaskImage,
+    globals: &storage::Globals,
+) {
+    let domain = trail.size.as_vec2();
+    let species = agent.species;
+    let genome = agent.genome;
+    let mut position = agent.position;
+    let mut velocity = agent.velocity;
+
+    let angle = globals.sensor_angle * genome.x;
+    let turn = globals.turn_rate * genome.y;
+    let sensor = velocity.normalize() * globals.sensor_distance * genome.w;
+
+    let left = sense(
+        trail,
+        mask,
+        position + rotate(sensor, angle),
+        species,
+        globals,
+    );
+    let right = sense(
+        trail,
+        mask,
+        position + rotate(sensor, -angle),
+        species,
+        globals,
+    );
+    let forward = sense(trail, mask, position + sensor, species, globals);
+
+    if forward >= left && forward >= right {
+        // Do nothing
+    } else if left > right {
+        velocity = rotate(velocity, turn);
+    } else {
+        velocity = rotate(velocity, -turn);
+    }
+
+    let speed =
+        SPEED_SCALE * genome.z * mask_multiplier(mask, position, domain, MaskMode::Speed, globals);
+    let step = velocity * speed;
+    let moved = move_agent(
+        index,
+        position,
+        velocity,
+        step * globals.dt,
+        trail.size,
+        globals,
+    );
+
+    // Turning around where the mask blocks the way
+    velocity = moved.velocity;
+    if mask_blocked(mask, moved.position, domain, globals) {
+        velocity = -velocity;
+    } else {
+        position = moved.position;
+    }
+
+    agent.velocity = velocity;
+    agent.position = position;
+    agent.age += globals.dt;
+}
+
+/// Moves an agent by `step`, applying the boundary when it leaves the domain.
+fn move_agent(
+    index: u32,
+    position: Vec2,
+    velocity: Vec2,
+    step: Vec2,
+    size: UVec2,
+    globals: &storage::Globals,
+) -> Motion {
+    let size = size.as_vec2();
+    let next = position + step;
+    let point = boundary_point(next, size, globals.boundary);
+
+    if globals.boundary == Boundary::Reflect as u32 {
+        let outside = next.cmplt(Vec2::ZERO) | next.cmpge(size);
+        return Motion {
+            position: point.p,
+            velocity: Vec2::select(outside, -velocity, velocity),
+        };
+    }
+
+    if point.inside {
+        return Motion {
+            position: point.p,
+            velocity,
+        };
+    }
+
+    if globals.boundary == Boundary::Circle as u32 {
+        // Bounce off the wall, staying where the agent was
+        let normal = (next - 0.5 * size).normalize();
+        return Motion {
+            position,
+            velocity: velocity - 2.0 * velocity.dot(normal) * normal,
+        };
+    }
+
+    // Respawn somewhere random, heading in a random direction
+    let mut seed = hash(index ^ hash(globals.frame));
+    let respawn = Vec2::new(random(&mut seed), random(&mut seed)) * size;
+    let heading = std::f32::consts::TAU * random(&mut seed);
+    Motion {
+        position: respawn,
+        velocity: Vec2::new(heading.cos(), heading.sin()) * velocity.length(),
+    }
+}
+
+/// Position along the gradient of the colour an agent deposits, for the
+/// colour sources that don't need an environment or lifecycle.
+fn color_position(
+    index: u32,
+    agent: &Agent,
+    trail: &TrailImage,
+    mask: &MaskImage,
+    globals: &storage::Globals,
+) -> f32 {
+    let velocity = agent.velocity;
+    match globals.color_source {
+        source if source == ColorSource::Species as u32 => agent.species as f32,
+        source if source == ColorSource::Speed as u32 => {
+            let domain = trail.size.as_vec2();
+            let multiplier =
+                mask_multiplier(mask, agent.position, domain, MaskMode::Speed, globals);
+            let step = velocity * SPEED_SCALE * agent.genome.z * multiplier;
+            0.5 * step.length() / (SPEED_SCALE * velocity.length()).max(1e-5)
+        }
+        source if source == ColorSource::Heading as u32 => {
+            velocity.y.atan2(velocity.x) / std::f32::consts::TAU + 0.5
+        }
+        source if source == ColorSource::Age as u32 => agent.age / globals.max_age,
+        source if source == ColorSource::Energy as u32 => {
+            agent.energy / globals.reproduction_energy
+        }
+        source if source == ColorSource::Density as u32 => {
+            let density = sample_area(
+                trail,
+                agent.position,
+                DENSITY_RADIUS,
+                agent.species,
+                globals,
+            );
+            density / globals.color_range
+        }
+        source if source == ColorSource::Spawn as u32 => {
+            let offset = agent.origin - 0.5 * trail.size.as_vec2();
+            offset.y.atan2(offset.x) / std::f32::consts::TAU + 0.5
+        }
+        _ => index as f32 / globals.num_agents as f32,
+    }
+}
+
+/// Trail around a sensor plus what the mask adds there, as in `sense` of
+/// `simulation.wgsl` without the environment.
+fn sense(
+    trail: &TrailImage,
+    mask: &MaskImage,
+    p: Vec2,
+    species: u32,
+    globals: &storage::Globals,
+) -> f32 {
+    let domain = trail.size.as_vec2();
+    let point = boundary_point(p, domain, globals.boundary).p;
+    if mask_blocked(mask, point, domain, globals) {
+        return OBSTACLE_SENSE;
+    }
+
+    let mut value = sample_area(trail, p, 1.0, species, globals);
+    if globals.mask_mode == mask_mode(MaskMode::Attractor) {
+        value += mask_value(mask, point, domain, globals) * globals.mask_strength;
+    }
+
+    value
+}
+
+/// Value of `mode` in the globals, which are 0 without a mask.
+fn mask_mode(mode: MaskMode) -> u32 {
+    mode as u32 + 1
+}
+
+/// Brightness of the mask at a point of the domain, 0 outside a fitted
+/// mask, as in `mask` of `simulation.wgsl`.
+fn mask_value(mask: &MaskImage, p: Vec2, domain: Vec2, globals: &storage::Globals) -> f32 {
+    mask_texel(p, domain, mask.size.as_vec2(), globals.mask_fit).map_or(0.0, |t| mask.load(t))
+}
+
+/// Texel of a mask `size` pixels large that the point `p` of the domain
+/// maps to, if any, with the fit's value in the globals.
+fn mask_texel(p: Vec2, domain: Vec2, size: Vec2, fit: u32) -> Option<UVec2> {
+    let mut uv = p / domain * size;
+    if fit == MaskFit::Fit as u32 {
+        let scale = (domain.x / size.x).min(domain.y / size.y);
+        uv = (p - 0.5 * (domain - size * scale)) / scale;
+        if uv.cmplt(Vec2::ZERO).any() || uv.cmpge(size).any() {
+            return None;
+        }
+    } else if fit == MaskFit::Tile as u32 {
+        uv = p - (p / size).floor() * size;
+    }
+
+    Some(uv.max(Vec2::ZERO).as_uvec2().min(size.as_uvec2() - 1))
+}
+
+/// Whether agents are kept out of a point by the mask.
+fn mask_blocked(mask: &MaskImage, p: Vec2, domain: Vec2, globals: &storage::Globals) -> bool {
+    globals.mask_mode == mask_mode(MaskMode::Obstacle)
+        && mask_value(mask, p, domain, globals) * globals.mask_strength > 0.5
+}
+
+/// Multiplier of agent speed or deposits when the mask is in `mode`.
+fn mask_multiplier(
+    mask: &MaskImage,
+    p: Vec2,
+    domain: Vec2,
+    mode: MaskMode,
+    globals: &storage::Globals,
+) -> f32 {
+    if globals.mask_mode != mask_mode(mode) {
+        return 1.0;
+    }
+
+    mix(
+        1.0,
+        mask_value(mask, p, domain, globals),
+        globals.mask_strength,
+    )
+}
+
+/// Trail at a point, nothing is sensed outside the domain.
+fn sample(trail: &TrailImage, p: Vec2, species: u32, globals: &storage::Globals) -> f32 {
+    let size = trail.size.as_vec2();
+    let point = boundary_point(p, size, globals.boundary);
+    if !point.inside {
+        return 0.0;
+    }
+
+    trail.sense(boundary_texel(&point, size), species)
+}
+
+/// Average trail over a disc of whole pixel offsets around `p`.
+fn sample_area(
+    trail: &TrailImage,
+    p: Vec2,
+    radius: f32,
+    species: u32,
+    globals: &storage::Globals,
+) -> f32 {
+    let samples = radius as i32;
+    let mut sum = 0.0;
+    let mut num_samples = 0;
+    for i in -samples..=samples {
+        for j in -samples..=samples {
+            if i * i + j * j > samples * samples {
+                continue;
+            }
+
+            sum += sample(trail, p + Vec2::new(i as f32, j as f32), species, globals);
+            num_samples += 1;
+        }
+    }
+
+    if num_samples == 0 {
+        return sample(trail, p, species, globals);
+    }
+
+    sum / num_samples as f32
+}
+
+/// Maps a point onto the domain, as in `boundary.wgsl`.
+fn boundary_point(p: Vec2, size: Vec2, mode: u32) -> BoundaryPoint {
+    if mode == Boundary::Wrap as u32 {
+        return BoundaryPoint {
+            p: p - (p / size).floor() * size,
+            inside: true,
+        };
+    }
+
+    if mode == Boundary::Reflect as u32 {
+        let m = p - (p / (2.0 * size)).floor() * 2.0 * size;
+        let below = Vec2::new(
+            f32::from_bits(size.x.to_bits() - 1),
+            f32::from_bits(size.y.to_bits() - 1),
+        );
+        return BoundaryPoint {
+            p: Vec2::select(m.cmpge(size), 2.0 * size - m, m).min(below),
+            inside: true,
+        };
+    }
+
+    if mode == Boundary::Circle as u32 {
+        let radius = 0.5 * size.min_element();
+        return BoundaryPoint {
+            p,
+            inside: (p - 0.5 * size).length() <= radius,
+        };
+    }
+
+    BoundaryPoint {
+        p,
+        inside: p.cmpge(Vec2::ZERO).all() && p.cmplt(size).all(),
+    }
+}
+
+/// Texel of a point mapped onto the domain.
+fn boundary_texel(point: &BoundaryPoint, size: Vec2) -> UVec2 {
+    point.p.max(Vec2::ZERO).as_uvec2().min(size.as_uvec2() - 1)
+}
+
+fn rotate(v: Vec2, angle: f32) -> Vec2 {
+    let (s, c) = angle.sin_cos();
+    Vec2::new(v.x * c - v.y * s, v.x * s + v.y * c)
+}
+
+/// PCG hash, see https://www.jcgt.org/published/0009/03/02/
+fn hash(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+fn random(seed: &mut u32) -> f32 {
+    *seed = hash(*seed);
+    *seed as f32 / 4294967295.0
+}
+
+/// WGSL's `mix`, which rounds differently from a lerp.
+fn mix<T>(x: T, y: T, a: f32) -> T
+where
+    T: std::ops::Mul<f32, Output = T> + std::ops::Add<Output = T>,
+{
+    x * (1.0 - a) + y * a
+}
+
+/// Linear colours of the gradient lookup texture.
+fn gradient_lut(gradient: &Gradient) -> Vec<Vec3> {
+    let sample = gradient.sampler();
+    (0..LUT_SIZE)
+        .map(|i| palette::to_linear(sample(i as f32 / (LUT_SIZE - 1) as f32)))
+        .collect()
+}
+
+/// Colour of the lookup texture at `t`, clamped to its ends, as `gradient` in `palette.wgsl`.
+fn lookup(gradient: &[Vec3], t: f32) -> Vec3 {
+    let last = gradient.len() - 1;
+    let texel = (t.clamp(0.0, 1.0) * last as f32 + 0.5) as usize;
+    gradient[texel.min(last)]
+}
+
+fn pack4x8unorm(v: Vec4) -> u32 {
+    v.to_array()
+        .iter()
+        .enumerate()
+        .map(|(i, c)| ((c.clamp(0.0, 1.0) * 255.0).round() as u32) << (8 * i))
+        .sum()
+}
+
+fn unpack4x8unorm(v: u32) -> Vec4 {
+    Vec4::from(std::array::from_fn(|i| {
+        ((v >> (8 * i)) & 0xff) as f32 / 255.0
+    }))
+}
+
+/// `value` as a texture of `format` would read it back after storing it.
+fn stored(format: TrailFormat, value: Vec4) -> Vec4 {
+    match format {
+        TrailFormat::Rgba32Float | TrailFormat::Rgba16Float => value,
+        TrailFormat::R32Float => Vec4::new(value.x, 0.0, 0.0, 1.0),
+        TrailFormat::Rg16Float => Vec4::new(value.x, value.y, 0.0, 1.0),
+    }
+}
+
+/// Size of a texel of the trail texture, without any colour buffer.
+fn texel_bytes(format: TrailFormat) -> u32 {
+    match format {
+        TrailFormat::Rgba32Float => 16,
+        TrailFormat::Rgba16Float => 8,
+        TrailFormat::R32Float | TrailFormat::Rg16Float => 4,
+    }
+}
+
+/// Channels of a pixel of `format` read back from a texture.
+fn decode(format: TrailFormat, bytes: &[u8]) -> Vec4 {
+    let mut channels = [0.0; 4];
+    match format {
+        TrailFormat::Rgba32Float | TrailFormat::R32Float => {
+            for (channel, bytes) in channels.iter_mut().zip(bytes.chunks_exact(4)) {
+                *channel = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+        }
+        TrailFormat::Rgba16Float | TrailFormat::Rg16Float => {
+            for (channel, bytes) in channels.iter_mut().zip(bytes.chunks_exact(2)) {
+                *channel = f16_to_f32(u16::from_le_bytes([bytes[0], bytes[1]]));
+            }
+        }
+    }
+
+    Vec4::from(channels)
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        adapter::{self, AdapterOptions},
+        profiler::Profiler,
+        scene::{Mask, Spawn},
+        trail::TrailSupport,
+    };
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn globals(boundary: Boundary) -> storage::Globals {
+        let mut scene = Scene {
+            boundary,
+            ..Scene::default()
+        };
+        scene.behaviour.decay = 1.0;
+        let mut timing = Timing::new(Some(Duration::from_secs_f32(DT)));
+        timing.tick();
+        (&Globals { timing, scene }).into()
+    }
+
+    fn agent(position: Vec2, velocity: Vec2) -> Agent {
+        Agent {
+            position,
+            velocity,
+            genome: Vec4::ONE,
+            energy: 1.0,
+            age: 0.0,
+            alive: 1,
+            species: 0,
+            origin: position,
+            color: 0,
+        }
+    }
+
+    #[test]
+    fn wrap_maps_points_into_the_domain() {
+        let size = Vec2::new(64.0, 32.0);
+        let wrap = Boundary::Wrap as u32;
+        assert_eq!(
+            boundary_point(Vec2::new(-1.0, 33.0), size, wrap).p,
+            Vec2::new(63.0, 1.0)
+        );
+        assert_eq!(
+            boundary_point(Vec2::new(130.0, -64.5), size, wrap).p,
+            Vec2::new(2.0, 31.5)
+        );
+
+        let respawn = Boundary::Respawn as u32;
+        assert!(!boundary_point(Vec2::new(-1.0, 0.0), size, respawn).inside);
+        assert!(boundary_point(Vec2::new(0.0, 31.9), size, respawn).inside);
+    }
+
+    #[test]
+    fn reflect_keeps_points_inside_the_domain() {
+        let size = Vec2::new(64.0, 32.0);
+        let reflect = Boundary::Reflect as u32;
+        assert_eq!(
+            boundary_point(Vec2::new(-1.0, 33.0), size, reflect).p,
+            Vec2::new(1.0, 31.0)
+        );
+
+        let edge = boundary_point(size, size, reflect).p;
+        assert!(edge.cmplt(size).all());
+        assert_eq!(edge.as_uvec2(), glam::uvec2(63, 31));
+    }
+
+    #[test]
+    fn agents_wrap_around_the_edges() {
+        let globals = globals(Boundary::Wrap);
+        let trail = TrailImage::new(TrailFormat::Rgba32Float, glam::uvec2(16, 16));
+        let mut agents = [agent(Vec2::new(15.9, 8.0), Vec2::new(24.0, 0.0))];
+
+        let deposits = update_agents(
+            &mut agents,
+            &trail,
+            &MaskImage::default(),
+            &globals,
+            &[Vec3::ONE],
+        );
+
+        let expected = 15.9 + 24.0 * SPEED_SCALE * DT - 16.0;
+        assert!((agents[0].position.x - expected).abs() < 1e-4);
+        assert_eq!(agents[0].velocity, Vec2::new(24.0, 0.0));
+        assert_eq!(deposits[0].pixel, glam::uvec2(1, 8));
+    }
+
+    #[test]
+    fn agents_turn_towards_the_stronger_side() {
+        let globals = globals(Boundary::Wrap);
+        let mut trail = TrailImage::new(TrailFormat::Rgba32Float, glam::uvec2(64, 64));
+        let position = Vec2::new(16.5, 32.5);
+        let velocity = Vec2::new(24.0, 0.0);
+
+        // Trail under the left sensor only
+        let sensor = velocity.normalize() * globals.sensor_distance;
+        let left = position + rotate(sensor, globals.sensor_angle);
+        trail.store(left.as_uvec2(), Vec4::ONE);
+
+        let mut agents = [agent(position, velocity)];
+        update_agents(
+            &mut agents,
+            &trail,
+            &MaskImage::default(),
+            &globals,
+            &[Vec3::ONE],
+        );
+
+        let turned = agents[0].velocity.y.atan2(agents[0].velocity.x);
+        assert!((turned - globals.turn_rate).abs() < 1e-6);
+    }
+
+    #[test]
+    fn diffusion_spreads_and_decays() {
+        let globals = globals(Boundary::Wrap);
+        let mut trail = TrailImage::new(TrailFormat::Rgba32Float, glam::uvec2(8, 8));
+        trail.store(glam::uvec2(4, 4), Vec4::ONE);
+
+        let next = diffuse(&trail, &globals);
+
+        let blend = globals.dt * 48.0;
+        let center = (1.0 - blend) + blend / 25.0 - globals.dt * globals.decay;
+        let neighbour = blend / 25.0 - globals.dt * globals.decay;
+        assert!((next.load(glam::uvec2(4, 4)).w - center).abs() < 1e-6);
+        assert!((next.load(glam::uvec2(6, 2)).w - neighbour).abs() < 1e-6);
+        assert_eq!(next.load(glam::uvec2(0, 0)), Vec4::ZERO);
+
+        // A uniform trail only decays
+        let mut uniform = TrailImage::new(TrailFormat::R32Float, glam::uvec2(8, 8));
+        for y in 0..8 {
+            for x in 0..8 {
+                uniform.store(glam::uvec2(x, y), Vec4::ONE);
+            }
+        }
+
+        let next = diffuse(&uniform, &globals);
+        let expected = 1.0 - globals.dt * globals.decay;
+        assert!(next.pixels().iter().all(|p| (p.x - expected).abs() < 1e-6));
+    }
+
+    #[test]
+    fn respawn_is_deterministic() {
+        let mut globals = globals(Boundary::Respawn);
+        let size = glam::uvec2(32, 32);
+        let first = move_agent(3, Vec2::ZERO, Vec2::X, -Vec2::ONE, size, &globals);
+        let again = move_agent(3, Vec2::ZERO, Vec2::X, -Vec2::ONE, size, &globals);
+        assert_eq!(first.position, again.position);
+        assert!((first.velocity.length() - 1.0).abs() < 1e-5);
+
+        globals.frame += 1;
+        let next_frame = move_agent(3, Vec2::ZERO, Vec2::X, -Vec2::ONE, size, &globals);
+        assert_ne!(first.position, next_frame.position);
+    }
+
+    #[test]
+    fn masks_map_onto_the_domain() {
+        let domain = Vec2::new(96.0, 64.0);
+        let size = Vec2::new(32.0, 16.0);
+        let texel = |p: Vec2, fit: MaskFit| mask_texel(p, domain, size, fit as u32);
+
+        // Stretched over the whole domain
+        assert_eq!(texel(Vec2::ZERO, MaskFit::Stretch), Some(glam::uvec2(0, 0)));
+        assert_eq!(
+            texel(Vec2::new(48.0, 32.0), MaskFit::Stretch),
+            Some(glam::uvec2(16, 8))
+        );
+        assert_eq!(texel(domain, MaskFit::Stretch), Some(glam::uvec2(31, 15)));
+
+        // Scaled by 3 and centred, leaving 8 pixels above and below
+        assert_eq!(texel(Vec2::new(0.0, 7.9), MaskFit::Fit), None);
+        assert_eq!(
+            texel(Vec2::new(0.0, 8.0), MaskFit::Fit),
+            Some(glam::uvec2(0, 0))
+        );
+        assert_eq!(
+            texel(Vec2::new(95.9, 55.9), MaskFit::Fit),
+            Some(glam::uvec2(31, 15))
+        );
+        assert_eq!(texel(Vec2::new(48.0, 56.0), MaskFit::Fit), None);
+
+        // Repeated at one pixel per simulated pixel
+        assert_eq!(
+            texel(Vec2::new(40.5, 17.0), MaskFit::Tile),
+            Some(glam::uvec2(8, 1))
+        );
+    }
+
+    #[test]
+    fn masks_are_loaded_bottom_up() {
+        let path = std::env::temp_dir().join("blossim-cpu-mask-orientation.png");
+        let mut image = image::GrayImage::new(2, 2);
+        image.put_pixel(0, 0, image::Luma([255]));
+        image.save(&path).unwrap();
+
+        let mut scene = Scene::default();
+        scene.environment.mask = Some(mask(&path, MaskMode::Speed));
+        let loaded = MaskImage::new(&scene).unwrap();
+        assert_eq!(loaded.load(glam::uvec2(0, 1)), 1.0);
+        assert_eq!(loaded.load(glam::uvec2(0, 0)), 0.0);
+
+        scene.environment.mask.as_mut().unwrap().invert = true;
+        let inverted = MaskImage::new(&scene).unwrap();
+        assert_eq!(inverted.load(glam::uvec2(0, 1)), 0.0);
+        assert_eq!(inverted.load(glam::uvec2(1, 0)), 1.0);
+    }
+
+    fn mask(path: &std::path::Path, mode: MaskMode) -> Mask {
+        Mask {
+            path: path.to_owned(),
+            mode,
+            fit: MaskFit::Fit,
+            strength: 1.0,
+            invert: false,
+        }
+    }
+
+    fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+        let options = AdapterOptions::default();
+        let instance = options.instance();
+        let Ok(adapter) = pollster::block_on(options.select(&instance, None)) else {
+            eprintln!("No adapter, skipping the comparison with the GPU");
+            return None;
+        };
+        let (device, queue, _) =
+            pollster::block_on(adapter::request_device(&adapter, wgpu::Limits::default()))
+                .expect("Failed to open the device");
+        Some((device, queue))
+    }
+
+    /// Steps the same agents on the GPU and on the CPU, and compares where
+    /// they end up and the trails they leave.
+    fn assert_matches_gpu(device: &wgpu::Device, queue: &wgpu::Queue, scene: Scene, label: &str) {
+        // Ping-pong access is available for every format used here, and
+        // doesn't diffuse in place, which races on the GPU
+        let format = scene.trail_format;
+        let domain = glam::uvec2(96, 64);
+        let mut gpu = simulation::Simulation::new(
+            device,
+            queue,
+            domain,
+            Timing::new(Some(Duration::from_secs_f32(DT))),
+            scene,
+            TrailSupport::only(format, TrailAccess::PingPong),
+        )
+        .expect("Failed to create the simulation");
+        let mut cpu = Simulation::read(device, queue, &gpu).expect("Failed to read the simulation");
+
+        let mut profiler = Profiler::new(device, queue);
+        for _ in 0..60 {
+            gpu.globals.timing.tick();
+            let mut encoder = device.create_command_encoder(&Default::default());
+            gpu.step(device, queue, &mut encoder, &mut profiler)
+                .expect("Failed to step the simulation");
+            queue.submit(std::iter::once(encoder.finish()));
+            gpu.after_submit();
+            gpu.poll(device);
+
+            cpu.globals.timing.tick();
+            cpu.step();
+        }
+
+        let expected =
+            Simulation::read(device, queue, &gpu).expect("Failed to read the simulation");
+        assert_eq!(expected.agents().len(), cpu.agents().len());
+        for (gpu_agent, cpu_agent) in expected.agents().iter().zip(cpu.agents()) {
+            // Wrapped around the edges when one is a hair to either side of them
+            let offset = (gpu_agent.position - cpu_agent.position).abs();
+            let distance = offset.min(domain.as_vec2() - offset).length();
+            assert!(
+                distance < 1e-2,
+                "{}: {:?} != {:?}",
+                label,
+                gpu_agent,
+                cpu_agent
+            );
+            // The GPU decodes the sRGB gradient itself
+            let color_error = (unpack4x8unorm(gpu_agent.color) - unpack4x8unorm(cpu_agent.color))
+                .abs()
+                .max_element();
+            assert!(
+                color_error <= 1.0 / 255.0,
+                "{}: {:?} != {:?}",
+                label,
+                gpu_agent,
+                cpu_agent
+            );
+        }
+
+        // Colours are as close as the gradients they come from, intensities closer
+        let (gpu_trail, cpu_trail) = (expected.trail(), cpu.trail());
+        let pixels = (0..domain.y).flat_map(|y| (0..domain.x).map(move |x| glam::uvec2(x, y)));
+        for p in pixels {
+            let intensity_error = (gpu_trail.intensity(p) - cpu_trail.intensity(p)).abs();
+            assert!(
+                intensity_error < 1e-4,
+                "{}: intensity differs at {}",
+                label,
+                p
+            );
+
+            let color_error = (gpu_trail.color(p, ColorSource::Index, &[])
+                - cpu_trail.color(p, ColorSource::Index, &[]))
+            .abs()
+            .max_element();
+            assert!(
+                color_error < 4.0 / 255.0,
+                "{}: colour differs at {}",
+                label,
+                p
+            );
+        }
+    }
+
+    /// Trails of every colour layout. Skipped without an adapter.
+    #[test]
+    fn matches_the_gpu() {
+        let Some((device, queue)) = device() else {
+            return;
+        };
+
+        for format in [TrailFormat::Rgba32Float, TrailFormat::R32Float] {
+            let scene = Scene {
+                agents: 128,
+                spawn: Spawn::Uniform,
+                trail_format: format,
+                ..Scene::default()
+            };
+            assert_matches_gpu(&device, &queue, scene, format.name());
+        }
+    }
+
+    /// Every mask mode, on a mask fitted into the domain with a bright
+    /// band across its middle. Skipped without an adapter.
+    #[test]
+    fn masks_match_the_gpu() {
+        let Some((device, queue)) = device() else {
+            return;
+        };
+
+        let path = std::env::temp_dir().join("blossim-cpu-mask.png");
+        image::GrayImage::from_fn(24, 12, |x, y| {
+            image::Luma([if (4..8).contains(&y) {
+                255
+            } else {
+                (x * 10) as u8
+            }])
+        })
+        .save(&path)
+        .unwrap();
+
+        for mode in MaskMode::ALL {
+            let mut scene = Scene {
+                agents: 128,
+                spawn: Spawn::Uniform,
+                trail_format: TrailFormat::Rgba32Float,
+                ..Scene::default()
+            };
+            scene.environment.mask = Some(mask(&path, mode));
+            assert_matches_gpu(&device, &queue, scene, mode.name());
+        }
+    }
+}
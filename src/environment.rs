@@ -0,0 +1,263 @@
+use std::path::PathBuf;
+
+use crate::{
+    error::Result,
+    pipeline::bindings::Resource,
+    scene::{self, Field},
+    storage::{self, Feature, Storable},
+};
+
+// Feature kinds, must match `environment.wgsl`
+const FOOD: u32 = 0;
+const ATTRACTOR: u32 = 1;
+const WIND: u32 = 2;
+const VORTEX: u32 = 3;
+
+/// GPU copy of the scene environment: a buffer of point features and a
+/// mask of obstacles, re-uploaded whenever the scene's environment changes.
+pub struct Environment {
+    current: scene::Environment,
+    domain: glam::UVec2,
+    features: wgpu::Buffer,
+    obstacles: wgpu::Texture,
+    obstacles_view: wgpu::TextureView,
+    /// Image and inversion the mask texture was loaded with
+    mask_source: Option<(PathBuf, bool)>,
+    mask_view: wgpu::TextureView,
+}
+
+impl Environment {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        environment: &scene::Environment,
+        domain: glam::UVec2,
+    ) -> Result<Self> {
+        let obstacles = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Obstacle mask"),
+            size: wgpu::Extent3d {
+                width: domain.x,
+                height: domain.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let (features, _) = create_features_buffer(device, queue, environment, domain)?;
+        let mut this = Self {
+            current: environment.clone(),
+            domain,
+            features,
+            obstacles_view: obstacles.create_view(&wgpu::TextureViewDescriptor::default()),
+            obstacles,
+            mask_source: None,
+            mask_view: create_mask_view(device, queue, 1, 1, &[u8::MAX]),
+        };
+
+        this.write_obstacles(queue);
+        this.load_mask(device, queue)?;
+        Ok(this)
+    }
+
+    /// Uploads the environment again if it differs from the one on the GPU.
+    /// A mask image that fails to load leaves the previous mask in place.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        environment: &scene::Environment,
+    ) -> Result<()> {
+        if *environment == self.current {
+            return Ok(());
+        }
+
+        let obstacles_changed = environment.obstacles != self.current.obstacles;
+        self.current = environment.clone();
+
+        let (buffer, size) = features_bytes(environment, self.domain)?;
+        if size <= self.features.size() {
+            queue.write_buffer(&self.features, 0, &buffer);
+        } else {
+            self.features = create_features_buffer(device, queue, environment, self.domain)?.0;
+        }
+
+        if obstacles_changed {
+            self.write_obstacles(queue);
+        }
+
+        self.load_mask(device, queue)
+    }
+
+    /// Resources of the features buffer and obstacle mask.
+    pub fn resources(&self) -> Vec<Resource<'_>> {
+        vec![
+            ("environment", self.features.as_entire_binding()),
+            (
+                "obstacles",
+                wgpu::BindingResource::TextureView(&self.obstacles_view),
+            ),
+        ]
+    }
+
+    /// Resource of the mask texture, only used by the simulation pass.
+    pub fn mask_resource(&self) -> Resource<'_> {
+        (
+            "mask_tex",
+            wgpu::BindingResource::TextureView(&self.mask_view),
+        )
+    }
+
+    /// Loads the mask image if it isn't the one already on the GPU. Images
+    /// that fail to load leave the previous mask in place, and aren't tried
+    /// again until the mask changes.
+    fn load_mask(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Result<()> {
+        let mask = self.current.mask.as_ref();
+        let source = mask.map(|mask| (mask.path.clone(), mask.invert));
+        if source == self.mask_source {
+            return Ok(());
+        }
+
+        self.mask_source = source;
+        let Some(mask) = mask else {
+            return Ok(());
+        };
+
+        let luma = mask.image()?;
+        let (width, height) = luma.dimensions();
+        self.mask_view = create_mask_view(device, queue, width, height, &luma);
+        Ok(())
+    }
+
+    fn write_obstacles(&self, queue: &wgpu::Queue) {
+        let aspect = self.domain.as_vec2() / self.domain.min_element() as f32;
+        let size = self.domain.as_vec2();
+        let mask: Vec<u8> = (0..self.domain.y)
+            .flat_map(|y| (0..self.domain.x).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let p = (glam::vec2(x as f32, y as f32) + 0.5) / size;
+                let blocked = self.current.obstacles.iter().any(|o| o.contains(p, aspect));
+                if blocked {
+                    u8::MAX
+                } else {
+                    0
+                }
+            })
+            .collect();
+
+        queue.write_texture(
+            self.obstacles.as_image_copy(),
+            &mask,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(self.domain.x),
+                rows_per_image: None,
+            },
+            self.obstacles.size(),
+        );
+    }
+}
+
+fn create_mask_view(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    width: u32,
+    height: u32,
+    luma: &[u8],
+) -> wgpu::TextureView {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Mask"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::R8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+
+    queue.write_texture(
+        texture.as_image_copy(),
+        luma,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(width),
+            rows_per_image: None,
+        },
+        texture.size(),
+    );
+
+    texture.create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_features_buffer(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    environment: &scene::Environment,
+    domain: glam::UVec2,
+) -> Result<(wgpu::Buffer, u64)> {
+    let (bytes, size) = features_bytes(environment, domain)?;
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Environment features"),
+        size,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    queue.write_buffer(&buffer, 0, &bytes);
+
+    Ok((buffer, size))
+}
+
+/// Features converted to pixels, and the buffer size needed to hold them.
+/// The size always leaves room for one feature since bindings can't be empty.
+fn features_bytes(environment: &scene::Environment, domain: glam::UVec2) -> Result<(Vec<u8>, u64)> {
+    let size = domain.as_vec2();
+    let scale = domain.min_element() as f32;
+
+    let food = environment.food.iter().map(|food| Feature {
+        kind: FOOD,
+        strength: food.rate,
+        radius: food.radius * scale,
+        position: food.position * size,
+        velocity: food.velocity * size,
+    });
+
+    let attractors = environment.attractors.iter().map(|attractor| Feature {
+        kind: ATTRACTOR,
+        strength: attractor.strength,
+        radius: attractor.radius * scale,
+        position: attractor.position * size,
+        velocity: glam::Vec2::ZERO,
+    });
+
+    let fields = environment.fields.iter().map(|field| match *field {
+        Field::Wind { velocity } => Feature {
+            kind: WIND,
+            strength: 0.0,
+            radius: 0.0,
+            position: glam::Vec2::ZERO,
+            velocity: velocity * size,
+        },
+        Field::Vortex { center, strength } => Feature {
+            kind: VORTEX,
+            strength,
+            radius: 0.0,
+            position: center * size,
+            velocity: glam::Vec2::ZERO,
+        },
+    });
+
+    let features: Vec<Feature> = food.chain(attractors).chain(fields).collect();
+    let bytes = storage::Buffer(&features).to_bytes()?;
+    let size = (bytes.len() as u64).max(storage::Buffer::<Feature>::offset(1));
+
+    Ok((bytes, size))
+}
@@ -0,0 +1,182 @@
+//! Errors of setting up the GPU, loading and saving files, and exporting images.
+
+use std::{
+    error::Error as _,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::trail::TrailFormat;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Anything that can go wrong in Blossim. New variants may be added
+/// without a major version bump.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("No graphics adapter with the features Blossim needs was found, updating the graphics driver may help")]
+    NoAdapter,
+    #[error("No graphics adapter matches \"{0}\", `blossim adapters` lists the available ones")]
+    AdapterNotFound(String),
+    #[error("Unable to create a surface to draw into: {0}")]
+    CreateSurface(#[from] wgpu::CreateSurfaceError),
+    #[error("The window's surface is not supported by the graphics adapter")]
+    UnsupportedSurface,
+    #[error("Unable to open the graphics device: {0}")]
+    RequestDevice(#[from] wgpu::RequestDeviceError),
+    #[error("{} is not supported by the graphics adapter", .0.name())]
+    UnsupportedTrailFormat(TrailFormat),
+    #[error("The graphics adapter does not support any trail format")]
+    NoTrailFormat,
+    /// A size exceeds one of the device's limits
+    #[error("{what} of {requested} exceeds the graphics device's limit of {limit}")]
+    Limit {
+        what: &'static str,
+        requested: u64,
+        limit: u64,
+    },
+    /// An error in a shader, at its line before preprocessing
+    #[error("{file}:{line}: {message}")]
+    ShaderSource {
+        file: String,
+        line: usize,
+        message: String,
+    },
+    /// A struct shared with Rust that a shader lays out differently
+    #[error("{shader}: struct {name} doesn't match its layout in Rust, {difference}")]
+    Layout {
+        shader: String,
+        name: String,
+        difference: String,
+    },
+    /// Agent rules that don't follow the hook interface
+    #[error("{}: {message}", path.display())]
+    Rules { path: PathBuf, message: String },
+    /// A shader binding without a resource, or the other way around
+    #[error("{shader}: binding `{name}` {problem}")]
+    Binding {
+        shader: String,
+        name: String,
+        problem: String,
+    },
+    /// A value encase couldn't write into the bytes of a GPU buffer
+    #[error("Unable to write GPU buffer data: {0}")]
+    Write(#[from] encase::internal::Error),
+    /// Shaders or pipelines the device rejected
+    #[error("Unable to compile shaders: {0}")]
+    Shader(String),
+    /// An error the device reported while running, outside of setup
+    #[error("Graphics error: {0}")]
+    Gpu(String),
+    #[error("The graphics device ran out of memory")]
+    OutOfMemory,
+    #[error("The graphics device was lost, restart Blossim to continue: {0}")]
+    DeviceLost(String),
+    #[error("{}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        source: ron::error::SpannedError,
+    },
+    #[error("Unable to serialise: {0}")]
+    Serialize(#[from] ron::Error),
+    #[error("{}: {source}", path.display())]
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    #[error("{}: {source}", path.display())]
+    Encode {
+        path: PathBuf,
+        source: png::EncodingError,
+    },
+    #[error("{}: {source}", path.display())]
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("{}: posters are written as PNG or TIFF", .0.display())]
+    UnsupportedEncoding(PathBuf),
+}
+
+impl Error {
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        Error::Io {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    /// Whether the device can't be used any more after this error.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Error::OutOfMemory | Error::DeviceLost(_))
+    }
+}
+
+impl From<wgpu::Error> for Error {
+    fn from(error: wgpu::Error) -> Self {
+        let lost = is_device_lost(&error);
+        match error {
+            wgpu::Error::OutOfMemory { .. } => Error::OutOfMemory,
+            wgpu::Error::Validation { description, .. } if lost => Error::DeviceLost(description),
+            wgpu::Error::Validation { description, .. } => Error::Gpu(description),
+        }
+    }
+}
+
+/// Whether any cause of `error` is the device having been lost. wgpu 0.16
+/// has no callback for it, so it only shows as the cause of failing calls.
+fn is_device_lost(error: &wgpu::Error) -> bool {
+    let mut source = error.source();
+    while let Some(error) = source {
+        if error.to_string().contains("device is lost") {
+            return true;
+        }
+        source = error.source();
+    }
+
+    false
+}
+
+/// Runs `create`, which creates shader modules and pipelines, and returns
+/// the first validation error the device reports for them.
+pub(crate) fn compile<T>(device: &wgpu::Device, create: impl FnOnce() -> T) -> Result<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(wgpu::Error::Validation { description, .. }) => Err(Error::Shader(description)),
+        Some(error) => Err(error.into()),
+        None => Ok(value),
+    }
+}
+
+/// Errors the device reports outside of any error scope, which wgpu
+/// otherwise turns into panics. They are collected to be shown to the user.
+#[derive(Clone, Default)]
+pub struct DeviceErrors(Arc<Mutex<Vec<Error>>>);
+
+impl DeviceErrors {
+    /// Starts collecting the uncaptured errors of `device`.
+    pub fn install(device: &wgpu::Device) -> Self {
+        let errors = Self::default();
+        let collected = errors.0.clone();
+        device.on_uncaptured_error(Box::new(move |error| {
+            if let Ok(mut collected) = collected.lock() {
+                collected.push(error.into());
+            }
+        }));
+
+        errors
+    }
+
+    /// Errors reported since the last call.
+    pub fn take(&self) -> Vec<Error> {
+        self.0
+            .lock()
+            .map(|mut errors| std::mem::take(&mut *errors))
+            .unwrap_or_default()
+    }
+}
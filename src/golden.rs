@@ -0,0 +1,371 @@
+//! Golden image tests: reference scenes simulated with a fixed seed and
+//! timestep, rendered, and compared against checked-in images.
+//!
+//! Cases are listed in a RON file next to their scenes and goldens, see
+//! `tests/golden/cases.ron`. Trail maps are ping-ponged so that every run
+//! gives the same result, and a software adapter is preferred so that
+//! goldens blessed on one machine hold on others.
+
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    adapter::{self, AdapterOptions},
+    camera::Camera,
+    error::{DeviceErrors, Error, Result},
+    profiler::Profiler,
+    renderer::Renderer,
+    scene::Scene,
+    screenshot,
+    simulation::{Simulation, Timing},
+    trail::TrailSupport,
+};
+
+/// Timestep every case is simulated with.
+const DT: f32 = 1.0 / 60.0;
+
+/// Seed of cases whose scene doesn't set one.
+const DEFAULT_SEED: u64 = 1;
+
+/// Images of the golden tests that don't match.
+#[derive(Debug, thiserror::Error)]
+#[error("{failed} of {total} images differ from their goldens, see {}", .output.display())]
+pub struct Mismatch {
+    pub failed: usize,
+    pub total: usize,
+    pub output: PathBuf,
+}
+
+/// Reference scene, and how long and at what size to simulate it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Case {
+    /// Name of the golden image, `<name>.png` next to the cases file
+    pub name: String,
+    /// Scene file, relative to the cases file
+    pub scene: PathBuf,
+    pub frames: usize,
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub tolerance: Tolerance,
+}
+
+/// How far a render may stray from its golden. Distances are perceptual,
+/// in units of one step of an 8-bit sRGB channel.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tolerance {
+    /// Distance up to which pixels count as the same
+    pub pixel: f32,
+    /// Largest fraction of pixels that may differ
+    pub differing: f32,
+    /// Largest mean distance over the whole image
+    pub mean: f32,
+}
+
+/// Differences between a render and its golden.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Comparison {
+    /// Fraction of pixels further apart than the tolerance allows
+    pub differing: f32,
+    /// Mean distance over the whole image
+    pub mean: f32,
+    /// Largest distance of any pixel
+    pub max: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    Passed(Comparison),
+    Failed(Comparison),
+    /// The golden has a different size than the case renders at
+    WrongSize(glam::UVec2),
+    /// There is no golden yet
+    Missing,
+    /// The render was saved as the new golden
+    Blessed,
+}
+
+/// Outcome of one case.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+pub struct Options {
+    /// RON file listing the cases
+    pub cases: PathBuf,
+    /// Directory renders and diff images of failed cases are written to
+    pub output: PathBuf,
+    /// Saves every render as its golden instead of comparing
+    pub bless: bool,
+    pub adapter: AdapterOptions,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            pixel: 8.0,
+            differing: 0.01,
+            mean: 1.0,
+        }
+    }
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        matches!(self, Outcome::Passed(_) | Outcome::Blessed)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<16}", self.name)?;
+        match self.outcome {
+            Outcome::Passed(comparison) => write!(f, "ok      {}", comparison),
+            Outcome::Failed(comparison) => write!(f, "FAILED  {}", comparison),
+            Outcome::WrongSize(size) => write!(f, "FAILED  golden is {}x{}", size.x, size.y),
+            Outcome::Missing => write!(f, "MISSING no golden, bless to create it"),
+            Outcome::Blessed => write!(f, "blessed"),
+        }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2}% differing, mean {:.2}, max {:.1}",
+            self.differing * 100.0,
+            self.mean,
+            self.max
+        )
+    }
+}
+
+/// Loads the cases listed in `path`.
+pub fn load_cases(path: &Path) -> Result<Vec<Case>> {
+    crate::scene::load_ron(path)
+}
+
+/// Renders every case and compares it with its golden, or saves it as the
+/// golden when blessing. Renders and diff images of cases that don't pass
+/// are written to the output directory.
+pub async fn run(options: &Options) -> Result<Vec<Report>> {
+    let cases = load_cases(&options.cases)?;
+    let dir = options.cases.parent().unwrap_or(Path::new(""));
+
+    let instance = options.adapter.instance();
+    let adapter = select_adapter(&options.adapter, &instance).await?;
+    let (device, queue, trail_support) =
+        adapter::request_device(&adapter, wgpu::Limits::default()).await?;
+    let errors = DeviceErrors::install(&device);
+    let trail_support = trail_support.ping_pong();
+
+    let mut reports = Vec::with_capacity(cases.len());
+    for case in &cases {
+        let image = render(&device, &queue, &trail_support, case, dir)?;
+        if let Some(error) = errors.take().into_iter().next() {
+            return Err(error);
+        }
+
+        let golden_path = dir.join(format!("{}.png", case.name));
+        let outcome = if options.bless {
+            save(&image, &golden_path)?;
+            Outcome::Blessed
+        } else {
+            check(case, &image, &golden_path, &options.output)?
+        };
+
+        reports.push(Report {
+            name: case.name.clone(),
+            outcome,
+        });
+    }
+
+    Ok(reports)
+}
+
+/// Simulates `case` and renders the whole domain, with scene paths relative to `dir`.
+pub fn render(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    trail_support: &TrailSupport,
+    case: &Case,
+    dir: &Path,
+) -> Result<image::RgbImage> {
+    let mut scene = Scene::load(&dir.join(&case.scene))?;
+    scene.seed = scene.seed.or(Some(DEFAULT_SEED));
+    let trail_format = scene.trail_format;
+
+    let domain = glam::uvec2(case.width, case.height);
+    let timing = Timing::new(Some(Duration::from_secs_f32(DT)));
+    let mut simulation =
+        Simulation::new(device, queue, domain, timing, scene, trail_support.clone())?;
+    let mut renderer = Renderer::new(device, screenshot::FORMAT, trail_format)?;
+    let mut profiler = Profiler::new(device, queue);
+
+    for _ in 0..case.frames {
+        simulation.globals.timing.tick();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Golden encoder"),
+        });
+        simulation.step(device, queue, &mut encoder, &mut profiler)?;
+        queue.submit(std::iter::once(encoder.finish()));
+
+        simulation.after_submit();
+        simulation.poll(device);
+        if let Some(error) = simulation.take_errors().into_iter().next() {
+            return Err(error);
+        }
+    }
+
+    let image = screenshot::capture(
+        device,
+        queue,
+        &mut renderer,
+        &simulation,
+        &Camera::default(),
+        domain,
+    )?;
+
+    // The viewer ignores alpha, which the trail's colour may set
+    Ok(image::DynamicImage::ImageRgba8(image).into_rgb8())
+}
+
+/// Perceptual differences between two images of the same size, counting
+/// pixels further apart than `tolerance.pixel` as differing.
+pub fn compare(
+    expected: &image::RgbImage,
+    actual: &image::RgbImage,
+    tolerance: &Tolerance,
+) -> Comparison {
+    let distances: Vec<f32> = expected
+        .pixels()
+        .zip(actual.pixels())
+        .map(|(a, b)| distance(a.0, b.0))
+        .collect();
+
+    let count = distances.len().max(1) as f32;
+    Comparison {
+        differing: distances.iter().filter(|d| **d > tolerance.pixel).count() as f32 / count,
+        mean: distances.iter().sum::<f32>() / count,
+        max: distances.iter().copied().fold(0.0, f32::max),
+    }
+}
+
+/// Image highlighting where `actual` differs from `expected`: differing
+/// pixels in red over a faded copy of the golden, and smaller differences
+/// in amber.
+pub fn diff_image(
+    expected: &image::RgbImage,
+    actual: &image::RgbImage,
+    tolerance: &Tolerance,
+) -> image::RgbImage {
+    image::RgbImage::from_fn(expected.width(), expected.height(), |x, y| {
+        let a = expected.get_pixel(x, y).0;
+        let b = actual.get_pixel(x, y).0;
+        let d = distance(a, b);
+
+        let faded = ((a[0] as f32 * 0.3 + a[1] as f32 * 0.59 + a[2] as f32 * 0.11) / 4.0) as u8;
+        if d > tolerance.pixel {
+            image::Rgb([(64.0 + d * 4.0).min(255.0) as u8, faded, faded])
+        } else if d > 0.0 {
+            let amber = (d / tolerance.pixel.max(f32::EPSILON) * 160.0) as u8;
+            image::Rgb([faded.max(amber), faded.max(amber / 2), faded])
+        } else {
+            image::Rgb([faded; 3])
+        }
+    })
+}
+
+/// Prefers a software adapter, which renders the same everywhere, unless
+/// the options name one.
+async fn select_adapter(
+    options: &AdapterOptions,
+    instance: &wgpu::Instance,
+) -> Result<wgpu::Adapter> {
+    if options.name.is_none() {
+        let software = options
+            .adapters(instance)
+            .into_iter()
+            .find(|adapter| adapter.get_info().device_type == wgpu::DeviceType::Cpu);
+        if let Some(adapter) = software {
+            return Ok(adapter);
+        }
+    }
+
+    options.select(instance, None).await
+}
+
+/// Compares `image` with the golden at `golden_path`, writing the render
+/// and a diff image to `output` when they differ.
+fn check(
+    case: &Case,
+    image: &image::RgbImage,
+    golden_path: &Path,
+    output: &Path,
+) -> Result<Outcome> {
+    let write_render = || {
+        fs::create_dir_all(output).map_err(|e| Error::io(output, e))?;
+        save(image, &output.join(format!("{}.png", case.name)))
+    };
+
+    if !golden_path.exists() {
+        write_render()?;
+        return Ok(Outcome::Missing);
+    }
+
+    let golden = image::open(golden_path)
+        .map_err(|source| Error::Image {
+            path: golden_path.to_owned(),
+            source,
+        })?
+        .into_rgb8();
+
+    if golden.dimensions() != image.dimensions() {
+        write_render()?;
+        return Ok(Outcome::WrongSize(golden.dimensions().into()));
+    }
+
+    let tolerance = &case.tolerance;
+    let comparison = compare(&golden, image, tolerance);
+    if comparison.differing <= tolerance.differing && comparison.mean <= tolerance.mean {
+        return Ok(Outcome::Passed(comparison));
+    }
+
+    write_render()?;
+    save(
+        &diff_image(&golden, image, tolerance),
+        &output.join(format!("{}.diff.png", case.name)),
+    )?;
+
+    Ok(Outcome::Failed(comparison))
+}
+
+fn save(image: &image::RgbImage, path: &Path) -> Result<()> {
+    image.save(path).map_err(|source| Error::Image {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+/// Perceptual distance between two sRGB colours, weighting channels by how
+/// sensitive the eye is to them at the pair's redness ("redmean"), scaled
+/// so that one step of every channel is a distance of about one.
+fn distance(a: [u8; 3], b: [u8; 3]) -> f32 {
+    let [r1, g1, b1] = a.map(|c| c as f32);
+    let [r2, g2, b2] = b.map(|c| c as f32);
+    let r = 0.5 * (r1 + r2);
+    let (dr, dg, db) = (r1 - r2, g1 - g2, b1 - b2);
+    let squared =
+        (2.0 + r / 256.0) * dr * dr + 4.0 * dg * dg + (2.0 + (255.0 - r) / 256.0) * db * db;
+    squared.sqrt() / 3.0
+}
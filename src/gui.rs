@@ -1,43 +1,209 @@
 use std::time::Instant;
 
-use crate::app;
+use blossim::{
+    brush::{Brush, BrushKind},
+    camera::Camera,
+    palette::{Gradient, Stop},
+    post::{Effect, ToneMap},
+    profiler::{Pass, Profiler},
+    scene::{
+        self, Attractor, Boundary, ColorSource, Field, Food, Lifecycle, Mask, MaskFit, MaskMode,
+        Obstacle, Palette, Spawn, SpriteShape, Sprites,
+    },
+    screenshot, simulation,
+    timeline::{Curve, Keyframe, Parameter, Timeline, Track},
+    trail::{TrailFormat, TrailSupport},
+};
+
+/// Upper bound of the agent count that can be picked in the GUI.
+const MAX_AGENTS: u32 = 4_000_000;
+
+/// Stops a built-in colormap is approximated with when it is turned into a custom gradient.
+const CUSTOM_GRADIENT_STOPS: usize = 5;
 
 pub struct Interface {
     info_pane: InfoPane,
 }
 
+/// Errors shown until they are dismissed.
+#[derive(Default)]
+struct Errors(Vec<String>);
+
 pub struct InfoPane {
+    adapter: wgpu::AdapterInfo,
+    /// Features the device was opened with
+    features: wgpu::Features,
+    trail_support: TrailSupport,
+    trail_formats: Vec<TrailFormat>,
+    tool: Tool,
+    /// Radius of placed features and brushes, relative to the shorter side of the domain
+    tool_size: f32,
+    brush_strength: f32,
+    brush_falloff: f32,
+    /// Image path being typed in, loaded as the mask on request
+    mask_path: String,
+    /// File palettes are saved to and loaded from
+    palette_path: String,
+    /// File timelines are saved to and loaded from
+    timeline_path: String,
+    screenshot_view: screenshot::View,
+    /// Set by the screenshot button until the app takes it
+    screenshot_requested: bool,
     fps: f32,
     checkpoint_fps_frame: usize,
     checkpoint_fps_time: Instant,
+    errors: Errors,
+}
+
+/// What clicking on the simulation does, either placing a feature in the
+/// environment or applying a brush for as long as the button is held.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    None,
+    Food,
+    Obstacle,
+    Attractor,
+    Repulsor,
+    Vortex,
+    Brush(BrushKind),
 }
 
 impl Interface {
-    pub fn new() -> Self {
+    pub fn new(
+        adapter: wgpu::AdapterInfo,
+        features: wgpu::Features,
+        trail_support: TrailSupport,
+    ) -> Self {
         Self {
             info_pane: InfoPane {
+                adapter,
+                features,
+                trail_formats: trail_support.formats(),
+                trail_support,
+                tool: Tool::None,
+                tool_size: 0.05,
+                brush_strength: 0.5,
+                brush_falloff: 0.5,
+                mask_path: String::new(),
+                palette_path: String::from("palette.ron"),
+                timeline_path: String::from("timeline.ron"),
+                screenshot_view: screenshot::View::Camera,
+                screenshot_requested: false,
                 fps: 0.0,
                 checkpoint_fps_frame: 0,
                 checkpoint_fps_time: Instant::now(),
+                errors: Errors::default(),
             },
         }
     }
 
-    pub fn ui(&mut self, ctx: &egui::Context, globals: &mut app::Globals) {
+    pub fn trail_formats(&self) -> &[TrailFormat] {
+        &self.info_pane.trail_formats
+    }
+
+    /// Adds a feature with the current tool at `position`, or removes the one
+    /// under it. `position` is a fraction of the domain from its bottom left.
+    /// Does nothing unless an environment tool is selected.
+    pub fn edit_environment(
+        &self,
+        environment: &mut scene::Environment,
+        position: glam::Vec2,
+        aspect: glam::Vec2,
+        remove: bool,
+    ) {
+        let size = self.info_pane.tool_size;
+        match self.info_pane.tool {
+            Tool::None | Tool::Brush(_) => {}
+            _ if remove => {
+                environment.remove_at(position, aspect, size);
+            }
+            Tool::Food => environment.food.push(Food {
+                position,
+                radius: size,
+                ..Default::default()
+            }),
+            Tool::Obstacle => environment.obstacles.push(Obstacle::Circle {
+                center: position,
+                radius: size,
+            }),
+            Tool::Attractor | Tool::Repulsor => environment.attractors.push(Attractor {
+                position,
+                radius: size,
+                strength: if self.info_pane.tool == Tool::Repulsor {
+                    -1.0
+                } else {
+                    1.0
+                },
+            }),
+            Tool::Vortex => environment.fields.push(Field::Vortex {
+                center: position,
+                strength: 0.5,
+            }),
+        }
+    }
+
+    /// Shows `error` until the user dismisses it.
+    pub fn report(&mut self, error: impl std::fmt::Display) {
+        self.info_pane.errors.push(error);
+    }
+
+    /// Asks for a screenshot of the current screenshot view.
+    pub fn request_screenshot(&mut self) {
+        self.info_pane.screenshot_requested = true;
+    }
+
+    /// View of the screenshot requested since the last call, if any.
+    pub fn take_screenshot(&mut self) -> Option<screenshot::View> {
+        std::mem::take(&mut self.info_pane.screenshot_requested)
+            .then_some(self.info_pane.screenshot_view)
+    }
+
+    /// Brush of the current tool at `position`, if it is one.
+    pub fn brush(&self, position: glam::Vec2) -> Option<Brush> {
+        let Tool::Brush(kind) = self.info_pane.tool else {
+            return None;
+        };
+
+        Some(Brush {
+            kind,
+            position,
+            radius: self.info_pane.tool_size,
+            strength: self.info_pane.brush_strength,
+            falloff: self.info_pane.brush_falloff,
+        })
+    }
+
+    pub fn ui(
+        &mut self,
+        ctx: &egui::Context,
+        globals: &mut simulation::Globals,
+        camera: &mut Camera,
+        live_agents: Option<u32>,
+        profiler: &Profiler,
+    ) {
         egui::CentralPanel::default()
             .frame(egui::Frame::none())
             .show(ctx, |_| {
                 egui::Window::new("Info")
                     .default_open(true)
                     .show(ctx, |ui: &mut egui::Ui| {
-                        self.info_pane.ui(ui, globals);
+                        self.info_pane.ui(ui, globals, camera, live_agents);
+                        self.info_pane.profiler_ui(ui, profiler);
                     });
+
+                self.info_pane.errors.ui(ctx);
             });
     }
 }
 
 impl InfoPane {
-    fn ui(&mut self, ui: &mut egui::Ui, globals: &mut app::Globals) {
+    fn ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        globals: &mut simulation::Globals,
+        camera: &mut Camera,
+        live_agents: Option<u32>,
+    ) {
         if self.checkpoint_fps_time.elapsed().as_secs_f32() > 0.2 {
             let frames = globals.timing.frame - self.checkpoint_fps_frame;
             self.fps = (frames) as f32 / self.checkpoint_fps_time.elapsed().as_secs_f32();
@@ -51,18 +217,876 @@ impl InfoPane {
 
             ui.end_row();
 
+            ui.label("Time");
+            ui.label(egui::RichText::new(format!("{:.2}", globals.timing.elapsed())).monospace());
+        });
+
+        draw_section(ui, "Device", |ui| {
+            let adapter = &self.adapter;
+            ui.label("Adapter");
+            ui.label(&adapter.name);
+            ui.end_row();
+
+            ui.label("Backend");
+            ui.label(format!("{:?}", adapter.backend));
+            ui.end_row();
+
+            ui.label("Type");
+            ui.label(format!("{:?}", adapter.device_type));
+            ui.end_row();
+
+            if !adapter.driver.is_empty() {
+                ui.label("Driver");
+                ui.label(format!("{} {}", adapter.driver, adapter.driver_info));
+                ui.end_row();
+            }
+
+            let access = self.trail_support.access(globals.scene.trail_format);
+            ui.label("Trail access");
+            ui.label(access.map_or("Unsupported", |access| access.name()));
+            ui.end_row();
+
+            let enabled = |feature| {
+                if self.features.contains(feature) {
+                    "Yes"
+                } else {
+                    "No"
+                }
+            };
+            ui.label("Read-write storage");
+            ui.label(enabled(
+                wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
+            ));
+            ui.end_row();
+
+            ui.label("GPU timestamps");
+            ui.label(enabled(wgpu::Features::TIMESTAMP_QUERY));
+        });
+
+        draw_section(ui, "Trail", |ui| {
+            ui.label("Format");
+            egui::ComboBox::from_id_source("Trail format")
+                .selected_text(globals.scene.trail_format.name())
+                .show_ui(ui, |ui| {
+                    for format in &self.trail_formats {
+                        let trail_format = &mut globals.scene.trail_format;
+                        ui.selectable_value(trail_format, *format, format.name());
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Decay");
+            ui.add(egui::Slider::new(
+                &mut globals.scene.behaviour.decay,
+                0.0..=5.0,
+            ));
+        });
+
+        draw_section(ui, "Palette", |ui| {
+            let palette = &mut globals.scene.palette;
+
+            ui.label("Source");
+            egui::ComboBox::from_id_source("Color source")
+                .selected_text(palette.source.name())
+                .show_ui(ui, |ui| {
+                    for source in ColorSource::ALL {
+                        ui.selectable_value(&mut palette.source, source, source.name());
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Gradient");
+            egui::ComboBox::from_id_source("Gradient")
+                .selected_text(palette.gradient.name())
+                .show_ui(ui, |ui| {
+                    for gradient in Gradient::ALL {
+                        let selected = palette.gradient.name() == gradient.name();
+                        if ui.selectable_label(selected, gradient.name()).clicked() && !selected {
+                            palette.gradient = match gradient {
+                                // Start editing from the current colours
+                                Gradient::Custom(_) => {
+                                    palette.gradient.to_custom(CUSTOM_GRADIENT_STOPS)
+                                }
+                                _ => gradient,
+                            };
+                        }
+                    }
+                });
+            ui.end_row();
+
+            ui.label("");
+            gradient_ui(ui, &mut palette.gradient);
+            ui.end_row();
+
+            ui.label("Background");
+            ui.color_edit_button_srgb(&mut palette.background);
+            ui.end_row();
+
+            ui.label("Range");
+            ui.add(egui::Slider::new(&mut palette.range, 0.01..=10.0).logarithmic(true));
+            ui.end_row();
+
+            ui.label("Preset");
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.palette_path);
+                if ui.button("Save").clicked() {
+                    if let Err(e) = palette.save(self.palette_path.as_ref()) {
+                        self.errors.push(e);
+                    }
+                }
+                if ui.button("Load").clicked() {
+                    match Palette::load(self.palette_path.as_ref()) {
+                        Ok(loaded) => *palette = loaded,
+                        Err(e) => self.errors.push(e),
+                    }
+                }
+            });
+        });
+
+        draw_section(ui, "Post-processing", |ui| {
+            let stages = &mut globals.scene.post;
+            let mut swap = None;
+            let count = stages.len();
+
+            for (i, stage) in stages.iter_mut().enumerate() {
+                ui.checkbox(&mut stage.enabled, stage.effect.name());
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(i > 0, egui::Button::new("Up").small())
+                        .clicked()
+                    {
+                        swap = Some(i - 1);
+                    }
+                    if ui
+                        .add_enabled(i + 1 < count, egui::Button::new("Down").small())
+                        .clicked()
+                    {
+                        swap = Some(i);
+                    }
+
+                    ui.add_enabled_ui(stage.enabled, |ui| effect_ui(ui, &mut stage.effect));
+                });
+                ui.end_row();
+            }
+
+            // Stages are applied top to bottom
+            if let Some(i) = swap {
+                stages.swap(i, i + 1);
+            }
+        });
+
+        draw_section(ui, "View", |ui| {
+            ui.label("Zoom");
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new(format!("{:.2}x", camera.zoom())).monospace());
+                if ui.button("Reset").clicked() {
+                    *camera = Camera::default();
+                }
+            });
+            ui.end_row();
+
+            ui.label("Screenshot");
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_id_source("Screenshot view")
+                    .selected_text(self.screenshot_view.name())
+                    .show_ui(ui, |ui| {
+                        for view in screenshot::View::ALL {
+                            ui.selectable_value(&mut self.screenshot_view, view, view.name());
+                        }
+                    });
+                if ui.button("Save").clicked() {
+                    self.screenshot_requested = true;
+                }
+            });
+        });
+
+        draw_section(ui, "Sprites", |ui| {
+            let sprites = &mut globals.scene.sprites;
+
+            ui.label("Enabled");
+            let mut enabled = sprites.is_some();
+            if ui.checkbox(&mut enabled, "").changed() {
+                *sprites = enabled.then(Sprites::default);
+            }
+
+            let Some(sprites) = sprites else {
+                return;
+            };
+
+            ui.end_row();
+            ui.label("Shape");
+            egui::ComboBox::from_id_source("Sprite shape")
+                .selected_text(sprites.shape.name())
+                .show_ui(ui, |ui| {
+                    for shape in SpriteShape::ALL {
+                        ui.selectable_value(&mut sprites.shape, shape, shape.name());
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Size");
+            ui.add(egui::Slider::new(&mut sprites.size, 1.0..=32.0).logarithmic(true));
+            ui.end_row();
+
+            ui.label("Opacity");
+            ui.add(egui::Slider::new(&mut sprites.opacity, 0.0..=1.0));
+            ui.end_row();
+
+            if sprites.shape == SpriteShape::Line {
+                ui.label("Length");
+                ui.add(egui::Slider::new(&mut sprites.length, 0.0..=4.0).suffix(" s"));
+                ui.end_row();
+            }
+
+            ui.label("Hide trail");
+            ui.checkbox(&mut sprites.hide_trail, "");
+        });
+
+        draw_section(ui, "Agents", |ui| {
+            let scene = &mut globals.scene;
+
+            ui.label("Count");
+            ui.add(
+                egui::DragValue::new(&mut scene.agents)
+                    .clamp_range(0..=MAX_AGENTS)
+                    .speed(1000.0),
+            );
+            ui.end_row();
+
+            ui.label("Spawn");
+            egui::ComboBox::from_id_source("Spawn")
+                .selected_text(scene.spawn.name())
+                .show_ui(ui, |ui| {
+                    for spawn in Spawn::ALL {
+                        let selected = scene.spawn.name() == spawn.name();
+                        if ui.selectable_label(selected, spawn.name()).clicked() && !selected {
+                            scene.spawn = spawn;
+                        }
+                    }
+                });
+
+            if let Spawn::Disc { radius } | Spawn::Ring { radius } = &mut scene.spawn {
+                ui.end_row();
+                ui.label("Radius");
+                ui.add(egui::Slider::new(radius, 0.0..=1.0));
+            }
+            ui.end_row();
+
+            let behaviour = &mut scene.behaviour;
+            ui.label("Sensor angle");
+            ui.add(egui::Slider::new(
+                &mut behaviour.sensor_angle,
+                0.0..=std::f32::consts::PI,
+            ));
+            ui.end_row();
+
+            ui.label("Sensor distance");
+            ui.add(egui::Slider::new(
+                &mut behaviour.sensor_distance,
+                1.0..=64.0,
+            ));
+            ui.end_row();
+
+            ui.label("Turn rate");
+            ui.add(egui::Slider::new(&mut behaviour.turn_rate, 0.0..=0.2));
+        });
+
+        draw_section(ui, "Tools", |ui| {
+            ui.label("Tool");
+            egui::ComboBox::from_id_source("Tool")
+                .selected_text(self.tool.name())
+                .show_ui(ui, |ui| {
+                    for tool in Tool::ALL {
+                        ui.selectable_value(&mut self.tool, tool, tool.name());
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Size");
+            ui.add(egui::Slider::new(&mut self.tool_size, 0.01..=0.5));
+
+            if let Tool::Brush(_) = self.tool {
+                ui.end_row();
+                ui.label("Strength");
+                ui.add(egui::Slider::new(&mut self.brush_strength, 0.0..=1.0));
+                ui.end_row();
+
+                ui.label("Falloff");
+                ui.add(egui::Slider::new(&mut self.brush_falloff, 0.0..=1.0));
+            }
+        });
+
+        draw_section(ui, "Environment", |ui| {
+            ui.label("Boundary");
+            egui::ComboBox::from_id_source("Boundary")
+                .selected_text(globals.scene.boundary.name())
+                .show_ui(ui, |ui| {
+                    for boundary in Boundary::ALL {
+                        let selected = &mut globals.scene.boundary;
+                        ui.selectable_value(selected, boundary, boundary.name());
+                    }
+                });
+            ui.end_row();
+
+            let environment = &mut globals.scene.environment;
+            ui.label("Features");
+            ui.label(format!(
+                "{} food, {} obstacles, {} attractors, {} fields",
+                environment.food.len(),
+                environment.obstacles.len(),
+                environment.attractors.len(),
+                environment.fields.len()
+            ));
+            ui.end_row();
+
+            ui.label("");
+            if ui.button("Clear").clicked() {
+                *environment = scene::Environment::default();
+            }
+        });
+
+        draw_section(ui, "Mask", |ui| {
+            let mask = &mut globals.scene.environment.mask;
+
+            ui.label("Image");
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.mask_path);
+                if ui.button("Load").clicked() && !self.mask_path.is_empty() {
+                    let path = self.mask_path.clone().into();
+                    match mask {
+                        Some(mask) => mask.path = path,
+                        None => {
+                            *mask = Some(Mask {
+                                path,
+                                mode: MaskMode::default(),
+                                fit: MaskFit::default(),
+                                strength: 1.0,
+                                invert: false,
+                            })
+                        }
+                    }
+                }
+            });
+
+            let Some(current) = mask else {
+                return;
+            };
+
+            ui.end_row();
+            ui.label("Mode");
+            egui::ComboBox::from_id_source("Mask mode")
+                .selected_text(current.mode.name())
+                .show_ui(ui, |ui| {
+                    for mode in MaskMode::ALL {
+                        ui.selectable_value(&mut current.mode, mode, mode.name());
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Fit");
+            egui::ComboBox::from_id_source("Mask fit")
+                .selected_text(current.fit.name())
+                .show_ui(ui, |ui| {
+                    for fit in MaskFit::ALL {
+                        ui.selectable_value(&mut current.fit, fit, fit.name());
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Strength");
+            ui.add(egui::Slider::new(&mut current.strength, 0.0..=1.0));
+            ui.end_row();
+
+            ui.label("Invert");
+            ui.checkbox(&mut current.invert, "");
+            ui.end_row();
+
+            ui.label("");
+            if ui.button("Remove").clicked() {
+                *mask = None;
+            }
+        });
+
+        draw_section(ui, "Lifecycle", |ui| {
+            let lifecycle = &mut globals.scene.lifecycle;
+
+            ui.label("Enabled");
+            let mut enabled = lifecycle.is_some();
+            if ui.checkbox(&mut enabled, "").changed() {
+                *lifecycle = enabled.then(Lifecycle::default);
+            }
+
+            let Some(lifecycle) = lifecycle else {
+                return;
+            };
+
+            ui.end_row();
+            ui.label("Alive");
+            let alive = live_agents.map_or("-".to_string(), |n| n.to_string());
+            ui.label(egui::RichText::new(alive).monospace());
+            ui.end_row();
+
+            ui.label("Metabolism");
+            ui.add(egui::Slider::new(&mut lifecycle.metabolism, 0.0..=1.0));
+            ui.end_row();
+
+            ui.label("Feeding");
+            ui.add(egui::Slider::new(&mut lifecycle.feeding, 0.0..=2.0));
+            ui.end_row();
+
+            ui.label("Reproduction energy");
+            ui.add(egui::Slider::new(
+                &mut lifecycle.reproduction_energy,
+                0.0..=10.0,
+            ));
+            ui.end_row();
+
+            ui.label("Reproduction trail");
+            ui.add(egui::Slider::new(
+                &mut lifecycle.reproduction_trail,
+                0.0..=2.0,
+            ));
+            ui.end_row();
+
+            ui.label("Max age");
+            ui.add(egui::Slider::new(&mut lifecycle.max_age, 1.0..=600.0).suffix(" s"));
+            ui.end_row();
+
+            ui.label("Mutation");
+            ui.add(egui::Slider::new(&mut lifecycle.mutation, 0.0..=0.5));
+        });
+
+        draw_section(ui, "Timeline", |ui| {
+            let time = globals.timing.elapsed();
+            let scene = &mut globals.scene;
+            let current = Parameter::ALL.map(|parameter| parameter.value(scene));
+            let current_gradient = scene.palette.gradient.clone();
+            let timeline = &mut scene.timeline;
+
             ui.label("Time");
             ui.label(
-                egui::RichText::new(format!(
-                    "{:.2}",
-                    (globals.timing.time - globals.timing.start_time).as_secs_f32()
-                ))
-                .monospace(),
+                egui::RichText::new(format!("{:.2} / {:.2}", time, timeline.duration()))
+                    .monospace(),
             );
+            ui.end_row();
+
+            ui.label("Curves");
+            timeline_plot(ui, timeline, time);
+            ui.end_row();
+
+            let mut removed = None;
+            for (i, track) in timeline.tracks.iter_mut().enumerate() {
+                egui::ComboBox::from_id_source(("Track", i))
+                    .selected_text(track.parameter.name())
+                    .show_ui(ui, |ui| {
+                        for parameter in Parameter::ALL {
+                            ui.selectable_value(&mut track.parameter, parameter, parameter.name());
+                        }
+                    });
+                ui.horizontal(|ui| {
+                    if ui.button("Key").clicked() {
+                        let index = Parameter::ALL.iter().position(|p| *p == track.parameter);
+                        let value = index.map_or(0.0, |index| current[index]);
+                        track.keyframes.push(Keyframe::new(time, value));
+                    }
+                    if ui.button("Remove").clicked() {
+                        removed = Some(i);
+                    }
+                });
+                ui.end_row();
+
+                let mut removed_keyframe = None;
+                for (j, keyframe) in track.keyframes.iter_mut().enumerate() {
+                    ui.label("");
+                    ui.horizontal(|ui| {
+                        keyframe_ui(ui, (i, j), keyframe);
+                        ui.add(egui::DragValue::new(&mut keyframe.value).speed(0.01));
+                        if ui.small_button("Remove").clicked() {
+                            removed_keyframe = Some(j);
+                        }
+                    });
+                    ui.end_row();
+                }
+
+                if let Some(j) = removed_keyframe {
+                    track.keyframes.remove(j);
+                }
+            }
+
+            if let Some(i) = removed {
+                timeline.tracks.remove(i);
+            }
+
+            ui.label("");
+            if ui.button("Add track").clicked() {
+                // The first parameter that isn't animated yet
+                let parameter = Parameter::ALL
+                    .into_iter()
+                    .find(|p| timeline.tracks.iter().all(|track| track.parameter != *p))
+                    .unwrap_or(Parameter::SensorAngle);
+                timeline.tracks.push(Track::new(parameter));
+            }
+            ui.end_row();
+
+            ui.label("Gradient");
+            if ui.button("Key").clicked() {
+                timeline
+                    .gradient
+                    .push(Keyframe::new(time, current_gradient));
+            }
+            ui.end_row();
+
+            let mut removed = None;
+            for (j, keyframe) in timeline.gradient.iter_mut().enumerate() {
+                ui.label("");
+                ui.vertical(|ui| {
+                    ui.horizontal(|ui| {
+                        keyframe_ui(ui, ("Gradient", j), keyframe);
+                        if ui.small_button("Remove").clicked() {
+                            removed = Some(j);
+                        }
+                    });
+                    gradient_ui(ui, &mut keyframe.value);
+                });
+                ui.end_row();
+            }
+
+            if let Some(j) = removed {
+                timeline.gradient.remove(j);
+            }
+
+            ui.label("Preset");
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.timeline_path);
+                if ui.button("Save").clicked() {
+                    if let Err(e) = timeline.save(self.timeline_path.as_ref()) {
+                        self.errors.push(e);
+                    }
+                }
+                if ui.button("Load").clicked() {
+                    match Timeline::load(self.timeline_path.as_ref()) {
+                        Ok(loaded) => *timeline = loaded,
+                        Err(e) => self.errors.push(e),
+                    }
+                }
+            });
+        });
+    }
+
+    fn profiler_ui(&self, ui: &mut egui::Ui, profiler: &Profiler) {
+        draw_profiler(ui, profiler);
+
+        // Compare every format that has been profiled during this run
+        draw_section(ui, "Trail formats", |ui| {
+            ui.label("Format");
+            ui.label("Access");
+            ui.label("Bytes/px");
+            ui.label("Precision");
+            for pass in [Pass::Diffuse, Pass::Simulation, Pass::Render] {
+                ui.label(pass.name());
+            }
+            ui.end_row();
+
+            for format in &self.trail_formats {
+                ui.label(format.name());
+                let access = self.trail_support.access(*format);
+                ui.label(access.map_or("", |access| access.name()));
+                ui.label(format.bytes_per_pixel().to_string());
+                ui.label(format!("{} bits", format.precision_bits()));
+                for pass in [Pass::Diffuse, Pass::Simulation, Pass::Render] {
+                    let ms = profiler.config_average(format.name(), pass);
+                    ui.label(egui::RichText::new(format_ms(ms)).monospace());
+                }
+                ui.end_row();
+            }
+        });
+    }
+}
+
+impl Errors {
+    /// Repeats of the last error are only shown once, since errors the
+    /// device reports tend to recur every frame.
+    fn push(&mut self, error: impl std::fmt::Display) {
+        let message = error.to_string();
+        if self.0.last() != Some(&message) {
+            self.0.push(message);
+        }
+    }
+
+    fn ui(&mut self, ctx: &egui::Context) {
+        if self.0.is_empty() {
+            return;
+        }
+
+        egui::Window::new("Errors")
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-8.0, -8.0))
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical()
+                    .max_height(200.0)
+                    .show(ui, |ui| {
+                        for message in &self.0 {
+                            ui.colored_label(ui.visuals().error_fg_color, message);
+                        }
+                    });
+                if ui.button("Dismiss").clicked() {
+                    self.0.clear();
+                }
+            });
+    }
+}
+
+fn format_ms(ms: Option<f32>) -> String {
+    match ms {
+        Some(ms) => format!("{:.3} ms", ms),
+        None => "-".to_string(),
+    }
+}
+
+fn draw_profiler(ui: &mut egui::Ui, profiler: &Profiler) {
+    draw_section(ui, "Profiler", |ui| {
+        ui.label("Source");
+        ui.label(profiler.source().name());
+        ui.end_row();
+
+        for pass in Pass::ALL {
+            ui.label(pass.name());
+            ui.label(egui::RichText::new(format_ms(profiler.average(pass))).monospace());
+            ui.end_row();
+        }
+
+        ui.label("Total");
+        ui.label(egui::RichText::new(format_ms(profiler.average_total())).monospace());
+    });
+
+    let lines = Pass::ALL.map(|pass| {
+        let points: egui::plot::PlotPoints = profiler
+            .history()
+            .filter_map(|sample| Some([sample.frame as f64, sample.get(pass)? as f64]))
+            .collect();
+
+        egui::plot::Line::new(points).name(pass.name())
+    });
+
+    egui::plot::Plot::new("Profiler graph")
+        .height(120.0)
+        .legend(egui::plot::Legend::default())
+        .include_y(0.0)
+        .allow_drag(false)
+        .allow_zoom(false)
+        .allow_scroll(false)
+        .show_x(false)
+        .show(ui, |plot_ui| {
+            for line in lines {
+                plot_ui.line(line);
+            }
+        });
+}
+
+impl Tool {
+    const ALL: [Tool; 11] = [
+        Tool::None,
+        Tool::Food,
+        Tool::Obstacle,
+        Tool::Attractor,
+        Tool::Repulsor,
+        Tool::Vortex,
+        Tool::Brush(BrushKind::Paint),
+        Tool::Brush(BrushKind::Erase),
+        Tool::Brush(BrushKind::Push),
+        Tool::Brush(BrushKind::Pull),
+        Tool::Brush(BrushKind::Spawn),
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Tool::None => "None",
+            Tool::Food => "Food",
+            Tool::Obstacle => "Obstacle",
+            Tool::Attractor => "Attractor",
+            Tool::Repulsor => "Repulsor",
+            Tool::Vortex => "Vortex",
+            Tool::Brush(kind) => kind.name(),
+        }
+    }
+}
+
+/// Parameters of a post-processing effect.
+fn effect_ui(ui: &mut egui::Ui, effect: &mut Effect) {
+    match effect {
+        Effect::Exposure { stops } => {
+            ui.add(egui::Slider::new(stops, -5.0..=5.0).text("stops"));
+        }
+        Effect::ToneMap { operator } => {
+            egui::ComboBox::from_id_source("Tone map")
+                .selected_text(operator.name())
+                .show_ui(ui, |ui| {
+                    for option in ToneMap::ALL {
+                        ui.selectable_value(operator, option, option.name());
+                    }
+                });
+        }
+        Effect::Bloom {
+            threshold,
+            radius,
+            intensity,
+        } => {
+            ui.vertical(|ui| {
+                ui.add(egui::Slider::new(threshold, 0.0..=4.0).text("threshold"));
+                ui.add(egui::Slider::new(radius, 0.001..=0.1).text("radius"));
+                ui.add(egui::Slider::new(intensity, 0.0..=2.0).text("intensity"));
+            });
+        }
+        Effect::Vignette { strength, radius } => {
+            ui.vertical(|ui| {
+                ui.add(egui::Slider::new(strength, 0.0..=1.0).text("strength"));
+                ui.add(egui::Slider::new(radius, 0.0..=1.0).text("radius"));
+            });
+        }
+        Effect::Grain { strength } | Effect::Sharpen { strength } => {
+            ui.add(egui::Slider::new(strength, 0.0..=1.0).text("strength"));
+        }
+    }
+}
+
+/// Time and curve of a keyframe, with the control points of Bézier curves.
+fn keyframe_ui<T>(ui: &mut egui::Ui, id: impl std::hash::Hash, keyframe: &mut Keyframe<T>) {
+    ui.add(
+        egui::DragValue::new(&mut keyframe.time)
+            .clamp_range(0.0..=f32::MAX)
+            .speed(0.1)
+            .suffix(" s"),
+    );
+
+    egui::ComboBox::from_id_source(id)
+        .width(60.0)
+        .selected_text(keyframe.curve.name())
+        .show_ui(ui, |ui| {
+            for curve in Curve::ALL {
+                let selected = keyframe.curve.name() == curve.name();
+                if ui.selectable_label(selected, curve.name()).clicked() && !selected {
+                    keyframe.curve = curve;
+                }
+            }
         });
+
+    if let Curve::Bezier { x1, y1, x2, y2 } = &mut keyframe.curve {
+        for value in [x1, y1, x2, y2] {
+            ui.add(egui::DragValue::new(value).speed(0.01));
+        }
     }
 }
 
+/// Every track over the length of the timeline, scaled to its own range of
+/// values, with a line at the current time.
+fn timeline_plot(ui: &mut egui::Ui, timeline: &Timeline, time: f32) {
+    const PLOT_STEPS: usize = 200;
+
+    let end = timeline.duration().max(time) * 1.1 + 1.0;
+    let lines: Vec<_> = timeline
+        .tracks
+        .iter()
+        .map(|track| {
+            let values: Vec<(f32, f32)> = (0..=PLOT_STEPS)
+                .filter_map(|i| {
+                    let t = end * i as f32 / PLOT_STEPS as f32;
+                    Some((t, track.value(t)?))
+                })
+                .collect();
+            let (min, max) = values
+                .iter()
+                .fold((f32::MAX, f32::MIN), |(min, max), (_, v)| {
+                    (min.min(*v), max.max(*v))
+                });
+            let scale = (max - min).max(f32::EPSILON);
+
+            let points: egui::plot::PlotPoints = values
+                .iter()
+                .map(|(t, v)| [*t as f64, ((v - min) / scale) as f64])
+                .collect();
+            egui::plot::Line::new(points).name(track.parameter.name())
+        })
+        .collect();
+
+    egui::plot::Plot::new("Timeline plot")
+        .height(100.0)
+        .width(200.0)
+        .allow_scroll(false)
+        .allow_zoom(false)
+        .allow_drag(false)
+        .show_y(false)
+        .show(ui, |plot_ui| {
+            for line in lines {
+                plot_ui.line(line);
+            }
+            plot_ui.vline(egui::plot::VLine::new(time as f64));
+        });
+}
+
+/// Preview of a gradient, with its stops below when it is a custom one.
+fn gradient_ui(ui: &mut egui::Ui, gradient: &mut Gradient) {
+    const PREVIEW_STEPS: u32 = 64;
+
+    ui.vertical(|ui| {
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(200.0, 16.0), egui::Sense::hover());
+        let mut mesh = egui::Mesh::default();
+        let sample = gradient.sampler();
+        for i in 0..=PREVIEW_STEPS {
+            let t = i as f32 / PREVIEW_STEPS as f32;
+            let [r, g, b] = sample(t);
+            let color = egui::Color32::from_rgb(r, g, b);
+            let x = egui::lerp(rect.left()..=rect.right(), t);
+            mesh.colored_vertex(egui::pos2(x, rect.top()), color);
+            mesh.colored_vertex(egui::pos2(x, rect.bottom()), color);
+
+            if i > 0 {
+                let v = 2 * i;
+                mesh.add_triangle(v - 2, v - 1, v);
+                mesh.add_triangle(v - 1, v + 1, v);
+            }
+        }
+        ui.painter().add(mesh);
+
+        let Gradient::Custom(stops) = gradient else {
+            return;
+        };
+
+        let mut removed = None;
+        for (i, stop) in stops.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::DragValue::new(&mut stop.position)
+                        .clamp_range(0.0..=1.0)
+                        .speed(0.01),
+                );
+                ui.color_edit_button_srgb(&mut stop.color);
+                if ui.small_button("Remove").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+
+        if let Some(i) = removed {
+            stops.remove(i);
+        }
+
+        if ui.button("Add stop").clicked() {
+            // Halfway across the widest gap between stops, or the ends of the gradient
+            let mut positions: Vec<f32> = stops.iter().map(|stop| stop.position).collect();
+            positions.extend([0.0, 1.0]);
+            positions.sort_by(f32::total_cmp);
+            let position = positions
+                .windows(2)
+                .max_by(|a, b| (a[1] - a[0]).total_cmp(&(b[1] - b[0])))
+                .map_or(0.5, |gap| 0.5 * (gap[0] + gap[1]));
+
+            let color = gradient.sample(position);
+            if let Gradient::Custom(stops) = gradient {
+                stops.push(Stop { position, color });
+            }
+        }
+    });
+}
+
 fn draw_section<F>(ui: &mut egui::Ui, name: &'static str, builder: F)
 where
     F: FnOnce(&mut egui::Ui),
@@ -0,0 +1,389 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use blossim::{
+    adapter::AdapterOptions,
+    camera::Camera,
+    cpu,
+    error::{DeviceErrors, Error, Result},
+    poster,
+    profiler::{Pass, Profiler},
+    renderer::Renderer,
+    scene::Scene,
+    screenshot,
+    simulation::{Simulation, Timing},
+    trail::{TrailAccess, TrailFormat, TrailSupport},
+};
+
+const TARGET_FORMAT: wgpu::TextureFormat = screenshot::FORMAT;
+
+pub struct Options {
+    pub adapter: AdapterOptions,
+    pub frames: usize,
+    pub width: u32,
+    pub height: u32,
+    pub dt: f32,
+    pub scene: Option<PathBuf>,
+    pub trail_format: Option<TrailFormat>,
+    pub profile_csv: Option<PathBuf>,
+    pub screenshot: Option<PathBuf>,
+}
+
+/// Options of a poster export, see [`poster`].
+pub struct PosterOptions {
+    pub adapter: AdapterOptions,
+    pub frames: usize,
+    pub width: u32,
+    pub height: u32,
+    pub dt: f32,
+    pub dpi: f32,
+    /// Samples per side of each poster pixel
+    pub supersample: u32,
+    pub scene: Option<PathBuf>,
+    pub trail_format: Option<TrailFormat>,
+    pub output: PathBuf,
+}
+
+/// Device, queue and scene of a run without a window.
+struct Setup {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    errors: DeviceErrors,
+    trail_support: TrailSupport,
+    scene: Scene,
+}
+
+/// Requests a device with `limits`, which may depend on the adapter, and
+/// loads the scene with a trail format the adapter supports.
+async fn setup(
+    adapter: &AdapterOptions,
+    scene: Option<&Path>,
+    trail_format: Option<TrailFormat>,
+    limits: impl FnOnce(&wgpu::Adapter) -> wgpu::Limits,
+) -> Result<Setup> {
+    let instance = adapter.instance();
+    let adapter = adapter.select(&instance, None).await?;
+    let (device, queue, trail_support) =
+        blossim::adapter::request_device(&adapter, limits(&adapter)).await?;
+    let errors = DeviceErrors::install(&device);
+
+    let mut scene = match scene {
+        Some(path) => Scene::load(path)?,
+        None => Scene::default(),
+    };
+
+    if let Some(format) = trail_format {
+        if trail_support.access(format).is_none() {
+            return Err(Error::UnsupportedTrailFormat(format));
+        }
+
+        scene.trail_format = format;
+    }
+
+    if let Some(fallback) = scene.resolve_trail_format(&trail_support.formats())? {
+        eprintln!("{}", fallback);
+    }
+
+    if let Some(access) = trail_support.access(scene.trail_format) {
+        eprintln!(
+            "Running on {} with {} {} trail access",
+            blossim::adapter::describe(&adapter.get_info()),
+            scene.trail_format.name(),
+            access.name().to_lowercase()
+        );
+    }
+
+    Ok(Setup {
+        device,
+        queue,
+        errors,
+        trail_support,
+        scene,
+    })
+}
+
+/// Runs the simulation without a window for a fixed number of frames, on
+/// the CPU when there is no graphics adapter at all.
+pub async fn run(options: Options) -> Result<()> {
+    let setup = setup(
+        &options.adapter,
+        options.scene.as_deref(),
+        options.trail_format,
+        |_| wgpu::Limits::default(),
+    )
+    .await;
+    let Setup {
+        device,
+        queue,
+        errors,
+        trail_support,
+        scene,
+    } = match setup {
+        Err(Error::NoAdapter) => return run_on_cpu(options),
+        setup => setup?,
+    };
+    let trail_format = scene.trail_format;
+
+    let mut simulation = Simulation::new(
+        &device,
+        &queue,
+        glam::UVec2::new(options.width, options.height),
+        Timing::new(Some(Duration::from_secs_f32(options.dt))),
+        scene,
+        trail_support,
+    )?;
+
+    let mut renderer = Renderer::new(&device, TARGET_FORMAT, trail_format)?;
+    let domain = simulation.domain();
+    let camera = Camera::default();
+
+    let mut profiler = Profiler::new(&device, &queue).with_log();
+
+    let target = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Headless target"),
+        size: wgpu::Extent3d {
+            width: domain.x,
+            height: domain.y,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: TARGET_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+
+    for _ in 0..options.frames {
+        simulation.globals.timing.tick();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Headless Encoder"),
+        });
+
+        profiler.set_config(trail_format.name());
+        profiler.begin_frame();
+        simulation.step(&device, &queue, &mut encoder, &mut profiler)?;
+        renderer.render(
+            &device,
+            &mut encoder,
+            &target,
+            &simulation,
+            &camera,
+            &mut profiler,
+        )?;
+        profiler.end_frame(&mut encoder);
+
+        queue.submit(std::iter::once(encoder.finish()));
+
+        profiler.after_submit();
+        profiler.poll(&device, false);
+        simulation.after_submit();
+        simulation.poll(&device);
+        check(&errors)?;
+        warn(&mut simulation);
+    }
+
+    profiler.poll(&device, true);
+    device.poll(wgpu::Maintain::Wait);
+    simulation.poll(&device);
+
+    if simulation.globals.scene.lifecycle.is_some() {
+        if let Some(live) = simulation.live_agents() {
+            println!("Live agents: {}", live);
+        }
+    }
+
+    println!(
+        "Profiler ({}, {}):",
+        profiler.source().name(),
+        trail_format.name()
+    );
+    for pass in Pass::ALL {
+        if let Some(ms) = profiler.average(pass) {
+            println!("  {:<12}{:.3} ms", pass.name(), ms);
+        }
+    }
+
+    if let Some(path) = options.screenshot {
+        let image =
+            screenshot::capture(&device, &queue, &mut renderer, &simulation, &camera, domain)?;
+        screenshot::save(&image, &path)?;
+    }
+
+    if let Some(path) = options.profile_csv {
+        let file = File::create(&path).map_err(|e| Error::io(&path, e))?;
+        profiler
+            .write_csv(BufWriter::new(file))
+            .map_err(|e| Error::io(&path, e))?;
+    }
+
+    Ok(())
+}
+
+/// Runs the simulation with the CPU reference implementation, which leaves
+/// out everything but agents and trails.
+fn run_on_cpu(options: Options) -> Result<()> {
+    let mut scene = match &options.scene {
+        Some(path) => Scene::load(path)?,
+        None => Scene::default(),
+    };
+
+    if let Some(format) = options.trail_format {
+        scene.trail_format = format;
+    }
+
+    eprintln!("No graphics adapter was found, simulating on the CPU");
+    let unsupported = cpu::unsupported(&scene);
+    if !unsupported.is_empty() {
+        eprintln!("The CPU leaves out the scene's {}", unsupported.join(", "));
+    }
+
+    let mut simulation = cpu::Simulation::new(
+        glam::UVec2::new(options.width, options.height),
+        Timing::new(Some(Duration::from_secs_f32(options.dt))),
+        scene,
+        TrailAccess::ReadWrite,
+    )?;
+
+    let start = Instant::now();
+    for frame in 0..options.frames {
+        simulation.globals.timing.tick();
+        simulation.step();
+        progress("Simulating", frame + 1, options.frames);
+    }
+
+    let frame_ms = start.elapsed().as_secs_f64() * 1000.0 / options.frames.max(1) as f64;
+    println!("CPU step: {:.3} ms", frame_ms);
+
+    if let Some(path) = options.screenshot {
+        screenshot::save(&simulation.render(), &path)?;
+    }
+
+    if let Some(path) = options.profile_csv {
+        eprintln!("Profiling needs a GPU, {} was not written", path.display());
+    }
+
+    Ok(())
+}
+
+/// Simulates at print resolution and writes a single large image, rendered
+/// in tiles small enough for the adapter.
+pub async fn poster(options: PosterOptions) -> Result<()> {
+    // Fail before simulating for minutes when the format is unknown
+    poster::Encoding::from_path(&options.output)?;
+
+    // Posters need the largest textures and buffers the adapter has
+    let Setup {
+        device,
+        queue,
+        errors,
+        trail_support,
+        mut scene,
+    } = setup(
+        &options.adapter,
+        options.scene.as_deref(),
+        options.trail_format,
+        wgpu::Adapter::limits,
+    )
+    .await?;
+    let trail_format = scene.trail_format;
+
+    // Sprites are sized in output pixels, which are split into samples
+    let supersample = options.supersample.max(1);
+    if let Some(sprites) = &mut scene.sprites {
+        sprites.size *= supersample as f32;
+    }
+
+    let size = glam::uvec2(options.width, options.height).max(glam::UVec2::ONE);
+    let post_processed = scene.post.iter().any(|stage| stage.enabled);
+    if poster::Tiling::new(&device, size, supersample).count() > 1 && post_processed {
+        eprintln!("Post-processing is applied to each tile separately, effects may show seams");
+    }
+
+    // Fails when the trail map of `size` exceeds the adapter's limits
+    let mut simulation = Simulation::new(
+        &device,
+        &queue,
+        size,
+        Timing::new(Some(Duration::from_secs_f32(options.dt))),
+        scene,
+        trail_support,
+    )?;
+
+    let mut profiler = Profiler::new(&device, &queue);
+    for frame in 0..options.frames {
+        simulation.globals.timing.tick();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Poster Encoder"),
+        });
+
+        profiler.begin_frame();
+        simulation.step(&device, &queue, &mut encoder, &mut profiler)?;
+        profiler.end_frame(&mut encoder);
+
+        queue.submit(std::iter::once(encoder.finish()));
+
+        // Waiting for every frame keeps the progress honest at sizes where frames take seconds
+        profiler.after_submit();
+        simulation.after_submit();
+        device.poll(wgpu::Maintain::Wait);
+        profiler.poll(&device, false);
+        simulation.poll(&device);
+        check(&errors)?;
+        warn(&mut simulation);
+
+        progress("Simulating", frame + 1, options.frames);
+    }
+
+    let mut renderer = Renderer::new(&device, screenshot::FORMAT, trail_format)?;
+    let image = poster::render(
+        &device,
+        &queue,
+        &mut renderer,
+        &simulation,
+        size,
+        supersample,
+        |done, total| progress("Rendering tiles", done, total),
+    )?;
+
+    check(&errors)?;
+
+    eprintln!("Writing {}", options.output.display());
+    poster::save(&image, &options.output, options.dpi)
+}
+
+/// Stops a run at the first error the device reported, since its output
+/// can't be trusted after one.
+fn check(errors: &DeviceErrors) -> Result<()> {
+    match errors.take().into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// Prints the errors the simulation recovered from, such as agent rules
+/// edited into an invalid state while it runs.
+fn warn(simulation: &mut Simulation) {
+    for error in simulation.take_errors() {
+        eprintln!("{}", error);
+    }
+}
+
+/// Overwrites the current line with how far along `stage` is.
+fn progress(stage: &str, done: usize, total: usize) {
+    let percent = done * 100 / total.max(1);
+    let previous = (done - 1) * 100 / total.max(1);
+    if done == 1 || done == total || percent != previous {
+        eprint!("\r{}: {}/{} ({}%)", stage, done, total, percent);
+        if done == total {
+            eprintln!();
+        }
+        io::stderr().flush().ok();
+    }
+}
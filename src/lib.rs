@@ -0,0 +1,89 @@
+//! Physarum-style agent simulation on the GPU.
+//!
+//! A [`Simulation`] owns the agents, trail map and environment of a
+//! [`Scene`] on a `wgpu` device and advances them one frame per
+//! [`Simulation::step`]. A [`Renderer`] draws the result into any texture,
+//! and [`screenshot`] and [`poster`] read images back for saving.
+//!
+//! The caller owns the device and queue, and records steps and renders into
+//! its own command encoders, so the simulation can be embedded next to other
+//! rendering. [`adapter::request_device`] opens a device with the features
+//! the simulation can use, or [`trail::TrailSupport::new`] describes one
+//! opened elsewhere:
+//!
+//! ```no_run
+//! # async fn run(adapter: wgpu::Adapter) -> blossim::Result<()> {
+//! use blossim::{adapter, profiler::Profiler, simulation::Timing, Camera, Renderer, Scene, Simulation};
+//!
+//! let (device, queue, trail_support) =
+//!     adapter::request_device(&adapter, wgpu::Limits::default()).await?;
+//! let mut scene = Scene::default();
+//! scene.resolve_trail_format(&trail_support.formats())?;
+//! let trail_format = scene.trail_format;
+//! let timing = Timing::new(Some(std::time::Duration::from_secs_f32(1.0 / 60.0)));
+//! let size = glam::uvec2(1920, 1080);
+//! let mut simulation = Simulation::new(&device, &queue, size, timing, scene, trail_support)?;
+//! let mut renderer = Renderer::new(&device, blossim::screenshot::FORMAT, trail_format)?;
+//! let mut profiler = Profiler::new(&device, &queue);
+//!
+//! for _ in 0..600 {
+//!     simulation.globals.timing.tick();
+//!     let mut encoder = device.create_command_encoder(&Default::default());
+//!     simulation.step(&device, &queue, &mut encoder, &mut profiler)?;
+//!     queue.submit(std::iter::once(encoder.finish()));
+//!     simulation.after_submit();
+//!     simulation.poll(&device);
+//! }
+//!
+//! let image = blossim::screenshot::capture(
+//!     &device,
+//!     &queue,
+//!     &mut renderer,
+//!     &simulation,
+//!     &Camera::default(),
+//!     simulation.domain(),
+//! )?;
+//! blossim::screenshot::save(&image, "out.png".as_ref())?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Scenes are plain data and (de)serialise with serde. Fields added to them
+//! in later versions come with defaults, so scene files keep loading. GPU
+//! layouts, shaders and pipelines are private and may change in any release,
+//! and so are the hidden `cpu`, `golden` and `bench` modules, which only
+//! support the `blossim` binary and its tests.
+
+pub mod adapter;
+#[doc(hidden)]
+pub mod bench;
+pub mod brush;
+pub mod camera;
+#[doc(hidden)]
+pub mod cpu;
+mod environment;
+pub mod error;
+#[doc(hidden)]
+pub mod golden;
+pub mod palette;
+mod pipeline;
+mod population;
+pub mod post;
+pub mod poster;
+pub mod profiler;
+mod readback;
+pub mod renderer;
+pub mod rules;
+pub mod scene;
+pub mod screenshot;
+mod shader;
+pub mod simulation;
+mod storage;
+pub mod timeline;
+pub mod trail;
+
+pub use camera::Camera;
+pub use error::{Error, Result};
+pub use renderer::Renderer;
+pub use scene::Scene;
+pub use simulation::Simulation;
@@ -1,9 +1,16 @@
 mod app;
 mod gui;
-mod pipeline;
-mod storage;
+mod headless;
+
+use std::path::PathBuf;
 
 use app::State;
+use blossim::{
+    adapter::{self, AdapterOptions, Backend},
+    bench, golden,
+    trail::{self, TrailSupport},
+};
+use clap::{Parser, Subcommand};
 use winit::{
     dpi::LogicalSize,
     event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
@@ -11,7 +18,146 @@ use winit::{
     window::WindowBuilder,
 };
 
-async fn run() -> Result<(), String> {
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Scene file to load, reloaded whenever it changes while running in a window
+    #[arg(long, global = true)]
+    scene: Option<PathBuf>,
+
+    /// Run on the first graphics adapter whose name contains this, ignoring case
+    #[arg(long, global = true)]
+    adapter: Option<String>,
+
+    /// Only consider adapters of this graphics API
+    #[arg(long, global = true, value_enum)]
+    backend: Option<Backend>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the graphics adapters and the trail formats each supports
+    Adapters,
+    /// Run the simulation without a window
+    Headless {
+        /// Number of frames to simulate
+        #[arg(long, default_value_t = 600)]
+        frames: usize,
+
+        /// Width of the simulation domain in pixels
+        #[arg(long, default_value_t = 1920)]
+        width: u32,
+
+        /// Height of the simulation domain in pixels
+        #[arg(long, default_value_t = 1080)]
+        height: u32,
+
+        /// Fixed timestep in seconds
+        #[arg(long, default_value_t = 1.0 / 60.0)]
+        dt: f32,
+
+        /// Storage format of the trail map, defaults to the most precise supported format
+        #[arg(long, value_enum)]
+        trail_format: Option<trail::TrailFormat>,
+
+        /// Write per-pass timings for every frame to a CSV file
+        #[arg(long)]
+        profile_csv: Option<PathBuf>,
+
+        /// Save the whole domain as a PNG image after the last frame
+        #[arg(long)]
+        screenshot: Option<PathBuf>,
+    },
+    /// Simulate at print resolution and save the result as a PNG or TIFF image
+    Poster {
+        /// Image to write, the format is picked by its extension
+        output: PathBuf,
+
+        /// Number of frames to simulate
+        #[arg(long, default_value_t = 600)]
+        frames: usize,
+
+        /// Width of the image in pixels
+        #[arg(long, default_value_t = 7680)]
+        width: u32,
+
+        /// Height of the image in pixels
+        #[arg(long, default_value_t = 4320)]
+        height: u32,
+
+        /// Fixed timestep in seconds
+        #[arg(long, default_value_t = 1.0 / 60.0)]
+        dt: f32,
+
+        /// Print resolution stored in the image
+        #[arg(long, default_value_t = 300.0)]
+        dpi: f32,
+
+        /// Samples per side of each pixel, averaged when rendering
+        #[arg(long, default_value_t = 2)]
+        supersample: u32,
+
+        /// Storage format of the trail map, defaults to the most precise supported format
+        #[arg(long, value_enum)]
+        trail_format: Option<trail::TrailFormat>,
+    },
+    /// Render the golden image test cases and compare them with their goldens
+    Golden {
+        /// Save the renders as the new goldens instead of comparing
+        #[arg(long)]
+        bless: bool,
+
+        /// File listing the cases, with their scenes and goldens next to it
+        #[arg(long, default_value = "tests/golden/cases.ron")]
+        cases: PathBuf,
+
+        /// Directory to write the renders and diff images of failing cases to
+        #[arg(long, default_value = "target/golden")]
+        output: PathBuf,
+    },
+    /// Measure agent and diffusion throughput across agent counts and resolutions
+    Bench {
+        /// Agent counts to measure, separated by commas
+        #[arg(long, value_delimiter = ',', default_values_t = bench::Options::default().agents)]
+        agents: Vec<u32>,
+
+        /// Domain sizes to measure, such as 1920x1080, separated by commas
+        #[arg(long, value_delimiter = ',', value_parser = parse_resolution, default_values = ["1280x720", "1920x1080", "3840x2160"])]
+        resolutions: Vec<glam::UVec2>,
+
+        /// Frames measured per case
+        #[arg(long, default_value_t = bench::Options::default().frames)]
+        frames: usize,
+
+        /// Frames run before measuring each case
+        #[arg(long, default_value_t = bench::Options::default().warmup)]
+        warmup: usize,
+
+        /// Storage format of the trail map, defaults to the most precise supported format
+        #[arg(long, value_enum)]
+        trail_format: Option<trail::TrailFormat>,
+
+        /// Write the results to a JSON file
+        #[arg(long)]
+        output: Option<PathBuf>,
+
+        /// Compare with the results of an earlier run, written with --output
+        #[arg(long)]
+        baseline: Option<PathBuf>,
+
+        /// Percentage by which throughput may drop below the baseline before failing
+        #[arg(long, default_value_t = bench::DEFAULT_THRESHOLD)]
+        threshold: f32,
+    },
+}
+
+async fn run(
+    scene: Option<PathBuf>,
+    adapter: AdapterOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new()
         .with_title("Blossim")
@@ -19,38 +165,33 @@ async fn run() -> Result<(), String> {
             width: 960,
             height: 540,
         })
-        .build(&event_loop)
-        .map_err(|e| e.to_string())?;
+        .build(&event_loop)?;
 
-    let mut state = State::new(&window).await;
+    let mut state = State::new(&window, scene, &adapter).await?;
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::WindowEvent {
             ref event,
             window_id,
-        } if window_id == window.id() => {
-            if !state.input(event) {
-                match event {
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
+        } if window_id == window.id() && !state.input(event) => match event {
+            WindowEvent::CloseRequested
+            | WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(VirtualKeyCode::Escape),
                         ..
-                    } => *control_flow = ControlFlow::Exit,
-                    WindowEvent::Resized(physical_size) => {
-                        state.resize(*physical_size);
-                    }
-                    WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        state.resize(**new_inner_size);
-                    }
-                    _ => {}
-                }
+                    },
+                ..
+            } => *control_flow = ControlFlow::Exit,
+            WindowEvent::Resized(physical_size) => {
+                state.resize(*physical_size);
             }
-        }
+            WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                state.resize(**new_inner_size);
+            }
+            _ => {}
+        },
         Event::RedrawRequested(window_id) if window_id == window.id() => {
             state.update();
             match state.render(&window) {
@@ -70,13 +211,198 @@ async fn run() -> Result<(), String> {
     });
 }
 
+/// Runs the golden image tests, printing the outcome of each case.
+async fn run_golden(options: golden::Options) -> Result<(), Box<dyn std::error::Error>> {
+    let reports = golden::run(&options).await?;
+    for report in &reports {
+        println!("{}", report);
+    }
+
+    let failed = reports.iter().filter(|r| !r.outcome.passed()).count();
+    if failed > 0 {
+        return Err(golden::Mismatch {
+            failed,
+            total: reports.len(),
+            output: options.output,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Runs the benchmarks and compares them with the baseline, failing when
+/// any got slower than the threshold allows.
+async fn run_bench(
+    options: bench::Options,
+    output: Option<PathBuf>,
+    baseline: Option<PathBuf>,
+    threshold: f32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Load the baseline first so a wrong path doesn't waste a whole run
+    let baseline = baseline.as_deref().map(bench::Report::load).transpose()?;
+
+    let report = bench::run(&options).await?;
+    print!("{}", report);
+
+    if let Some(path) = output {
+        report.save(&path)?;
+    }
+
+    let Some(baseline) = baseline else {
+        return Ok(());
+    };
+
+    if !baseline.comparable(&report) {
+        eprintln!(
+            "The baseline ran {} on {} with {}, timings may not be comparable",
+            baseline.trail_format.name(),
+            baseline.adapter,
+            baseline.clock.name()
+        );
+    }
+
+    println!("Compared with the baseline:");
+    let changes = bench::compare(&baseline, &report);
+    for change in &changes {
+        let marker = if change.regressed(threshold) {
+            "  SLOWER"
+        } else {
+            ""
+        };
+        println!("  {}{}", change, marker);
+    }
+
+    let count = changes.iter().filter(|c| c.regressed(threshold)).count();
+    if count > 0 {
+        return Err(bench::Regression { count, threshold }.into());
+    }
+
+    Ok(())
+}
+
+/// Parses a domain size written as `WIDTHxHEIGHT`.
+fn parse_resolution(s: &str) -> Result<glam::UVec2, String> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, such as 1920x1080, not \"{}\"", s))?;
+    let parse = |n: &str| n.trim().parse::<u32>().map_err(|e| format!("{}: {}", n, e));
+    Ok(glam::uvec2(parse(width)?, parse(height)?))
+}
+
+/// Prints every adapter matching `options`, with the trail formats it supports.
+fn list_adapters(options: &AdapterOptions) {
+    let instance = options.instance();
+    let adapters = options.adapters(&instance);
+    if adapters.is_empty() {
+        println!("No graphics adapters found");
+    }
+
+    for adapter in adapters {
+        println!("{}", adapter::describe(&adapter.get_info()));
+
+        let support = TrailSupport::new(&adapter, adapter::optional_features(&adapter));
+        for format in support.formats() {
+            if let Some(access) = support.access(format) {
+                println!("    {:<20}{}", format.name(), access.name());
+            }
+        }
+    }
+}
+
 fn main() {
     env_logger::init();
-    match pollster::block_on(run()) {
-        Ok(_) => {}
-        Err(e) => {
-            eprintln!("{}", e);
-            std::process::exit(1);
+
+    let args = Args::parse();
+    let adapter = AdapterOptions {
+        backend: args.backend,
+        name: args.adapter,
+    };
+    let result = match args.command {
+        None => pollster::block_on(run(args.scene, adapter)),
+        Some(Command::Adapters) => {
+            list_adapters(&adapter);
+            Ok(())
         }
+        Some(Command::Headless {
+            frames,
+            width,
+            height,
+            dt,
+            trail_format,
+            profile_csv,
+            screenshot,
+        }) => pollster::block_on(headless::run(headless::Options {
+            adapter,
+            frames,
+            width,
+            height,
+            dt,
+            scene: args.scene,
+            trail_format,
+            profile_csv,
+            screenshot,
+        }))
+        .map_err(Into::into),
+        Some(Command::Poster {
+            output,
+            frames,
+            width,
+            height,
+            dt,
+            dpi,
+            supersample,
+            trail_format,
+        }) => pollster::block_on(headless::poster(headless::PosterOptions {
+            adapter,
+            frames,
+            width,
+            height,
+            dt,
+            dpi,
+            supersample,
+            scene: args.scene,
+            trail_format,
+            output,
+        }))
+        .map_err(Into::into),
+        Some(Command::Golden {
+            bless,
+            cases,
+            output,
+        }) => pollster::block_on(run_golden(golden::Options {
+            cases,
+            output,
+            bless,
+            adapter,
+        })),
+        Some(Command::Bench {
+            agents,
+            resolutions,
+            frames,
+            warmup,
+            trail_format,
+            output,
+            baseline,
+            threshold,
+        }) => pollster::block_on(run_bench(
+            bench::Options {
+                adapter,
+                scene: args.scene,
+                trail_format,
+                agents,
+                resolutions,
+                frames,
+                warmup,
+            },
+            output,
+            baseline,
+            threshold,
+        )),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
     }
 }
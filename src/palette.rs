@@ -0,0 +1,354 @@
+//! Gradients that trails and agents are coloured with.
+
+use serde::{Deserialize, Serialize};
+
+use crate::pipeline::bindings::Resource;
+
+/// Number of colours the gradient is sampled into on the GPU.
+const LUT_SIZE: u32 = 256;
+
+/// Colours trail intensity or agent properties are mapped through, from 0 to 1.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Gradient {
+    Viridis,
+    Magma,
+    Inferno,
+    Plasma,
+    /// Linear interpolation between user defined stops
+    Custom(Vec<Stop>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stop {
+    /// Where along the gradient the colour is reached, from 0 to 1
+    pub position: f32,
+    /// sRGB colour
+    pub color: [u8; 3],
+}
+
+/// GPU copy of the scene gradient, sampled into a one pixel high texture
+/// that is rewritten whenever the gradient changes.
+pub(crate) struct Palette {
+    current: Gradient,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+// Polynomial fits of the matplotlib colormaps, see https://www.shadertoy.com/view/WlfXRN
+const VIRIDIS: [[f32; 3]; 7] = [
+    [0.277_727_33, 0.005_407_344_5, 0.334_099_8],
+    [0.105_093_04, 1.404_613_5, 1.384_590_2],
+    [-0.330_861_83, 0.214_847_56, 0.095_095_16],
+    [-4.634_230_6, -5.799_101, -19.332_441],
+    [6.228_27, 14.179_933, 56.690_55],
+    [4.776_385, -13.745_145, -65.353_03],
+    [-5.435_456, 4.645_852_6, 26.312_435],
+];
+
+const MAGMA: [[f32; 3]; 7] = [
+    [-0.002_136_485, -0.000_749_655_05, -0.005_386_128],
+    [0.251_660_54, 0.677_523_2, 2.494_026_6],
+    [8.353_717, -3.577_719_4, 0.314_467_9],
+    [-27.668_733, 14.264_731, -13.649_213],
+    [52.176_14, -27.943_607, 12.944_169],
+    [-50.768_524, 29.046_583, 4.234_153],
+    [18.655_705, -11.489_774, -5.601_961_4],
+];
+
+const INFERNO: [[f32; 3]; 7] = [
+    [0.000_218_940_37, 0.001_651_004_6, -0.019_480_899],
+    [0.106_513_42, 0.563_956_4, 3.932_712_3],
+    [11.602_493, -3.972_854, -15.942_394],
+    [-41.703_995, 17.436_4, 44.354_145],
+    [77.162_94, -33.402_36, -81.807_31],
+    [-71.319_43, 32.626_064, 73.209_52],
+    [25.131_126, -12.242_669, -23.070_325],
+];
+
+const PLASMA: [[f32; 3]; 7] = [
+    [0.058_732_344, 0.023_336_709, 0.543_340_2],
+    [2.176_514_6, 0.238_383_42, 0.753_960_4],
+    [-2.689_460_5, -7.455_851, 3.110_8],
+    [6.130_348, 42.346_188, -28.518_854],
+    [-11.107_436, -82.666_31, 60.139_847],
+    [10.023_065, 71.413_62, -54.072_186],
+    [-3.658_713_8, -22.931_534, 18.191_908],
+];
+
+impl Default for Gradient {
+    /// Green to cyan, the hue ramp agents were coloured with before gradients existed.
+    fn default() -> Self {
+        Gradient::Custom(vec![
+            Stop {
+                position: 0.0,
+                color: [111, 231, 0],
+            },
+            Stop {
+                position: 1.0 / 6.0,
+                color: [0, 231, 0],
+            },
+            Stop {
+                position: 1.0,
+                color: [0, 231, 231],
+            },
+        ])
+    }
+}
+
+impl Gradient {
+    /// Built-in colormaps followed by an empty custom gradient.
+    pub const ALL: [Gradient; 5] = [
+        Gradient::Viridis,
+        Gradient::Magma,
+        Gradient::Inferno,
+        Gradient::Plasma,
+        Gradient::Custom(Vec::new()),
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Gradient::Viridis => "Viridis",
+            Gradient::Magma => "Magma",
+            Gradient::Inferno => "Inferno",
+            Gradient::Plasma => "Plasma",
+            Gradient::Custom(_) => "Custom",
+        }
+    }
+
+    /// sRGB colour at `t`, clamped to the ends of the gradient.
+    pub fn sample(&self, t: f32) -> [u8; 3] {
+        self.sampler()(t)
+    }
+
+    /// Function sampling the gradient like [`Gradient::sample`], for taking
+    /// many samples with the custom stops sorted only once.
+    pub fn sampler(&self) -> impl Fn(f32) -> [u8; 3] {
+        let (coefficients, mut sorted) = match self {
+            Gradient::Viridis => (Some(&VIRIDIS), Vec::new()),
+            Gradient::Magma => (Some(&MAGMA), Vec::new()),
+            Gradient::Inferno => (Some(&INFERNO), Vec::new()),
+            Gradient::Plasma => (Some(&PLASMA), Vec::new()),
+            Gradient::Custom(stops) => (None, stops.clone()),
+        };
+        sorted.sort_by(|a, b| a.position.total_cmp(&b.position));
+
+        move |t| {
+            let t = t.clamp(0.0, 1.0);
+            match coefficients {
+                Some(coefficients) => to_bytes(polynomial(coefficients, t)),
+                None => sample_stops(&sorted, t),
+            }
+        }
+    }
+
+    /// Custom gradient approximating this one with `count` evenly spaced stops.
+    pub fn to_custom(&self, count: usize) -> Gradient {
+        if let Gradient::Custom(_) = self {
+            return self.clone();
+        }
+
+        let sample = self.sampler();
+        let stops = (0..count)
+            .map(|i| {
+                let position = i as f32 / (count - 1).max(1) as f32;
+                Stop {
+                    position,
+                    color: sample(position),
+                }
+            })
+            .collect();
+
+        Gradient::Custom(stops)
+    }
+
+    /// Custom gradient fading from this one to `other` by `t`, in linear
+    /// space, with `count` evenly spaced stops.
+    pub fn blend(&self, other: &Gradient, t: f32, count: usize) -> Gradient {
+        let (sample_a, sample_b) = (self.sampler(), other.sampler());
+        let stops = (0..count)
+            .map(|i| {
+                let position = i as f32 / (count - 1).max(1) as f32;
+                let a = to_linear(sample_a(position));
+                let b = to_linear(sample_b(position));
+                Stop {
+                    position,
+                    color: to_srgb(a.lerp(b, t)),
+                }
+            })
+            .collect();
+
+        Gradient::Custom(stops)
+    }
+
+    /// Pixels of the lookup texture, in sRGB with opaque alpha.
+    fn lut(&self) -> Vec<u8> {
+        let sample = self.sampler();
+        (0..LUT_SIZE)
+            .flat_map(|i| {
+                let [r, g, b] = sample(i as f32 / (LUT_SIZE - 1) as f32);
+                [r, g, b, u8::MAX]
+            })
+            .collect()
+    }
+}
+
+impl Palette {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, gradient: &Gradient) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Gradient"),
+            size: wgpu::Extent3d {
+                width: LUT_SIZE,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            // Stops are picked in sRGB, but interpolated and deposited in linear space
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        let this = Self {
+            current: gradient.clone(),
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            texture,
+        };
+
+        this.write(queue);
+        this
+    }
+
+    /// Uploads the gradient again if it differs from the one on the GPU.
+    pub fn update(&mut self, queue: &wgpu::Queue, gradient: &Gradient) {
+        if *gradient != self.current {
+            self.current = gradient.clone();
+            self.write(queue);
+        }
+    }
+
+    /// Resource of the gradient texture, shared by the simulation and render passes.
+    pub(crate) fn resource(&self) -> Resource<'_> {
+        (
+            "gradient_tex",
+            wgpu::BindingResource::TextureView(&self.view),
+        )
+    }
+
+    fn write(&self, queue: &wgpu::Queue) {
+        queue.write_texture(
+            self.texture.as_image_copy(),
+            &self.current.lut(),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(LUT_SIZE * 4),
+                rows_per_image: None,
+            },
+            self.texture.size(),
+        );
+    }
+}
+
+/// Converts an sRGB colour to linear values from 0 to 1.
+pub fn to_linear(color: [u8; 3]) -> glam::Vec3 {
+    glam::Vec3::from(color.map(|c| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    }))
+}
+
+/// Converts a linear colour to sRGB bytes, clamping values outside 0 to 1.
+pub fn to_srgb(color: glam::Vec3) -> [u8; 3] {
+    to_bytes(color.to_array().map(|c| {
+        if c <= 0.003_130_8 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    }))
+}
+
+fn to_bytes(color: [f32; 3]) -> [u8; 3] {
+    color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+fn polynomial(coefficients: &[[f32; 3]; 7], t: f32) -> [f32; 3] {
+    let color = coefficients
+        .iter()
+        .rev()
+        .fold(glam::Vec3::ZERO, |sum, c| sum * t + glam::Vec3::from(*c));
+
+    color.to_array()
+}
+
+/// Interpolates between the stops around `t` in linear space, `sorted` by position.
+fn sample_stops(sorted: &[Stop], t: f32) -> [u8; 3] {
+    let after = sorted.iter().position(|stop| stop.position >= t);
+    match after {
+        None => sorted.last().map_or([0; 3], |stop| stop.color),
+        Some(0) => sorted[0].color,
+        Some(i) => {
+            let (a, b) = (sorted[i - 1], sorted[i]);
+            let f = (t - a.position) / (b.position - a.position).max(f32::EPSILON);
+            to_srgb(to_linear(a.color).lerp(to_linear(b.color), f))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stop(position: f32, color: [u8; 3]) -> Stop {
+        Stop { position, color }
+    }
+
+    #[test]
+    fn custom_gradients_interpolate_between_their_stops() {
+        let gradient = Gradient::Custom(vec![stop(1.0, [255; 3]), stop(0.25, [0; 3])]);
+        assert_eq!(gradient.sample(-1.0), [0; 3]);
+        assert_eq!(gradient.sample(0.25), [0; 3]);
+        assert_eq!(gradient.sample(1.0), [255; 3]);
+        assert_eq!(gradient.sample(2.0), [255; 3]);
+        // Halfway in linear space is brighter than halfway in sRGB
+        assert_eq!(gradient.sample(0.625), to_srgb(glam::Vec3::splat(0.5)));
+        assert_eq!(Gradient::Custom(Vec::new()).sample(0.5), [0; 3]);
+    }
+
+    #[test]
+    fn custom_copies_match_the_colormap_at_their_stops() {
+        let Gradient::Custom(stops) = Gradient::Magma.to_custom(5) else {
+            panic!("to_custom should return a custom gradient");
+        };
+
+        let positions: Vec<f32> = stops.iter().map(|stop| stop.position).collect();
+        assert_eq!(positions, [0.0, 0.25, 0.5, 0.75, 1.0]);
+        for stop in stops {
+            assert_eq!(stop.color, Gradient::Magma.sample(stop.position));
+        }
+
+        let custom = Gradient::default();
+        assert_eq!(custom.to_custom(5), custom);
+    }
+
+    #[test]
+    fn blends_fade_from_one_gradient_to_the_other() {
+        let (a, b) = (Gradient::Viridis, Gradient::Plasma);
+        assert_eq!(a.blend(&b, 0.0, 9), a.to_custom(9));
+        assert_eq!(a.blend(&b, 1.0, 9), b.to_custom(9));
+
+        let black = Gradient::Custom(vec![stop(0.0, [0; 3])]);
+        let white = Gradient::Custom(vec![stop(0.0, [255; 3])]);
+        let Gradient::Custom(stops) = black.blend(&white, 0.5, 3) else {
+            panic!("blend should return a custom gradient");
+        };
+        assert!(stops
+            .iter()
+            .all(|stop| stop.color == to_srgb(glam::Vec3::splat(0.5))));
+    }
+}
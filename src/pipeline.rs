@@ -1,2 +1,4 @@
+pub mod bindings;
 pub mod compute;
 pub mod render;
+pub mod sprite;
@@ -0,0 +1,423 @@
+//! Bind group layouts reflected from the naga module of a shader, and bind
+//! groups of resources given by the names of the variables they bind to.
+
+use naga::{AddressSpace, ImageClass, ImageDimension, ScalarKind, StorageAccess, TypeInner};
+
+use crate::error::{Error, Result};
+
+/// A resource and the name of the shader variable it is bound to.
+pub type Resource<'a> = (&'static str, wgpu::BindingResource<'a>);
+
+/// A variable of a shader bound in group 0.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Binding {
+    pub name: String,
+    pub entry: wgpu::BindGroupLayoutEntry,
+}
+
+/// The bindings of a shader, with the layout made of them.
+pub struct Bindings {
+    /// Shader the bindings were reflected from
+    shader: String,
+    bindings: Vec<Binding>,
+    pub layout: wgpu::BindGroupLayout,
+}
+
+impl Bindings {
+    /// Reflects the bindings of `module`, validated as `info`, and creates
+    /// their layout.
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &str,
+        module: &naga::Module,
+        info: &naga::valid::ModuleInfo,
+    ) -> Result<Self> {
+        let bindings = reflect(shader, module, info)?;
+        let entries: Vec<_> = bindings.iter().map(|binding| binding.entry).collect();
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(shader),
+            entries: &entries,
+        });
+
+        Ok(Self {
+            shader: shader.to_owned(),
+            bindings,
+            layout,
+        })
+    }
+
+    /// Bind group of `resources`, which must bind every variable of the
+    /// shader and nothing else. A mismatch is an [`Error::Binding`] naming
+    /// the binding.
+    pub fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        resources: Vec<Resource>,
+    ) -> Result<wgpu::BindGroup> {
+        let entries: Vec<_> = resolve(&self.shader, &self.bindings, resources)?
+            .into_iter()
+            .map(|(binding, resource)| wgpu::BindGroupEntry { binding, resource })
+            .collect();
+
+        Ok(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&self.shader),
+            layout: &self.layout,
+            entries: &entries,
+        }))
+    }
+}
+
+/// Bindings of the global variables of `module`, visible to the stages of
+/// the entry points that use them.
+pub fn reflect(
+    shader: &str,
+    module: &naga::Module,
+    info: &naga::valid::ModuleInfo,
+) -> Result<Vec<Binding>> {
+    let all_stages = module
+        .entry_points
+        .iter()
+        .fold(wgpu::ShaderStages::NONE, |stages, entry_point| {
+            stages | stage(entry_point.stage)
+        });
+
+    let mut bindings = Vec::new();
+    for (handle, variable) in module.global_variables.iter() {
+        let Some(binding) = &variable.binding else {
+            continue;
+        };
+        let name = variable.name.clone().unwrap_or_default();
+        let error = |problem: String| Error::Binding {
+            shader: shader.to_owned(),
+            name: name.clone(),
+            problem,
+        };
+        if binding.group != 0 {
+            return Err(error(format!(
+                "is in group {}, and only group 0 is bound",
+                binding.group
+            )));
+        }
+
+        let users = module
+            .entry_points
+            .iter()
+            .enumerate()
+            .filter(|&(index, _)| !info.get_entry_point(index)[handle].is_empty());
+        let visibility = users.fold(wgpu::ShaderStages::NONE, |stages, (_, entry_point)| {
+            stages | stage(entry_point.stage)
+        });
+        // Filterable textures can also be bound where filtering isn't needed
+        let sampled = (0..module.entry_points.len()).any(|index| {
+            let sampling = &info.get_entry_point(index).sampling_set;
+            sampling.iter().any(|key| key.image == handle)
+        });
+
+        let ty = binding_type(variable.space, &module.types[variable.ty].inner, sampled)
+            .map_err(|problem| error(problem.to_owned()))?;
+        bindings.push(Binding {
+            name,
+            entry: wgpu::BindGroupLayoutEntry {
+                binding: binding.binding,
+                visibility: if visibility.is_empty() {
+                    all_stages
+                } else {
+                    visibility
+                },
+                ty,
+                count: None,
+            },
+        });
+    }
+
+    Ok(bindings)
+}
+
+/// Binding numbers of the `resources` for `bindings`, given by name.
+fn resolve<R>(
+    shader: &str,
+    bindings: &[Binding],
+    resources: Vec<(&str, R)>,
+) -> Result<Vec<(u32, R)>> {
+    let error = |name: &str, problem: &str| Error::Binding {
+        shader: shader.to_owned(),
+        name: name.to_owned(),
+        problem: problem.to_owned(),
+    };
+
+    if let Some(missing) = bindings
+        .iter()
+        .find(|binding| resources.iter().all(|(name, _)| *name != binding.name))
+    {
+        return Err(error(&missing.name, "was given no resource"));
+    }
+
+    resources
+        .into_iter()
+        .map(|(name, resource)| {
+            let binding = bindings
+                .iter()
+                .find(|binding| binding.name == name)
+                .ok_or_else(|| error(name, "is not declared by the shader"))?;
+            Ok((binding.entry.binding, resource))
+        })
+        .collect()
+}
+
+fn stage(stage: naga::ShaderStage) -> wgpu::ShaderStages {
+    match stage {
+        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+    }
+}
+
+/// Binding type of a variable of type `ty` in `space`, `sampled` when a
+/// sampler is used with it.
+fn binding_type(
+    space: AddressSpace,
+    ty: &TypeInner,
+    sampled: bool,
+) -> Result<wgpu::BindingType, &'static str> {
+    let buffer = |ty| {
+        Ok(wgpu::BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        })
+    };
+
+    match (space, ty) {
+        (AddressSpace::Uniform, _) => buffer(wgpu::BufferBindingType::Uniform),
+        (AddressSpace::Storage { access }, _) => buffer(wgpu::BufferBindingType::Storage {
+            read_only: !access.contains(StorageAccess::STORE),
+        }),
+        (AddressSpace::Handle, TypeInner::Sampler { comparison }) => {
+            Ok(wgpu::BindingType::Sampler(if *comparison {
+                wgpu::SamplerBindingType::Comparison
+            } else {
+                wgpu::SamplerBindingType::Filtering
+            }))
+        }
+        (
+            AddressSpace::Handle,
+            TypeInner::Image {
+                dim,
+                arrayed,
+                class,
+            },
+        ) => {
+            let view_dimension = match (dim, arrayed) {
+                (ImageDimension::D1, false) => wgpu::TextureViewDimension::D1,
+                (ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                (ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                (ImageDimension::D3, false) => wgpu::TextureViewDimension::D3,
+                (ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                (ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+                (_, true) => return Err("is an array of a dimension that can't be arrayed"),
+            };
+
+            match *class {
+                ImageClass::Sampled { kind, multi } => Ok(wgpu::BindingType::Texture {
+                    sample_type: match kind {
+                        ScalarKind::Float => wgpu::TextureSampleType::Float {
+                            filterable: sampled,
+                        },
+                        ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                        ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                        ScalarKind::Bool => return Err("is a texture of booleans"),
+                    },
+                    view_dimension,
+                    multisampled: multi,
+                }),
+                ImageClass::Depth { multi } => Ok(wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension,
+                    multisampled: multi,
+                }),
+                ImageClass::Storage { format, access } => Ok(wgpu::BindingType::StorageTexture {
+                    access: if access == StorageAccess::STORE {
+                        wgpu::StorageTextureAccess::WriteOnly
+                    } else if access == StorageAccess::LOAD {
+                        wgpu::StorageTextureAccess::ReadOnly
+                    } else {
+                        wgpu::StorageTextureAccess::ReadWrite
+                    },
+                    format: texture_format(format),
+                    view_dimension,
+                }),
+            }
+        }
+        (AddressSpace::Handle, TypeInner::BindingArray { .. }) => {
+            Err("is a binding array, which Blossim doesn't bind")
+        }
+        _ => Err("has a type that can't be bound"),
+    }
+}
+
+fn texture_format(format: naga::StorageFormat) -> wgpu::TextureFormat {
+    use naga::StorageFormat as S;
+    use wgpu::TextureFormat as T;
+
+    match format {
+        S::R8Unorm => T::R8Unorm,
+        S::R8Snorm => T::R8Snorm,
+        S::R8Uint => T::R8Uint,
+        S::R8Sint => T::R8Sint,
+        S::R16Uint => T::R16Uint,
+        S::R16Sint => T::R16Sint,
+        S::R16Float => T::R16Float,
+        S::Rg8Unorm => T::Rg8Unorm,
+        S::Rg8Snorm => T::Rg8Snorm,
+        S::Rg8Uint => T::Rg8Uint,
+        S::Rg8Sint => T::Rg8Sint,
+        S::R32Uint => T::R32Uint,
+        S::R32Sint => T::R32Sint,
+        S::R32Float => T::R32Float,
+        S::Rg16Uint => T::Rg16Uint,
+        S::Rg16Sint => T::Rg16Sint,
+        S::Rg16Float => T::Rg16Float,
+        S::Rgba8Unorm => T::Rgba8Unorm,
+        S::Rgba8Snorm => T::Rgba8Snorm,
+        S::Rgba8Uint => T::Rgba8Uint,
+        S::Rgba8Sint => T::Rgba8Sint,
+        S::Rgb10a2Unorm => T::Rgb10a2Unorm,
+        S::Rg11b10Float => T::Rg11b10Float,
+        S::Rg32Uint => T::Rg32Uint,
+        S::Rg32Sint => T::Rg32Sint,
+        S::Rg32Float => T::Rg32Float,
+        S::Rgba16Uint => T::Rgba16Uint,
+        S::Rgba16Sint => T::Rgba16Sint,
+        S::Rgba16Float => T::Rgba16Float,
+        S::Rgba32Uint => T::Rgba32Uint,
+        S::Rgba32Sint => T::Rgba32Sint,
+        S::Rgba32Float => T::Rgba32Float,
+        S::R16Unorm => T::R16Unorm,
+        S::R16Snorm => T::R16Snorm,
+        S::Rg16Unorm => T::Rg16Unorm,
+        S::Rg16Snorm => T::Rg16Snorm,
+        S::Rgba16Unorm => T::Rgba16Unorm,
+        S::Rgba16Snorm => T::Rgba16Snorm,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::Shader;
+
+    fn bindings(source: &str) -> Vec<Binding> {
+        let shader = Shader::preprocess("main.wgsl", source, &[], &|_| None).unwrap();
+        let (module, info) = shader.validate().unwrap();
+        reflect("main.wgsl", &module, &info).unwrap()
+    }
+
+    #[test]
+    fn bindings_are_reflected_with_the_stages_using_them() {
+        let bindings = bindings(
+            "@group(0) @binding(0) var<uniform> scale: vec4<f32>;
+             @group(0) @binding(3) var<storage, read> values: array<f32>;
+             @group(0) @binding(4) var image: texture_2d<f32>;
+             @group(0) @binding(5) var image_sampler: sampler;
+             @group(0) @binding(7) var data: texture_2d<f32>;
+
+             @vertex fn vs_main() -> @builtin(position) vec4<f32> {
+                 return scale * values[0];
+             }
+
+             @fragment fn fs_main() -> @location(0) vec4<f32> {
+                 return textureSample(image, image_sampler, vec2<f32>(0.5)) * scale;
+             }",
+        );
+
+        let names: Vec<_> = bindings.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["scale", "values", "image", "image_sampler", "data"]);
+
+        let entry = |name| bindings.iter().find(|b| b.name == name).unwrap().entry;
+        assert_eq!(
+            entry("scale").visibility,
+            wgpu::ShaderStages::VERTEX_FRAGMENT
+        );
+        assert_eq!(entry("values").binding, 3);
+        assert_eq!(
+            entry("values").ty,
+            wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            }
+        );
+        assert_eq!(entry("values").visibility, wgpu::ShaderStages::VERTEX);
+        assert!(matches!(
+            entry("image").ty,
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                ..
+            }
+        ));
+        // Unused by every entry point
+        assert_eq!(
+            entry("data").visibility,
+            wgpu::ShaderStages::VERTEX_FRAGMENT
+        );
+        assert!(matches!(
+            entry("data").ty,
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn storage_textures_keep_their_format_and_access() {
+        let bindings = bindings(
+            "@group(0) @binding(2) var tex: texture_storage_2d<rg16float, write>;
+
+             @compute @workgroup_size(1) fn main() {
+                 textureStore(tex, vec2<i32>(0), vec4<f32>(1.0));
+             }",
+        );
+
+        assert_eq!(
+            bindings[0].entry.ty,
+            wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: wgpu::TextureFormat::Rg16Float,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            }
+        );
+        assert_eq!(bindings[0].entry.visibility, wgpu::ShaderStages::COMPUTE);
+    }
+
+    #[test]
+    fn resources_are_bound_by_name() {
+        let bindings = bindings(
+            "@group(0) @binding(1) var<storage, read_write> a: array<u32>;
+             @group(0) @binding(6) var<uniform> b: vec4<f32>;
+
+             @compute @workgroup_size(1) fn main() {
+                 a[0] = u32(b.x);
+             }",
+        );
+        let resolve = |resources| resolve("main.wgsl", &bindings, resources);
+
+        assert_eq!(
+            resolve(vec![("b", 'b'), ("a", 'a')]).unwrap(),
+            [(6, 'b'), (1, 'a')]
+        );
+
+        let problem = |error| match error {
+            Error::Binding { name, problem, .. } => Some((name, problem)),
+            _ => None,
+        };
+        assert_eq!(
+            problem(resolve(vec![("a", 'a')]).unwrap_err()),
+            Some(("b".to_owned(), "was given no resource".to_owned()))
+        );
+        assert_eq!(
+            problem(resolve(vec![("a", 'a'), ("b", 'b'), ("c", 'c')]).unwrap_err()),
+            Some(("c".to_owned(), "is not declared by the shader".to_owned()))
+        );
+    }
+}
@@ -1,123 +1,148 @@
+use super::bindings::Bindings;
+use crate::{
+    error::Result,
+    rules::Rules,
+    shader::{self, Defines},
+    trail::{TrailAccess, TrailFormat},
+};
+
 pub struct ComputePipeline {
     pub pipeline: wgpu::ComputePipeline,
-    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bindings: Bindings,
 }
 
 impl ComputePipeline {
-    pub fn diffuse(device: &wgpu::Device) -> Self {
-        let module = {
-            let descriptor = wgpu::include_wgsl!("../shader/diffuse.wgsl");
-            device.create_shader_module(descriptor)
-        };
-
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Diffuse"),
-            entries: &[
-                // Globals
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // Render texture
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        format: wgpu::TextureFormat::Rgba32Float,
-                        access: wgpu::StorageTextureAccess::ReadWrite,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                    },
-                    count: None,
-                },
-            ],
-        });
+    pub fn diffuse(
+        device: &wgpu::Device,
+        trail_format: TrailFormat,
+        access: TrailAccess,
+    ) -> Result<Self> {
+        Self::new(
+            device,
+            "diffuse.wgsl",
+            &trail_defines(trail_format, access),
+            "main",
+        )
+    }
 
-        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
-            bind_group_layouts: &[&bind_group_layout],
-            push_constant_ranges: &[],
-        });
+    /// Moves and deposits agents, steering them by `rules` when given.
+    pub fn simulation(
+        device: &wgpu::Device,
+        trail_format: TrailFormat,
+        access: TrailAccess,
+        rules: Option<&Rules>,
+    ) -> Result<Self> {
+        let defines = trail_defines(trail_format, access);
+        match rules {
+            Some(rules) => Ok(Self::from_module(
+                device,
+                rules.module(device, &defines)?,
+                "main",
+            )),
+            None => Self::new(device, "simulation.wgsl", &defines, "main"),
+        }
+    }
 
-        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: None,
-            layout: Some(&pipeline_layout),
-            module: &module,
-            entry_point: "main",
-        });
+    /// Paints into or erases from the trail map.
+    pub fn paint(
+        device: &wgpu::Device,
+        trail_format: TrailFormat,
+        access: TrailAccess,
+    ) -> Result<Self> {
+        Self::new(
+            device,
+            "brush.wgsl",
+            &trail_defines(trail_format, access),
+            "paint",
+        )
+    }
 
-        Self {
-            pipeline,
-            bind_group_layout,
-        }
+    /// Pushes agents away from or pulls them towards the brush.
+    pub fn push(
+        device: &wgpu::Device,
+        trail_format: TrailFormat,
+        access: TrailAccess,
+    ) -> Result<Self> {
+        Self::new(
+            device,
+            "brush.wgsl",
+            &trail_defines(trail_format, access),
+            "push",
+        )
     }
 
-    pub fn simulation(device: &wgpu::Device) -> Self {
-        let module = {
-            let descriptor = wgpu::include_wgsl!("../shader/simulation.wgsl");
-            device.create_shader_module(descriptor)
-        };
-
-        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            label: Some("Simulation"),
-            entries: &[
-                // Globals
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // Agents
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                // Render texture
-                wgpu::BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::COMPUTE,
-                    ty: wgpu::BindingType::StorageTexture {
-                        format: wgpu::TextureFormat::Rgba32Float,
-                        access: wgpu::StorageTextureAccess::ReadWrite,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                    },
-                    count: None,
-                },
-            ],
-        });
+    /// Sorts the agents buffer into lists of live and free slots.
+    pub fn compact(device: &wgpu::Device) -> Result<Self> {
+        Self::new(device, "lifecycle.wgsl", &[], "compact")
+    }
+
+    /// Writes the indirect dispatch arguments of the simulation pass.
+    pub fn prepare_dispatch(device: &wgpu::Device) -> Result<Self> {
+        Self::new(device, "lifecycle.wgsl", &[], "prepare_dispatch")
+    }
+
+    /// Removes live agents past a given count.
+    pub fn remove(device: &wgpu::Device) -> Result<Self> {
+        Self::new(device, "resize.wgsl", &[], "remove")
+    }
+
+    /// Spawns agents into free slots.
+    pub fn spawn(device: &wgpu::Device) -> Result<Self> {
+        Self::new(device, "resize.wgsl", &[], "spawn")
+    }
+
+    /// Pipeline of `entry_point` in the built-in shader at `path`, laid out
+    /// by the bindings reflected from the shader.
+    fn new(
+        device: &wgpu::Device,
+        path: &str,
+        defines: &Defines,
+        entry_point: &str,
+    ) -> Result<Self> {
+        let module = shader::module(device, path, defines)?;
+        Ok(Self::from_module(device, module, entry_point))
+    }
+
+    fn from_module(device: &wgpu::Device, module: shader::Module, entry_point: &str) -> Self {
+        let shader::Module { module, bindings } = module;
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: None,
-            bind_group_layouts: &[&bind_group_layout],
+            bind_group_layouts: &[&bindings.layout],
             push_constant_ranges: &[],
         });
 
         let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: None,
+            label: Some(entry_point),
             layout: Some(&pipeline_layout),
             module: &module,
-            entry_point: "main",
+            entry_point,
         });
 
-        Self {
-            pipeline,
-            bind_group_layout,
-        }
+        Self { pipeline, bindings }
+    }
+}
+
+/// Dispatches enough workgroups of `workgroup_size` for `invocations`, split
+/// across two dimensions once there are too many workgroups for one.
+pub fn dispatch_linear(
+    compute_pass: &mut wgpu::ComputePass,
+    device: &wgpu::Device,
+    invocations: u32,
+    workgroup_size: u32,
+) {
+    let work_groups = invocations.div_ceil(workgroup_size);
+    let max_work_groups = device.limits().max_compute_workgroups_per_dimension;
+    let x = work_groups.min(max_work_groups);
+    if x > 0 {
+        compute_pass.dispatch_workgroups(x, work_groups.div_ceil(x), 1);
     }
 }
+
+/// Preprocessor definitions of the trail kernels for `trail_format` and `access`.
+fn trail_defines(
+    trail_format: TrailFormat,
+    access: TrailAccess,
+) -> Vec<(&'static str, &'static str)> {
+    [&trail_format.defines()[..], &[access.define()]].concat()
+}
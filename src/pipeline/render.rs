@@ -1,36 +1,24 @@
-use crate::storage;
+use super::bindings::Bindings;
+use crate::{error::Result, shader, storage, trail::TrailFormat};
 
 pub struct RenderPipeline {
     pub pipeline: wgpu::RenderPipeline,
-    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bindings: Bindings,
 }
 
 impl RenderPipeline {
-    pub fn new(device: &wgpu::Device, target_format: wgpu::TextureFormat) -> Self {
-        let module = {
-            let descriptor = wgpu::include_wgsl!("../shader/render.wgsl");
-            device.create_shader_module(descriptor)
-        };
-
-        let bind_group_layout: wgpu::BindGroupLayout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Render"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::StorageTexture {
-                        format: wgpu::TextureFormat::Rgba32Float,
-                        access: wgpu::StorageTextureAccess::ReadOnly,
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                    },
-                    count: None,
-                }],
-            });
+    pub fn new(
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+        trail_format: TrailFormat,
+    ) -> Result<Self> {
+        let shader::Module { module, bindings } =
+            shader::module(device, "render.wgsl", &trail_format.defines())?;
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: &[&bind_group_layout],
+                bind_group_layouts: &[&bindings.layout],
                 push_constant_ranges: &[],
             });
 
@@ -69,9 +57,9 @@ impl RenderPipeline {
             multiview: None,
         });
 
-        Self {
+        Ok(Self {
             pipeline: render_pipeline,
-            bind_group_layout,
-        }
+            bindings,
+        })
     }
 }
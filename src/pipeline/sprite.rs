@@ -0,0 +1,67 @@
+use super::bindings::Bindings;
+use crate::{error::Result, shader, storage};
+
+/// Draws agents straight from the agents buffer, one instance per agent.
+pub struct SpritePipeline {
+    pub pipeline: wgpu::RenderPipeline,
+    pub bindings: Bindings,
+}
+
+impl SpritePipeline {
+    pub fn new(device: &wgpu::Device, target_format: wgpu::TextureFormat) -> Result<Self> {
+        let shader::Module { module, bindings } = shader::module(device, "sprite.wgsl", &[])?;
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sprite Pipeline Layout"),
+            bind_group_layouts: &[&bindings.layout],
+            push_constant_ranges: &[],
+        });
+
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sprite Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &module,
+                entry_point: "vs_main",
+                buffers: &[storage::Agent::instance_desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: target_format,
+                    blend: Some(wgpu::BlendState {
+                        color: additive,
+                        alpha: additive,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // Quads turn with their agents, so either side may face the camera
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Ok(Self { pipeline, bindings })
+    }
+}
@@ -0,0 +1,382 @@
+use encase::ShaderSize;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    error::Result,
+    pipeline::{
+        bindings::Resource,
+        compute::{self, ComputePipeline},
+    },
+    readback::Readback,
+    storage::{self, Agent, Storable},
+};
+
+/// Agents handled by each workgroup of the compaction and resize passes, must
+/// match `lifecycle.wgsl` and `resize.wgsl`.
+const SLOT_WORKGROUP_SIZE: u32 = 64;
+
+/// Tracks which slots of the agents buffer hold live agents, so the
+/// simulation pass only runs for those and newborn agents can claim the rest.
+pub struct Population {
+    compact: ComputePipeline,
+    prepare_dispatch: ComputePipeline,
+    remove: ComputePipeline,
+    spawn: ComputePipeline,
+    /// Live and free slot counts
+    counters: wgpu::Buffer,
+    live_indices: wgpu::Buffer,
+    free_indices: wgpu::Buffer,
+    /// Indirect dispatch arguments of the simulation pass
+    dispatch: wgpu::Buffer,
+    capacity: u32,
+    live_readback: Readback,
+    live: Option<u32>,
+    /// Agents to place in free slots at the next update
+    spawned: Vec<Agent>,
+    /// Live agents to keep at the next update, removing the others
+    keep: Option<u32>,
+}
+
+impl Population {
+    pub fn new(device: &wgpu::Device, capacity: u32) -> Result<Self> {
+        Ok(Self {
+            compact: ComputePipeline::compact(device)?,
+            prepare_dispatch: ComputePipeline::prepare_dispatch(device)?,
+            remove: ComputePipeline::remove(device)?,
+            spawn: ComputePipeline::spawn(device)?,
+            counters: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Population counters"),
+                size: storage::Population::SHADER_SIZE.get(),
+                usage: wgpu::BufferUsages::STORAGE
+                    | wgpu::BufferUsages::COPY_DST
+                    | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            live_indices: create_indices_buffer(device, "Live agent indices", capacity),
+            free_indices: create_indices_buffer(device, "Free agent indices", capacity),
+            dispatch: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Simulation dispatch arguments"),
+                size: std::mem::size_of::<wgpu::util::DispatchIndirect>() as u64,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+                mapped_at_creation: false,
+            }),
+            capacity,
+            live_readback: Readback::new(device, "Live agents readback", 4),
+            live: None,
+            spawned: Vec::new(),
+            keep: None,
+        })
+    }
+
+    /// Number of live agents, as of a few frames ago.
+    pub fn live(&self) -> Option<u32> {
+        self.live
+    }
+
+    pub fn dispatch(&self) -> &wgpu::Buffer {
+        &self.dispatch
+    }
+
+    /// Places `agents` in free slots at the next update, dropping those
+    /// left once every slot is taken.
+    pub fn spawn(&mut self, agents: impl IntoIterator<Item = Agent>) {
+        self.spawned.extend(agents);
+    }
+
+    /// Number of agents waiting to be spawned.
+    pub fn spawning(&self) -> u32 {
+        self.spawned.len() as u32
+    }
+
+    /// Removes all but `live` live agents at the next update, before
+    /// spawning any.
+    pub fn keep(&mut self, live: u32) {
+        self.keep = Some(live);
+    }
+
+    /// Makes room for the indices of an agents buffer holding `capacity` agents.
+    pub fn resize(&mut self, device: &wgpu::Device, capacity: u32) {
+        if capacity != self.capacity {
+            self.live_indices = create_indices_buffer(device, "Live agent indices", capacity);
+            self.free_indices = create_indices_buffer(device, "Free agent indices", capacity);
+            self.capacity = capacity;
+        }
+    }
+
+    /// Records the passes that rebuild the live and free lists from `agents`
+    /// and size the following simulation pass. Agents waiting to be removed
+    /// or spawned are handled in between two compactions.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        agents: &wgpu::Buffer,
+    ) -> Result<()> {
+        let bind_group = self.compact.bindings.bind_group(
+            device,
+            [
+                vec![
+                    ("agents_buffer", agents.as_entire_binding()),
+                    ("dispatch", self.dispatch.as_entire_binding()),
+                ],
+                self.resources(),
+            ]
+            .concat(),
+        )?;
+
+        self.compact(device, encoder, &bind_group);
+        if self.keep.is_some() || !self.spawned.is_empty() {
+            self.remove_and_spawn(device, encoder, agents)?;
+            self.compact(device, encoder, &bind_group);
+        }
+
+        self.live_readback.copy(encoder, &self.counters, 0);
+        Ok(())
+    }
+
+    fn compact(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        encoder.clear_buffer(&self.counters, 0, None);
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Compact pass"),
+            });
+
+            compute_pass.set_pipeline(&self.compact.pipeline);
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute::dispatch_linear(
+                &mut compute_pass,
+                device,
+                self.capacity,
+                SLOT_WORKGROUP_SIZE,
+            );
+        }
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Prepare dispatch pass"),
+            });
+
+            compute_pass.set_pipeline(&self.prepare_dispatch.pipeline);
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+    }
+
+    /// Records the passes removing and spawning the agents waiting for it,
+    /// which rely on the lists of the last compaction.
+    fn remove_and_spawn(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        agents: &wgpu::Buffer,
+    ) -> Result<()> {
+        let spawned = std::mem::take(&mut self.spawned);
+        let keep = self.keep.take();
+
+        let resize = [spawned.len() as u32, keep.unwrap_or(u32::MAX)];
+        let resize_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Resize buffer"),
+            contents: bytemuck::cast_slice(&resize),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        // Bindings can't be empty, a zeroed and so dead agent stands in for none
+        let spawned_bytes = if spawned.is_empty() {
+            let stride = storage::Buffer::<Agent>::offset(1) - storage::Buffer::<Agent>::offset(0);
+            vec![0; stride as usize]
+        } else {
+            storage::Elements(&spawned).to_bytes()?
+        };
+        let spawned_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Spawned agents buffer"),
+            contents: &spawned_bytes,
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let bind_group = self.remove.bindings.bind_group(
+            device,
+            [
+                vec![
+                    ("agents_buffer", agents.as_entire_binding()),
+                    ("resize", resize_buffer.as_entire_binding()),
+                    ("spawned", spawned_buffer.as_entire_binding()),
+                ],
+                self.resources(),
+            ]
+            .concat(),
+        )?;
+
+        let passes = [
+            (&self.remove, keep.map(|_| self.capacity)),
+            (
+                &self.spawn,
+                (!spawned.is_empty()).then_some(spawned.len() as u32),
+            ),
+        ];
+        for (pipeline, invocations) in passes {
+            let Some(invocations) = invocations else {
+                continue;
+            };
+
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Resize pass"),
+            });
+
+            compute_pass.set_pipeline(&pipeline.pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute::dispatch_linear(&mut compute_pass, device, invocations, SLOT_WORKGROUP_SIZE);
+        }
+
+        Ok(())
+    }
+
+    /// Live and free slot counts, and the list of live slots.
+    #[cfg(test)]
+    pub(crate) fn live_list(&self) -> (&wgpu::Buffer, &wgpu::Buffer) {
+        (&self.counters, &self.live_indices)
+    }
+
+    /// Resources of the population counters and index lists.
+    pub fn resources(&self) -> Vec<Resource<'_>> {
+        vec![
+            ("population", self.counters.as_entire_binding()),
+            ("live_indices", self.live_indices.as_entire_binding()),
+            ("free_indices", self.free_indices.as_entire_binding()),
+        ]
+    }
+
+    pub fn after_submit(&mut self) {
+        self.live_readback.after_submit();
+    }
+
+    /// Picks up the live count once it has been read back.
+    pub fn poll(&mut self) {
+        if let Some(bytes) = self.live_readback.take() {
+            self.live = Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+        }
+    }
+}
+
+fn create_indices_buffer(device: &wgpu::Device, label: &str, capacity: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: capacity.max(1) as u64 * std::mem::size_of::<u32>() as u64,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        adapter::{self, AdapterOptions},
+        readback,
+    };
+
+    fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+        let options = AdapterOptions::default();
+        let instance = options.instance();
+        let Ok(adapter) = pollster::block_on(options.select(&instance, None)) else {
+            eprintln!("No adapter, skipping the population test");
+            return None;
+        };
+        let (device, queue, _) =
+            pollster::block_on(adapter::request_device(&adapter, wgpu::Limits::default()))
+                .expect("Failed to open the device");
+        Some((device, queue))
+    }
+
+    /// An agent told apart from the others by its `x` position.
+    fn agent(x: f32, alive: bool) -> Agent {
+        Agent {
+            position: glam::vec2(x, 0.0),
+            velocity: glam::Vec2::ZERO,
+            genome: glam::Vec4::ONE,
+            energy: 1.0,
+            age: 0.0,
+            alive: alive as u32,
+            species: 0,
+            origin: glam::Vec2::ZERO,
+            color: 0,
+        }
+    }
+
+    /// Updates the population, then returns the slots and positions of the
+    /// live agents.
+    fn update(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        population: &mut Population,
+        agents: &wgpu::Buffer,
+    ) -> Vec<(usize, f32)> {
+        let mut encoder = device.create_command_encoder(&Default::default());
+        population.update(device, &mut encoder, agents).unwrap();
+        queue.submit(Some(encoder.finish()));
+        population.after_submit();
+        device.poll(wgpu::Maintain::Wait);
+        population.poll();
+
+        let bytes = readback::read_buffer(device, queue, agents, agents.size());
+        storage::Buffer::<Agent>::read(&bytes)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, agent)| agent.alive != 0)
+            .map(|(slot, agent)| (slot, agent.position.x))
+            .collect()
+    }
+
+    #[test]
+    fn resizing_keeps_live_agents() {
+        let Some((device, queue)) = device() else {
+            return;
+        };
+
+        // Live agents spread out the way offspring leave them
+        let slots: Vec<Agent> = (0..8)
+            .map(|slot| agent(slot as f32, slot % 3 == 0))
+            .collect();
+        let agents = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: &storage::Buffer(&slots).to_bytes().unwrap(),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        });
+        let mut population = Population::new(&device, slots.len() as u32).unwrap();
+        let original = [(0, 0.0), (3, 3.0), (6, 6.0)];
+        assert_eq!(update(&device, &queue, &mut population, &agents), original);
+        assert_eq!(population.live(), Some(3));
+
+        // Growing fills free slots only
+        population.spawn([agent(10.0, true), agent(11.0, true)]);
+        let live = update(&device, &queue, &mut population, &agents);
+        assert_eq!(live.len(), 5);
+        assert!(original.iter().all(|agent| live.contains(agent)));
+        assert!([10.0, 11.0]
+            .iter()
+            .all(|x| live.iter().any(|(_, live)| live == x)));
+        assert_eq!(population.live(), Some(5));
+
+        // Agents left without a free slot are dropped
+        population.spawn((0..5).map(|i| agent(20.0 + i as f32, true)));
+        let live = update(&device, &queue, &mut population, &agents);
+        assert_eq!(live.len(), 8);
+        assert!(original.iter().all(|agent| live.contains(agent)));
+
+        // Shrinking removes live agents wherever they are
+        population.keep(2);
+        assert_eq!(update(&device, &queue, &mut population, &agents).len(), 2);
+        assert_eq!(population.live(), Some(2));
+
+        // Removing comes before spawning
+        population.keep(0);
+        population.spawn([agent(30.0, true)]);
+        let live = update(&device, &queue, &mut population, &agents);
+        assert_eq!(live.iter().map(|(_, x)| *x).collect::<Vec<_>>(), [30.0]);
+        assert_eq!(population.live(), Some(1));
+    }
+}
@@ -0,0 +1,404 @@
+//! Post-processing stages applied between the trail map and the screen.
+
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    error::Result,
+    pipeline::bindings::Bindings,
+    shader,
+    storage::{self, Storable},
+};
+
+/// Format stages are rendered in, so bright areas survive until tone mapping.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// One step of the post-processing chain, applied in the order they are listed.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stage {
+    pub enabled: bool,
+    pub effect: Effect,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Effect {
+    /// Scales brightness by a power of two
+    Exposure {
+        stops: f32,
+    },
+    /// Compresses brightness into what the display can show
+    ToneMap {
+        operator: ToneMap,
+    },
+    /// Glow around areas brighter than the threshold. The radius is
+    /// relative to the image height so exports of any size look the same.
+    Bloom {
+        threshold: f32,
+        radius: f32,
+        intensity: f32,
+    },
+    /// Darkens the corners, starting at `radius` from the centre
+    Vignette {
+        strength: f32,
+        radius: f32,
+    },
+    /// Noise that changes every frame
+    Grain {
+        strength: f32,
+    },
+    Sharpen {
+        strength: f32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToneMap {
+    Reinhard,
+    Aces,
+    #[default]
+    AgX,
+}
+
+/// Pipelines and intermediate textures of the post-processing chain.
+pub(crate) struct PostProcess {
+    pipelines: Pipelines,
+    bindings: Bindings,
+    sampler: wgpu::Sampler,
+    /// Resized to the output by [`PostProcess::input`]
+    targets: Targets,
+}
+
+struct Pipelines {
+    copy: wgpu::RenderPipeline,
+    exposure: wgpu::RenderPipeline,
+    tone_map: wgpu::RenderPipeline,
+    bright: wgpu::RenderPipeline,
+    blur: wgpu::RenderPipeline,
+    bloom: wgpu::RenderPipeline,
+    vignette: wgpu::RenderPipeline,
+    grain: wgpu::RenderPipeline,
+    sharpen: wgpu::RenderPipeline,
+}
+
+/// Textures sized to the output, stages ping-pong between the two full size ones.
+struct Targets {
+    size: glam::UVec2,
+    full: [wgpu::TextureView; 2],
+    /// Half size, for blurring highlights
+    half: [wgpu::TextureView; 2],
+}
+
+/// Where a stage reads from and writes to.
+#[derive(Clone, Copy)]
+enum Target<'a> {
+    Full(usize),
+    Half(usize),
+    Output(&'a wgpu::TextureView),
+}
+
+/// Every effect in its default order, disabled.
+pub fn default_chain() -> Vec<Stage> {
+    Effect::ALL
+        .into_iter()
+        .map(|effect| Stage {
+            enabled: false,
+            effect,
+        })
+        .collect()
+}
+
+impl Effect {
+    /// Each effect with default parameters, in the order they usually go.
+    pub const ALL: [Effect; 6] = [
+        Effect::Exposure { stops: 0.0 },
+        Effect::Bloom {
+            threshold: 1.0,
+            radius: 0.02,
+            intensity: 0.5,
+        },
+        Effect::ToneMap {
+            operator: ToneMap::AgX,
+        },
+        Effect::Sharpen { strength: 0.25 },
+        Effect::Vignette {
+            strength: 0.5,
+            radius: 0.5,
+        },
+        Effect::Grain { strength: 0.05 },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Effect::Exposure { .. } => "Exposure",
+            Effect::ToneMap { .. } => "Tone map",
+            Effect::Bloom { .. } => "Bloom",
+            Effect::Vignette { .. } => "Vignette",
+            Effect::Grain { .. } => "Grain",
+            Effect::Sharpen { .. } => "Sharpen",
+        }
+    }
+}
+
+impl ToneMap {
+    pub const ALL: [ToneMap; 3] = [ToneMap::Reinhard, ToneMap::Aces, ToneMap::AgX];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToneMap::Reinhard => "Reinhard",
+            ToneMap::Aces => "ACES",
+            ToneMap::AgX => "AgX",
+        }
+    }
+}
+
+impl PostProcess {
+    pub fn new(device: &wgpu::Device, target_format: wgpu::TextureFormat) -> Result<Self> {
+        let shader::Module { module, bindings } = shader::module(device, "post.wgsl", &[])?;
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Post Pipeline Layout"),
+            bind_group_layouts: &[&bindings.layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = |entry_point: &str, format: wgpu::TextureFormat| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &module,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &module,
+                    entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+            })
+        };
+
+        Ok(Self {
+            pipelines: Pipelines {
+                copy: pipeline("fs_copy", target_format),
+                exposure: pipeline("fs_exposure", HDR_FORMAT),
+                tone_map: pipeline("fs_tone_map", HDR_FORMAT),
+                bright: pipeline("fs_bright", HDR_FORMAT),
+                blur: pipeline("fs_blur", HDR_FORMAT),
+                bloom: pipeline("fs_bloom", HDR_FORMAT),
+                vignette: pipeline("fs_vignette", HDR_FORMAT),
+                grain: pipeline("fs_grain", HDR_FORMAT),
+                sharpen: pipeline("fs_sharpen", HDR_FORMAT),
+            },
+            bindings,
+            sampler: device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("Bloom sampler"),
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            }),
+            targets: Targets::new(device, glam::UVec2::ONE),
+        })
+    }
+
+    /// Texture the image to post-process has to be drawn into, at the size of the output.
+    pub fn input(&mut self, device: &wgpu::Device, size: glam::UVec2) -> &wgpu::TextureView {
+        if self.targets.size != size {
+            self.targets = Targets::new(device, size);
+        }
+
+        &self.targets.full[0]
+    }
+
+    /// Records the enabled stages, reading what was drawn into [`PostProcess::input`]
+    /// and writing the result to `output`.
+    pub fn apply(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        stages: &[Stage],
+        frame: u32,
+        output: &wgpu::TextureView,
+    ) -> Result<()> {
+        let targets = &self.targets;
+
+        let pass = |encoder: &mut wgpu::CommandEncoder,
+                    pipeline: &wgpu::RenderPipeline,
+                    input: Target,
+                    bloom: Target,
+                    output: Target,
+                    values: glam::Vec4| {
+            self.pass(
+                device, encoder, targets, pipeline, input, bloom, output, values, frame,
+            )
+        };
+
+        let mut current = 0;
+        for stage in stages.iter().filter(|stage| stage.enabled) {
+            let input = Target::Full(current);
+            let next = Target::Full(1 - current);
+            let p = &self.pipelines;
+
+            match stage.effect {
+                Effect::Exposure { stops } => pass(
+                    encoder,
+                    &p.exposure,
+                    input,
+                    input,
+                    next,
+                    glam::vec4(stops, 0.0, 0.0, 0.0),
+                )?,
+                Effect::ToneMap { operator } => {
+                    let values = glam::vec4(operator as u32 as f32, 0.0, 0.0, 0.0);
+                    pass(encoder, &p.tone_map, input, input, next, values)?
+                }
+                Effect::Bloom {
+                    threshold,
+                    radius,
+                    intensity,
+                } => {
+                    // Standard deviation in half size texels
+                    let sigma = 0.5 * radius * targets.size.y as f32;
+                    let threshold = glam::vec4(threshold, 0.0, 0.0, 0.0);
+                    let horizontal = glam::vec4(1.0, 0.0, sigma, 0.0);
+                    let vertical = glam::vec4(0.0, 1.0, sigma, 0.0);
+                    let (half_a, half_b) = (Target::Half(0), Target::Half(1));
+
+                    pass(encoder, &p.bright, input, input, half_a, threshold)?;
+                    pass(encoder, &p.blur, half_a, half_a, half_b, horizontal)?;
+                    pass(encoder, &p.blur, half_b, half_b, half_a, vertical)?;
+                    let values = glam::vec4(intensity, 0.0, 0.0, 0.0);
+                    pass(encoder, &p.bloom, input, half_a, next, values)?;
+                }
+                Effect::Vignette { strength, radius } => {
+                    let values = glam::vec4(strength, radius, 0.0, 0.0);
+                    pass(encoder, &p.vignette, input, input, next, values)?
+                }
+                Effect::Grain { strength } => {
+                    let values = glam::vec4(strength, 0.0, 0.0, 0.0);
+                    pass(encoder, &p.grain, input, input, next, values)?
+                }
+                Effect::Sharpen { strength } => {
+                    let values = glam::vec4(strength, 0.0, 0.0, 0.0);
+                    pass(encoder, &p.sharpen, input, input, next, values)?
+                }
+            }
+
+            current = 1 - current;
+        }
+
+        let input = Target::Full(current);
+        let values = glam::Vec4::ZERO;
+        pass(
+            encoder,
+            &self.pipelines.copy,
+            input,
+            input,
+            Target::Output(output),
+            values,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn pass(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        targets: &Targets,
+        pipeline: &wgpu::RenderPipeline,
+        input: Target,
+        bloom: Target,
+        output: Target,
+        values: glam::Vec4,
+        frame: u32,
+    ) -> Result<()> {
+        let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post parameters"),
+            contents: &storage::Uniform(&storage::PostParams { values, frame }).to_bytes()?,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let bind_group = self.bindings.bind_group(
+            device,
+            vec![
+                (
+                    "input",
+                    wgpu::BindingResource::TextureView(targets.view(input)),
+                ),
+                ("params", params.as_entire_binding()),
+                (
+                    "bloom_tex",
+                    wgpu::BindingResource::TextureView(targets.view(bloom)),
+                ),
+                (
+                    "bloom_sampler",
+                    wgpu::BindingResource::Sampler(&self.sampler),
+                ),
+            ],
+        )?;
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: targets.view(output),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            })],
+            depth_stencil_attachment: None,
+        });
+
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+        Ok(())
+    }
+}
+
+impl Targets {
+    fn new(device: &wgpu::Device, size: glam::UVec2) -> Self {
+        let half = (size / 2).max(glam::UVec2::ONE);
+        Self {
+            size,
+            full: [0, 1].map(|_| create_view(device, "Post target", size)),
+            half: [0, 1].map(|_| create_view(device, "Bloom target", half)),
+        }
+    }
+
+    fn view<'a>(&'a self, target: Target<'a>) -> &'a wgpu::TextureView {
+        match target {
+            Target::Full(i) => &self.full[i],
+            Target::Half(i) => &self.half[i],
+            Target::Output(view) => view,
+        }
+    }
+}
+
+fn create_view(device: &wgpu::Device, label: &str, size: glam::UVec2) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
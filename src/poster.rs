@@ -0,0 +1,338 @@
+//! Print-sized images, rendered in tiles and written with their print resolution.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use crate::{
+    camera::Camera,
+    error::{Error, Result},
+    palette,
+    renderer::Renderer,
+    screenshot,
+    simulation::Simulation,
+};
+
+/// Largest side of the tiles the poster is rendered in, keeping readback buffers small.
+const MAX_TILE_SIZE: u32 = 4096;
+
+/// Rows of pixels per TIFF strip.
+const TIFF_ROWS_PER_STRIP: u32 = 64;
+
+/// Grid of tiles a poster of `size` is rendered in, at `supersample` by
+/// `supersample` samples per pixel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tiling {
+    /// Poster pixels along each side of a tile
+    pub tile_size: u32,
+    /// Tiles along each side of the poster
+    pub tiles: glam::UVec2,
+}
+
+impl Tiling {
+    pub fn new(device: &wgpu::Device, size: glam::UVec2, supersample: u32) -> Self {
+        let supersample = supersample.max(1);
+        let max_tile = MAX_TILE_SIZE.min(device.limits().max_texture_dimension_2d);
+        // Whole pixels per tile, so that samples of a pixel never straddle tiles
+        let tile_size = (max_tile / supersample).max(1);
+        Self {
+            tile_size,
+            tiles: (size + tile_size - 1) / tile_size,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        (self.tiles.x * self.tiles.y) as usize
+    }
+}
+
+/// Image formats posters can be written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Png,
+    Tiff,
+}
+
+impl Encoding {
+    /// Format picked by the extension of `path`.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase);
+
+        match extension.as_deref() {
+            Some("png") => Ok(Encoding::Png),
+            Some("tif" | "tiff") => Ok(Encoding::Tiff),
+            _ => Err(Error::UnsupportedEncoding(path.to_owned())),
+        }
+    }
+}
+
+/// Renders the whole domain into an image of `size`, one tile at a time,
+/// averaging `supersample` by `supersample` samples into each pixel.
+/// `progress` is called with the number of tiles done and the total after
+/// each one.
+///
+/// Post-processing runs on each tile separately, so image-space effects like
+/// bloom and vignettes show seams when there is more than one tile.
+pub fn render(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    renderer: &mut Renderer,
+    simulation: &Simulation,
+    size: glam::UVec2,
+    supersample: u32,
+    mut progress: impl FnMut(usize, usize),
+) -> Result<image::RgbImage> {
+    let supersample = supersample.max(1);
+    let tiling = Tiling::new(device, size, supersample);
+    let tiles = tiling.tiles;
+    let tile_pixels = tiling.tile_size;
+    let count = tiling.count();
+
+    // Linear value of each sRGB byte, for averaging samples
+    let linear: Vec<f32> = (0..=u8::MAX)
+        .map(|c| palette::to_linear([c; 3]).x)
+        .collect();
+
+    let mut image = image::RgbImage::new(size.x, size.y);
+    for (i, tile) in (0..tiles.y)
+        .flat_map(|y| (0..tiles.x).map(move |x| glam::uvec2(x, y)))
+        .enumerate()
+    {
+        // In pixels from the top left corner of the poster
+        let min = tile * tile_pixels;
+        let max = (min + tile_pixels).min(size);
+
+        // The domain is measured from the bottom left
+        let fraction = |p: glam::UVec2| p.as_vec2() / size.as_vec2();
+        let camera = Camera::region(
+            glam::vec2(fraction(min).x, 1.0 - fraction(max).y),
+            glam::vec2(fraction(max).x, 1.0 - fraction(min).y),
+        );
+
+        let tile_image = screenshot::capture(
+            device,
+            queue,
+            renderer,
+            simulation,
+            &camera,
+            (max - min) * supersample,
+        )?;
+
+        let weight = 1.0 / (supersample * supersample) as f32;
+        for y in min.y..max.y {
+            for x in min.x..max.x {
+                let mut sum = glam::Vec3::ZERO;
+                for sy in 0..supersample {
+                    for sx in 0..supersample {
+                        let sample = tile_image.get_pixel(
+                            (x - min.x) * supersample + sx,
+                            (y - min.y) * supersample + sy,
+                        );
+                        let [r, g, b, _] = sample.0;
+                        sum += glam::Vec3::from([r, g, b].map(|c| linear[c as usize]));
+                    }
+                }
+
+                image.put_pixel(x, y, image::Rgb(palette::to_srgb(sum * weight)));
+            }
+        }
+
+        progress(i + 1, count);
+    }
+
+    Ok(image)
+}
+
+/// Writes `image` in the format its extension asks for, with `dpi` as the
+/// print resolution.
+pub fn save(image: &image::RgbImage, path: &Path, dpi: f32) -> Result<()> {
+    let encoding = Encoding::from_path(path)?;
+    let file = File::create(path).map_err(|e| Error::io(path, e))?;
+    let writer = BufWriter::new(file);
+    match encoding {
+        Encoding::Png => write_png(writer, image, dpi).map_err(|source| Error::Encode {
+            path: path.to_owned(),
+            source,
+        }),
+        Encoding::Tiff => write_tiff(writer, image, dpi).map_err(|e| Error::io(path, e)),
+    }
+}
+
+fn write_png(
+    writer: impl Write,
+    image: &image::RgbImage,
+    dpi: f32,
+) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(writer, image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+
+    // Physical pixel size, in pixels per metre
+    let ppm = (dpi / 0.0254).round() as u32;
+    let mut phys = Vec::with_capacity(9);
+    phys.extend_from_slice(&ppm.to_be_bytes());
+    phys.extend_from_slice(&ppm.to_be_bytes());
+    phys.push(png::Unit::Meter as u8);
+    writer.write_chunk(png::chunk::pHYs, &phys)?;
+
+    writer.write_image_data(image.as_raw())?;
+    writer.finish()
+}
+
+/// Writes a baseline, uncompressed little-endian TIFF with the resolution in
+/// pixels per inch. The image crate's encoder has no way to set it.
+fn write_tiff(mut writer: impl Write, image: &image::RgbImage, dpi: f32) -> io::Result<()> {
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+    const RATIONAL: u16 = 5;
+
+    let (width, height) = image.dimensions();
+    let too_large = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "image too large for a TIFF file",
+        )
+    };
+
+    // Pixels come straight after the header, followed by the directory and
+    // then the values too large to fit in its entries
+    let row_bytes = width as u64 * 3;
+    let data_bytes = row_bytes * height as u64;
+    let ifd_offset = 8 + data_bytes;
+    // Offsets are 32 bits, leaving some room for the directory
+    if ifd_offset + (1 << 20) > u32::MAX as u64 {
+        return Err(too_large());
+    }
+
+    let strips = height.div_ceil(TIFF_ROWS_PER_STRIP);
+    let strip_offsets = (0..strips)
+        .map(|i| u32::try_from(8 + (i * TIFF_ROWS_PER_STRIP) as u64 * row_bytes))
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|_| too_large())?;
+    let strip_byte_counts = (0..strips)
+        .map(|i| {
+            let rows = (height - i * TIFF_ROWS_PER_STRIP).min(TIFF_ROWS_PER_STRIP);
+            u32::try_from(rows as u64 * row_bytes)
+        })
+        .collect::<Result<Vec<u32>, _>>()
+        .map_err(|_| too_large())?;
+    let ifd_offset = u32::try_from(ifd_offset).map_err(|_| too_large())?;
+
+    let entries = 13;
+    let ifd_bytes = 2 + entries * 12 + 4;
+    let mut extra_offset = ifd_offset + ifd_bytes;
+    let mut extra = Vec::new();
+    let mut directory = Vec::new();
+
+    // Appends an entry, storing its value after the directory if it doesn't fit in 4 bytes
+    let mut entry = |tag: u16, kind: u16, count: u32, value: Vec<u8>| {
+        directory.extend_from_slice(&tag.to_le_bytes());
+        directory.extend_from_slice(&kind.to_le_bytes());
+        directory.extend_from_slice(&count.to_le_bytes());
+        if value.len() <= 4 {
+            let mut inline = value;
+            inline.resize(4, 0);
+            directory.extend_from_slice(&inline);
+        } else {
+            directory.extend_from_slice(&extra_offset.to_le_bytes());
+            extra_offset += value.len() as u32;
+            extra.extend_from_slice(&value);
+        }
+    };
+
+    let shorts = |values: &[u16]| values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let longs = |values: &[u32]| values.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let resolution = longs(&[(dpi * 100.0).round() as u32, 100]);
+
+    // Tags have to be in ascending order
+    entry(256, LONG, 1, longs(&[width]));
+    entry(257, LONG, 1, longs(&[height]));
+    entry(258, SHORT, 3, shorts(&[8, 8, 8]));
+    // No compression
+    entry(259, SHORT, 1, shorts(&[1]));
+    // RGB
+    entry(262, SHORT, 1, shorts(&[2]));
+    entry(273, LONG, strips, longs(&strip_offsets));
+    entry(277, SHORT, 1, shorts(&[3]));
+    entry(278, LONG, 1, longs(&[TIFF_ROWS_PER_STRIP]));
+    entry(279, LONG, strips, longs(&strip_byte_counts));
+    entry(282, RATIONAL, 1, resolution.clone());
+    entry(283, RATIONAL, 1, resolution);
+    // Interleaved channels
+    entry(284, SHORT, 1, shorts(&[1]));
+    // Resolution in inches
+    entry(296, SHORT, 1, shorts(&[2]));
+
+    writer.write_all(b"II")?;
+    writer.write_all(&42u16.to_le_bytes())?;
+    writer.write_all(&ifd_offset.to_le_bytes())?;
+    writer.write_all(image.as_raw())?;
+    writer.write_all(&(entries as u16).to_le_bytes())?;
+    writer.write_all(&directory)?;
+    // No further directories
+    writer.write_all(&0u32.to_le_bytes())?;
+    writer.write_all(&extra)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// An image spanning several TIFF strips, with no two rows alike.
+    fn image() -> image::RgbImage {
+        image::RgbImage::from_fn(5, TIFF_ROWS_PER_STRIP * 2 + 3, |x, y| {
+            image::Rgb([x as u8 * 50, y as u8, (x + y) as u8 ^ 0xa5])
+        })
+    }
+
+    #[test]
+    fn png_round_trips() {
+        let image = image();
+        let mut bytes = Vec::new();
+        write_png(&mut bytes, &image, 300.0).unwrap();
+
+        let read = image::load_from_memory_with_format(&bytes, image::ImageFormat::Png).unwrap();
+        assert_eq!(read.to_rgb8(), image);
+
+        let decoder = png::Decoder::new(bytes.as_slice()).read_info().unwrap();
+        let dims = decoder.info().pixel_dims.unwrap();
+        // 300 dpi in pixels per metre
+        assert_eq!((dims.xppu, dims.yppu), (11811, 11811));
+        assert_eq!(dims.unit, png::Unit::Meter);
+    }
+
+    #[test]
+    fn tiff_round_trips() {
+        use tiff::{
+            decoder::{ifd::Value, Decoder},
+            tags::Tag,
+        };
+
+        let image = image();
+        let mut bytes = Vec::new();
+        write_tiff(&mut bytes, &image, 300.0).unwrap();
+
+        let read = image::load_from_memory_with_format(&bytes, image::ImageFormat::Tiff).unwrap();
+        assert_eq!(read.to_rgb8(), image);
+
+        let mut decoder = Decoder::new(Cursor::new(&bytes)).unwrap();
+        for tag in [Tag::XResolution, Tag::YResolution] {
+            let Value::Rational(numerator, denominator) = decoder.get_tag(tag).unwrap() else {
+                panic!("{tag:?} is not a rational");
+            };
+            assert_eq!(numerator as f32 / denominator as f32, 300.0);
+        }
+        // Inches
+        assert_eq!(decoder.get_tag_u32(Tag::ResolutionUnit).unwrap(), 2);
+    }
+}
@@ -0,0 +1,534 @@
+//! Per-pass GPU timings, falling back to CPU encode times.
+
+use std::{
+    collections::VecDeque,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+/// Number of frames kept for the rolling graph.
+const HISTORY_LENGTH: usize = 240;
+
+/// Number of most recent frames averaged for the timings table.
+const AVERAGE_WINDOW: usize = 60;
+
+/// Number of in-flight readback buffers used for GPU timestamps.
+const READBACK_SLOTS: usize = 4;
+
+const QUERIES_PER_FRAME: u32 = 2 * Pass::ALL.len() as u32;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pass {
+    Diffuse,
+    Simulation,
+    Render,
+    Gui,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// Timings come from GPU timestamp queries
+    Gpu,
+    /// Timings measure how long each pass took to encode on the CPU
+    Cpu,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    pub frame: usize,
+    /// Name of the configuration that was active for this frame
+    pub config: &'static str,
+    pub timings: [Option<f32>; Pass::ALL.len()],
+}
+
+/// Running totals of every sample recorded under one configuration.
+struct ConfigTotals {
+    config: &'static str,
+    sums: [f32; Pass::ALL.len()],
+    counts: [u32; Pass::ALL.len()],
+}
+
+/// Keeps a history of frame timings, split into [`Pass`]es.
+pub struct Profiler {
+    source: Source,
+    gpu: Option<GpuTimer>,
+    frame: usize,
+    config: &'static str,
+    current: Sample,
+    cpu_start: [Option<Instant>; Pass::ALL.len()],
+    history: VecDeque<Sample>,
+    totals: Vec<ConfigTotals>,
+    log: Option<Vec<Sample>>,
+}
+
+struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    slots: Vec<ReadbackSlot>,
+    active_slot: Option<usize>,
+    period: f32,
+}
+
+struct ReadbackSlot {
+    buffer: wgpu::Buffer,
+    frame: usize,
+    config: &'static str,
+    recorded: [bool; Pass::ALL.len()],
+    state: SlotState,
+    mapped: Arc<AtomicBool>,
+}
+
+#[derive(PartialEq, Eq)]
+enum SlotState {
+    Free,
+    Recording,
+    Mapping,
+}
+
+/// Optional device features used by the profiler if the adapter supports them.
+pub fn optional_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+    adapter.features() & wgpu::Features::TIMESTAMP_QUERY
+}
+
+impl Pass {
+    pub const ALL: [Pass; 4] = [Pass::Diffuse, Pass::Simulation, Pass::Render, Pass::Gui];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pass::Diffuse => "Diffuse",
+            Pass::Simulation => "Simulation",
+            Pass::Render => "Render",
+            Pass::Gui => "GUI",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+impl Source {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Source::Gpu => "GPU timestamps",
+            Source::Cpu => "CPU encode",
+        }
+    }
+}
+
+impl Sample {
+    fn new(frame: usize, config: &'static str) -> Self {
+        Self {
+            frame,
+            config,
+            timings: [None; Pass::ALL.len()],
+        }
+    }
+
+    pub fn get(&self, pass: Pass) -> Option<f32> {
+        self.timings[pass.index()]
+    }
+
+    pub fn total(&self) -> f32 {
+        self.timings.iter().flatten().sum()
+    }
+}
+
+impl Profiler {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let gpu = if device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            Some(GpuTimer::new(device, queue))
+        } else {
+            None
+        };
+
+        Self {
+            source: if gpu.is_some() {
+                Source::Gpu
+            } else {
+                Source::Cpu
+            },
+            gpu,
+            frame: 0,
+            config: "",
+            current: Sample::new(0, ""),
+            cpu_start: [None; Pass::ALL.len()],
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+            totals: Vec::new(),
+            log: None,
+        }
+    }
+
+    /// Keep every completed sample so they can be written out with [`Profiler::write_csv`].
+    pub fn with_log(mut self) -> Self {
+        self.log = Some(Vec::new());
+        self
+    }
+
+    pub fn source(&self) -> Source {
+        self.source
+    }
+
+    /// Labels the frames that follow, so that timings of different
+    /// configurations can be compared with [`Profiler::config_average`].
+    pub fn set_config(&mut self, config: &'static str) {
+        self.config = config;
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+        self.current = Sample::new(self.frame, self.config);
+
+        if let Some(gpu) = &mut self.gpu {
+            gpu.begin_frame(self.frame, self.config);
+        }
+    }
+
+    pub fn begin_pass(&mut self, encoder: &mut wgpu::CommandEncoder, pass: Pass) {
+        match &mut self.gpu {
+            Some(gpu) => gpu.write(encoder, pass, 0),
+            None => self.cpu_start[pass.index()] = Some(Instant::now()),
+        }
+    }
+
+    pub fn end_pass(&mut self, encoder: &mut wgpu::CommandEncoder, pass: Pass) {
+        match &mut self.gpu {
+            Some(gpu) => gpu.write(encoder, pass, 1),
+            None => {
+                if let Some(start) = self.cpu_start[pass.index()].take() {
+                    self.current.timings[pass.index()] =
+                        Some(start.elapsed().as_secs_f32() * 1000.0);
+                }
+            }
+        }
+    }
+
+    /// Records the commands needed to read back this frame's timestamps. Must be
+    /// called on the last encoder of the frame, before it is submitted.
+    pub fn end_frame(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        match &mut self.gpu {
+            Some(gpu) => gpu.resolve(encoder),
+            None => self.push(self.current),
+        }
+    }
+
+    /// Must be called once the frame's commands have been submitted.
+    pub fn after_submit(&mut self) {
+        if let Some(gpu) = &mut self.gpu {
+            gpu.map_active();
+        }
+    }
+
+    /// Collects any timestamps that have finished reading back.
+    pub fn poll(&mut self, device: &wgpu::Device, wait: bool) {
+        let Some(gpu) = &mut self.gpu else {
+            return;
+        };
+
+        device.poll(if wait {
+            wgpu::Maintain::Wait
+        } else {
+            wgpu::Maintain::Poll
+        });
+
+        let mut completed = gpu.collect();
+        completed.sort_by_key(|s| s.frame);
+        for sample in completed {
+            self.push(sample);
+        }
+    }
+
+    fn push(&mut self, sample: Sample) {
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(sample);
+
+        let index = match self.totals.iter().position(|t| t.config == sample.config) {
+            Some(index) => index,
+            None => {
+                self.totals.push(ConfigTotals {
+                    config: sample.config,
+                    sums: [0.0; Pass::ALL.len()],
+                    counts: [0; Pass::ALL.len()],
+                });
+                self.totals.len() - 1
+            }
+        };
+
+        let totals = &mut self.totals[index];
+        for pass in Pass::ALL {
+            if let Some(ms) = sample.get(pass) {
+                totals.sums[pass.index()] += ms;
+                totals.counts[pass.index()] += 1;
+            }
+        }
+
+        if let Some(log) = &mut self.log {
+            log.push(sample);
+        }
+    }
+
+    pub fn history(&self) -> impl Iterator<Item = &Sample> {
+        self.history.iter()
+    }
+
+    /// Average time in milliseconds spent in `pass` over the most recent frames.
+    pub fn average(&self, pass: Pass) -> Option<f32> {
+        average(self.recent().filter_map(|s| s.get(pass)))
+    }
+
+    /// Average total frame time in milliseconds over the most recent frames.
+    pub fn average_total(&self) -> Option<f32> {
+        average(self.recent().map(|s| s.total()))
+    }
+
+    /// Average time in milliseconds spent in `pass` over every frame recorded under `config`.
+    pub fn config_average(&self, config: &str, pass: Pass) -> Option<f32> {
+        let totals = self.totals.iter().find(|t| t.config == config)?;
+        let count = totals.counts[pass.index()];
+        (count > 0).then(|| totals.sums[pass.index()] / count as f32)
+    }
+
+    fn recent(&self) -> impl Iterator<Item = &Sample> {
+        self.history
+            .iter()
+            .skip(self.history.len().saturating_sub(AVERAGE_WINDOW))
+    }
+
+    /// Writes every logged sample as CSV, one row per frame with timings in milliseconds.
+    pub fn write_csv<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        write!(writer, "frame,source,config")?;
+        for pass in Pass::ALL {
+            write!(writer, ",{}_ms", pass.name().to_lowercase())?;
+        }
+        writeln!(writer, ",total_ms")?;
+
+        for sample in self.log.iter().flatten() {
+            write!(
+                writer,
+                "{},{:?},{}",
+                sample.frame, self.source, sample.config
+            )?;
+            for pass in Pass::ALL {
+                match sample.get(pass) {
+                    Some(ms) => write!(writer, ",{:.4}", ms)?,
+                    None => write!(writer, ",")?,
+                }
+            }
+            writeln!(writer, ",{:.4}", sample.total())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl GpuTimer {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let size = (QUERIES_PER_FRAME as usize * std::mem::size_of::<u64>()) as u64;
+
+        Self {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Profiler queries"),
+                ty: wgpu::QueryType::Timestamp,
+                count: QUERIES_PER_FRAME,
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Profiler resolve buffer"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            }),
+            slots: (0..READBACK_SLOTS)
+                .map(|_| ReadbackSlot {
+                    buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("Profiler readback buffer"),
+                        size,
+                        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }),
+                    frame: 0,
+                    config: "",
+                    recorded: [false; Pass::ALL.len()],
+                    state: SlotState::Free,
+                    mapped: Arc::new(AtomicBool::new(false)),
+                })
+                .collect(),
+            active_slot: None,
+            period: queue.get_timestamp_period(),
+        }
+    }
+
+    fn begin_frame(&mut self, frame: usize, config: &'static str) {
+        // If every slot is still waiting on a readback this frame simply goes unmeasured
+        self.active_slot = self.slots.iter().position(|s| s.state == SlotState::Free);
+        if let Some(slot) = self.active_slot.map(|i| &mut self.slots[i]) {
+            slot.frame = frame;
+            slot.config = config;
+            slot.recorded = [false; Pass::ALL.len()];
+            slot.state = SlotState::Recording;
+        }
+    }
+
+    fn write(&mut self, encoder: &mut wgpu::CommandEncoder, pass: Pass, offset: u32) {
+        if let Some(slot) = self.active_slot.map(|i| &mut self.slots[i]) {
+            encoder.write_timestamp(&self.query_set, 2 * pass.index() as u32 + offset);
+            slot.recorded[pass.index()] = true;
+        }
+    }
+
+    fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(slot) = self.active_slot.map(|i| &self.slots[i]) {
+            encoder.resolve_query_set(
+                &self.query_set,
+                0..QUERIES_PER_FRAME,
+                &self.resolve_buffer,
+                0,
+            );
+            encoder.copy_buffer_to_buffer(
+                &self.resolve_buffer,
+                0,
+                &slot.buffer,
+                0,
+                self.resolve_buffer.size(),
+            );
+        }
+    }
+
+    fn map_active(&mut self) {
+        if let Some(slot) = self.active_slot.take().map(|i| &mut self.slots[i]) {
+            let mapped = slot.mapped.clone();
+            slot.state = SlotState::Mapping;
+            slot.buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    if result.is_ok() {
+                        mapped.store(true, Ordering::Release);
+                    }
+                });
+        }
+    }
+
+    fn collect(&mut self) -> Vec<Sample> {
+        let mut completed = Vec::new();
+        for slot in &mut self.slots {
+            if slot.state != SlotState::Mapping || !slot.mapped.swap(false, Ordering::Acquire) {
+                continue;
+            }
+
+            let mut sample = Sample::new(slot.frame, slot.config);
+            {
+                let view = slot.buffer.slice(..).get_mapped_range();
+                let timestamps: &[u64] = bytemuck::cast_slice(&view);
+                for pass in Pass::ALL {
+                    if slot.recorded[pass.index()] {
+                        let start = timestamps[2 * pass.index()];
+                        let end = timestamps[2 * pass.index() + 1];
+                        let nanos = end.saturating_sub(start) as f32 * self.period;
+                        sample.timings[pass.index()] = Some(nanos / 1_000_000.0);
+                    }
+                }
+            }
+
+            slot.buffer.unmap();
+            slot.state = SlotState::Free;
+            completed.push(sample);
+        }
+
+        completed
+    }
+}
+
+fn average(values: impl Iterator<Item = f32>) -> Option<f32> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+    if count > 0 {
+        Some(sum / count as f32)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Profiler on CPU timings that logs its samples, without a device.
+    fn profiler() -> Profiler {
+        Profiler {
+            source: Source::Cpu,
+            gpu: None,
+            frame: 0,
+            config: "",
+            current: Sample::new(0, ""),
+            cpu_start: [None; Pass::ALL.len()],
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+            totals: Vec::new(),
+            log: Some(Vec::new()),
+        }
+    }
+
+    fn sample(
+        frame: usize,
+        config: &'static str,
+        timings: [Option<f32>; Pass::ALL.len()],
+    ) -> Sample {
+        Sample {
+            frame,
+            config,
+            timings,
+        }
+    }
+
+    #[test]
+    fn csv_has_a_row_per_logged_frame() {
+        let mut profiler = profiler();
+        profiler.push(sample(1, "base", [Some(1.0), Some(2.5), None, Some(0.25)]));
+        profiler.push(sample(2, "sprites", [Some(1.5), None, Some(3.0), None]));
+
+        let mut csv = Vec::new();
+        profiler.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "frame,source,config,diffuse_ms,simulation_ms,render_ms,gui_ms,total_ms\n\
+             1,Cpu,base,1.0000,2.5000,,0.2500,3.7500\n\
+             2,Cpu,sprites,1.5000,,3.0000,,4.5000\n"
+        );
+    }
+
+    #[test]
+    fn averages_cover_the_most_recent_frames() {
+        let mut profiler = profiler();
+        for frame in 0..AVERAGE_WINDOW {
+            profiler.push(sample(frame, "", [Some(100.0), None, None, None]));
+        }
+        for frame in 0..AVERAGE_WINDOW {
+            let diffuse = (frame % 2 == 0).then_some(frame as f32);
+            profiler.push(sample(frame, "", [diffuse, Some(2.0), None, None]));
+        }
+
+        let diffuse = (0..AVERAGE_WINDOW).step_by(2).map(|f| f as f32);
+        let expected = diffuse.clone().sum::<f32>() / diffuse.count() as f32;
+        assert_eq!(profiler.average(Pass::Diffuse), Some(expected));
+        assert_eq!(profiler.average(Pass::Simulation), Some(2.0));
+        assert_eq!(profiler.average(Pass::Render), None);
+        assert_eq!(profiler.average_total(), Some(expected / 2.0 + 2.0));
+    }
+
+    #[test]
+    fn config_averages_are_kept_apart() {
+        let mut profiler = profiler();
+        profiler.push(sample(1, "a", [Some(1.0), None, None, None]));
+        profiler.push(sample(2, "b", [Some(4.0), None, None, None]));
+        profiler.push(sample(3, "a", [Some(3.0), None, None, None]));
+
+        assert_eq!(profiler.config_average("a", Pass::Diffuse), Some(2.0));
+        assert_eq!(profiler.config_average("b", Pass::Diffuse), Some(4.0));
+        assert_eq!(profiler.config_average("a", Pass::Render), None);
+        assert_eq!(profiler.config_average("c", Pass::Diffuse), None);
+    }
+}
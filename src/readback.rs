@@ -0,0 +1,152 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Copies a small range of a GPU buffer back to the CPU without stalling,
+/// skipping copies while the previous one is still in flight.
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    state: State,
+    mapped: Arc<AtomicBool>,
+}
+
+#[derive(PartialEq, Eq)]
+enum State {
+    Idle,
+    Copied,
+    Mapping,
+}
+
+impl Readback {
+    pub fn new(device: &wgpu::Device, label: &str, size: wgpu::BufferAddress) -> Self {
+        Self {
+            buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            state: State::Idle,
+            mapped: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Records a copy from `source` unless a previous copy hasn't been read yet.
+    pub fn copy(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+    ) {
+        if self.state == State::Idle {
+            encoder.copy_buffer_to_buffer(source, offset, &self.buffer, 0, self.buffer.size());
+            self.state = State::Copied;
+        }
+    }
+
+    /// Must be called once the commands recorded by [`Readback::copy`] have been submitted.
+    pub fn after_submit(&mut self) {
+        if self.state == State::Copied {
+            let mapped = self.mapped.clone();
+            self.state = State::Mapping;
+            self.buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    if result.is_ok() {
+                        mapped.store(true, Ordering::Release);
+                    }
+                });
+        }
+    }
+
+    /// Returns the copied bytes once they are available. The device must be
+    /// polled elsewhere for this to make progress.
+    pub fn take(&mut self) -> Option<Vec<u8>> {
+        if self.state != State::Mapping || !self.mapped.swap(false, Ordering::Acquire) {
+            return None;
+        }
+
+        let bytes = self.buffer.slice(..).get_mapped_range().to_vec();
+        self.buffer.unmap();
+        self.state = State::Idle;
+
+        Some(bytes)
+    }
+}
+
+/// Copies `size` bytes of `source` back to the CPU, waiting for the GPU to finish.
+pub fn read_buffer(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    source: &wgpu::Buffer,
+    size: wgpu::BufferAddress,
+) -> Vec<u8> {
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Buffer readback"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Buffer readback encoder"),
+    });
+    encoder.copy_buffer_to_buffer(source, 0, &buffer, 0, size);
+    queue.submit(std::iter::once(encoder.finish()));
+
+    map(device, &buffer)
+}
+
+/// Copies the pixels of `texture` back to the CPU, row by row without
+/// padding, waiting for the GPU to finish.
+pub fn read_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+    bytes_per_pixel: u32,
+) -> Vec<u8> {
+    // Rows of a texture copy have to be aligned
+    let row_bytes = texture.width() * bytes_per_pixel;
+    let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Texture readback"),
+        size: padded_row_bytes as u64 * texture.height() as u64,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Texture readback encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes),
+                rows_per_image: None,
+            },
+        },
+        texture.size(),
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    map(device, &buffer)
+        .chunks(padded_row_bytes as usize)
+        .flat_map(|row| &row[..row_bytes as usize])
+        .copied()
+        .collect()
+}
+
+/// Contents of a buffer that copies have been submitted into.
+fn map(device: &wgpu::Device, buffer: &wgpu::Buffer) -> Vec<u8> {
+    let slice = buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::Maintain::Wait);
+
+    let bytes = slice.get_mapped_range().to_vec();
+    buffer.unmap();
+    bytes
+}
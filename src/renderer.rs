@@ -0,0 +1,271 @@
+//! Drawing the trail map and agents into a texture.
+
+use wgpu::util::DeviceExt;
+
+use crate::{
+    camera::Camera,
+    error::{self, Result},
+    palette, pipeline,
+    post::{self, PostProcess},
+    profiler::{Pass, Profiler},
+    simulation::Simulation,
+    storage::{self, Storable},
+    trail::TrailFormat,
+};
+
+const QUAD_VERTICIES: &[storage::Vertex] = &[
+    storage::Vertex {
+        position: glam::f32::vec3(-1.0, 1.0, 0.0),
+        uvs: glam::f32::vec2(0.0, 1.0),
+    },
+    storage::Vertex {
+        position: glam::f32::vec3(-1.0, -1.0, 0.0),
+        uvs: glam::f32::vec2(0.0, 0.0),
+    },
+    storage::Vertex {
+        position: glam::f32::vec3(1.0, -1.0, 0.0),
+        uvs: glam::f32::vec2(1.0, 0.0),
+    },
+    storage::Vertex {
+        position: glam::f32::vec3(1.0, 1.0, 0.0),
+        uvs: glam::f32::vec2(1.0, 1.0),
+    },
+];
+
+const QUAD_INDICES: &[u32] = &[0, 1, 2, 2, 3, 0];
+
+/// Draws a simulation into textures of one format, recreating its pipelines
+/// when the trail format changes.
+pub struct Renderer {
+    target_format: wgpu::TextureFormat,
+    trail_format: TrailFormat,
+    pipeline: pipeline::render::RenderPipeline,
+    /// Draws into the post-processing input instead of the target
+    hdr_pipeline: pipeline::render::RenderPipeline,
+    sprite_pipeline: pipeline::sprite::SpritePipeline,
+    hdr_sprite_pipeline: pipeline::sprite::SpritePipeline,
+    post: PostProcess,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+}
+
+impl Renderer {
+    pub fn new(
+        device: &wgpu::Device,
+        target_format: wgpu::TextureFormat,
+        trail_format: TrailFormat,
+    ) -> Result<Self> {
+        error::compile(device, || -> Result<Self> {
+            Ok(Self {
+                target_format,
+                trail_format,
+                pipeline: pipeline::render::RenderPipeline::new(
+                    device,
+                    target_format,
+                    trail_format,
+                )?,
+                hdr_pipeline: pipeline::render::RenderPipeline::new(
+                    device,
+                    post::HDR_FORMAT,
+                    trail_format,
+                )?,
+                sprite_pipeline: pipeline::sprite::SpritePipeline::new(device, target_format)?,
+                hdr_sprite_pipeline: pipeline::sprite::SpritePipeline::new(
+                    device,
+                    post::HDR_FORMAT,
+                )?,
+                post: PostProcess::new(device, target_format)?,
+                vertex_buffer: {
+                    let bytes = bytemuck::cast_slice(QUAD_VERTICIES);
+                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("Vertex Buffer"),
+                        contents: bytes,
+                        usage: wgpu::BufferUsages::VERTEX,
+                    })
+                },
+                index_buffer: {
+                    let bytes = bytemuck::cast_slice(QUAD_INDICES);
+                    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some("Index Buffer"),
+                        contents: bytes,
+                        usage: wgpu::BufferUsages::INDEX,
+                    })
+                },
+            })
+        })?
+    }
+
+    /// Records the passes drawing the simulation's trail map and agent
+    /// sprites into `target`, including post-processing.
+    pub fn render(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::Texture,
+        simulation: &Simulation,
+        camera: &Camera,
+        profiler: &mut Profiler,
+    ) -> Result<()> {
+        profiler.begin_pass(encoder, Pass::Render);
+        let drawn = self.draw(device, encoder, target, simulation, camera);
+        profiler.end_pass(encoder, Pass::Render);
+        drawn
+    }
+
+    /// Same as [`Renderer::render`] without profiling, for drawing outside of a frame.
+    pub fn draw(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::Texture,
+        simulation: &Simulation,
+        camera: &Camera,
+    ) -> Result<()> {
+        let trail = simulation.trail();
+        if trail.format() != self.trail_format {
+            self.trail_format = trail.format();
+            self.pipeline =
+                pipeline::render::RenderPipeline::new(device, self.target_format, trail.format())?;
+            self.hdr_pipeline =
+                pipeline::render::RenderPipeline::new(device, post::HDR_FORMAT, trail.format())?;
+        }
+
+        let stages = &simulation.globals.scene.post;
+        let post_processed = stages.iter().any(|stage| stage.enabled);
+        let target_view = target.create_view(&wgpu::TextureViewDescriptor::default());
+        let size = glam::uvec2(target.width(), target.height());
+        let (pipeline, sprite_pipeline, view) = if post_processed {
+            (
+                &self.hdr_pipeline,
+                &self.hdr_sprite_pipeline,
+                self.post.input(device, size),
+            )
+        } else {
+            (&self.pipeline, &self.sprite_pipeline, &target_view)
+        };
+
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera buffer"),
+            contents: &storage::Uniform(&camera.uniform()).to_bytes()?,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let palette = &simulation.globals.scene.palette;
+        let background = palette::to_linear(palette.background);
+        let palette_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Palette buffer"),
+            contents: &storage::Uniform(&storage::Palette {
+                background,
+                range: palette.range.max(f32::EPSILON),
+                source: palette.source as u32,
+            })
+            .to_bytes()?,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let mut resources = vec![
+            ("tex", wgpu::BindingResource::TextureView(trail.view())),
+            ("camera", camera_buffer.as_entire_binding()),
+            ("palette", palette_buffer.as_entire_binding()),
+            simulation.palette().resource(),
+        ];
+
+        if let Some(colors) = trail.colors() {
+            resources.push(("trail_colors", colors.as_entire_binding()));
+        }
+
+        let bind_group = pipeline.bindings.bind_group(device, resources)?;
+
+        let sprites = simulation.globals.scene.sprites;
+        let sprite_bind_group = sprites
+            .map(|sprites| -> Result<_> {
+                let sprites_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Sprites buffer"),
+                    contents: &storage::Uniform(&storage::Sprites {
+                        domain: simulation.domain().as_vec2(),
+                        viewport: size.as_vec2(),
+                        size: sprites.size,
+                        opacity: sprites.opacity,
+                        length: sprites.length,
+                        shape: sprites.shape as u32,
+                    })
+                    .to_bytes()?,
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+
+                sprite_pipeline.bindings.bind_group(
+                    device,
+                    vec![
+                        ("camera", camera_buffer.as_entire_binding()),
+                        ("sprites", sprites_buffer.as_entire_binding()),
+                    ],
+                )
+            })
+            .transpose()?;
+
+        // Without the trail map the sprites are drawn straight over the background
+        let hide_trail = sprites.is_some_and(|sprites| sprites.hide_trail);
+        let clear = if hide_trail {
+            wgpu::Color {
+                r: background.x as f64,
+                g: background.y as f64,
+                b: background.z as f64,
+                a: 1.0,
+            }
+        } else {
+            wgpu::Color {
+                r: 1.0,
+                g: 0.0,
+                b: 0.5,
+                a: 1.0,
+            }
+        };
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[
+                    // This is what @location(0) in the fragment shader targets
+                    Some(wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(clear),
+                            store: true,
+                        },
+                    }),
+                ],
+                depth_stencil_attachment: None,
+            });
+
+            if !hide_trail {
+                render_pass.set_pipeline(&pipeline.pipeline);
+                render_pass.set_bind_group(0, &bind_group, &[]);
+                render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+
+                render_pass.draw_indexed(0..6, 0, 0..1);
+            }
+
+            if let Some(sprite_bind_group) = &sprite_bind_group {
+                // Dead slots are drawn too and discarded by the vertex shader
+                let (agents, capacity) = simulation.agents();
+                let start = storage::Buffer::<storage::Agent>::offset(0);
+
+                render_pass.set_pipeline(&sprite_pipeline.pipeline);
+                render_pass.set_bind_group(0, sprite_bind_group, &[]);
+                render_pass.set_vertex_buffer(0, agents.slice(start..));
+                render_pass.draw(0..6, 0..capacity);
+            }
+        }
+
+        if post_processed {
+            let frame = simulation.globals.timing.frame as u32;
+            self.post
+                .apply(device, encoder, stages, frame, &target_view)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+//! Agent behaviour written in WGSL, replacing how agents steer and what they
+//! deposit in the simulation pass.
+//!
+//! A rules file defines two hooks, which are spliced into the simulation
+//! kernel and called for every live agent each step:
+//!
+//! ```wgsl
+//! // Velocity after turning, whose length is the agent's speed
+//! fn steer(agent: Agent, ctx: SenseContext) -> vec2<f32> {
+//!     return rotate(agent.velocity, ctx.turn_rate * sign(ctx.left - ctx.right));
+//! }
+//!
+//! // Linear colour in rgb and amount of trail in a
+//! fn deposit(agent: Agent) -> vec4<f32> {
+//!     return vec4<f32>(gradient(agent.energy), 1.0);
+//! }
+//! ```
+//!
+//! `Agent` has the fields of the agents buffer: `position`, `velocity`,
+//! `genome`, `energy`, `age`, `alive`, `species`, `origin` and `color`.
+//! `SenseContext` and the helpers rules can call, such as `sample_area` and
+//! `warp_clamp`, are documented in `src/shader/hooks.wgsl`. Rules are plain
+//! WGSL otherwise, and can include the built-in shader files.
+
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    error::{Error, Result},
+    shader::{self, Defines, Shader},
+};
+
+/// Path the rules are included at by `simulation.wgsl`.
+const INCLUDE: &str = "rules.wgsl";
+
+/// Hooks a rules file must define, with their signatures.
+const HOOKS: [(&str, &str); 2] = [
+    (
+        "steer",
+        "fn steer(agent: Agent, ctx: SenseContext) -> vec2<f32>",
+    ),
+    ("deposit", "fn deposit(agent: Agent) -> vec4<f32>"),
+];
+
+/// A rules file, as it was when loaded.
+#[derive(Clone, Debug)]
+pub struct Rules {
+    pub path: PathBuf,
+    source: String,
+}
+
+impl Rules {
+    /// Reads the rules at `path` and checks that they define both hooks.
+    pub fn load(path: &Path) -> Result<Self> {
+        let source = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        let error = |message: String| Error::Rules {
+            path: path.to_owned(),
+            message,
+        };
+
+        for (name, signature) in HOOKS {
+            if !source.lines().any(|line| declares(line, name)) {
+                return Err(error(format!("agent rules must define `{}`", signature)));
+            }
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            source,
+        })
+    }
+
+    /// Module of the simulation pass with these rules, for the trail
+    /// kernels of `defines`. Errors in the rules point at their file.
+    pub(crate) fn module(
+        &self,
+        device: &wgpu::Device,
+        defines: &Defines,
+    ) -> Result<shader::Module> {
+        self.shader(defines)
+            .and_then(|shader| shader.create_module(device))
+            .map_err(|error| self.locate(error))
+    }
+
+    /// The simulation pass preprocessed with these rules included, checked
+    /// for bindings declared by the rules.
+    fn shader(&self, defines: &Defines) -> Result<Shader> {
+        let defines = [defines, &[("USER_RULES", "")]].concat();
+        let read = |path: &str| match path {
+            INCLUDE => Some(Cow::Owned(self.source.clone())),
+            _ => shader::builtin_file(path),
+        };
+
+        let source = shader::builtin_file("simulation.wgsl").unwrap_or_default();
+        let shader = Shader::preprocess("simulation.wgsl", &source, &defines, &read)?;
+        check_bindings(&shader)?;
+        Ok(shader)
+    }
+
+    /// `error` pointing at the rules' file rather than where they're included.
+    fn locate(&self, error: Error) -> Error {
+        match error {
+            Error::ShaderSource {
+                file,
+                line,
+                message,
+            } if file == INCLUDE => Error::ShaderSource {
+                file: self.path.display().to_string(),
+                line,
+                message,
+            },
+            error => error,
+        }
+    }
+}
+
+/// Errors at the first global variable with a binding that comes from the
+/// rules, since the simulation pass is laid out without them.
+fn check_bindings(shader: &Shader) -> Result<()> {
+    // Syntax errors are reported by validation, with their line
+    let Ok(module) = naga::front::wgsl::parse_str(shader.source()) else {
+        return Ok(());
+    };
+
+    for (handle, variable) in module.global_variables.iter() {
+        if variable.binding.is_none() {
+            continue;
+        }
+
+        let location = module
+            .global_variables
+            .get_span(handle)
+            .location(shader.source());
+        if let Some(origin) = shader
+            .origin(location.line_number as usize)
+            .filter(|origin| origin.file == INCLUDE)
+        {
+            return Err(Error::ShaderSource {
+                file: origin.file.clone(),
+                line: origin.line,
+                message: format!(
+                    "agent rules can't declare bindings such as `{}`, only use those of the simulation pass",
+                    variable.name.as_deref().unwrap_or_default()
+                ),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `line` starts the declaration of the function `name`.
+fn declares(line: &str, name: &str) -> bool {
+    line.trim_start()
+        .strip_prefix("fn")
+        .filter(|rest| rest.starts_with(char::is_whitespace))
+        .and_then(|rest| rest.trim_start().strip_prefix(name))
+        .is_some_and(|rest| rest.trim_start().starts_with('('))
+}
+
+/// When the file at `path` was last modified, to reload rules once they change.
+pub fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trail::{TrailAccess, TrailFormat};
+
+    fn rules(source: &str) -> Rules {
+        Rules {
+            path: PathBuf::from("tests/rules.wgsl"),
+            source: source.to_owned(),
+        }
+    }
+
+    fn validate(rules: &Rules) -> Result<()> {
+        let format = TrailFormat::Rgba32Float;
+        let defines = [&format.defines()[..], &[TrailAccess::ReadWrite.define()]].concat();
+        rules
+            .shader(&defines)
+            .and_then(|shader| shader.validate())
+            .map(|_| ())
+            .map_err(|error| rules.locate(error))
+    }
+
+    #[test]
+    fn hooks_are_found_by_their_declarations() {
+        assert!(declares(
+            "fn steer(agent: Agent, ctx: SenseContext) -> vec2<f32> {",
+            "steer"
+        ));
+        assert!(declares(
+            "  fn  deposit (agent: Agent) -> vec4<f32> {",
+            "deposit"
+        ));
+        assert!(!declares("fn steering(agent: Agent) {", "steer"));
+        assert!(!declares(
+            "// fn steer(agent: Agent, ctx: SenseContext)",
+            "steer"
+        ));
+        assert!(!declares("let v = steer(agent, ctx);", "steer"));
+    }
+
+    #[test]
+    fn golden_rules_validate() {
+        let rules = Rules::load(Path::new("tests/golden/rules.wgsl")).unwrap();
+        if let Err(error) = validate(&rules) {
+            panic!("{}", error);
+        }
+    }
+
+    #[test]
+    fn errors_point_at_the_rules_file() {
+        let rules = rules(
+            "fn steer(agent: Agent, ctx: SenseContext) -> vec2<f32> {\n    return agent.velocity;\n}\n\nfn deposit(agent: Agent) -> vec4<f32> {\n    return agent.missing;\n}",
+        );
+        match validate(&rules) {
+            Err(Error::ShaderSource { file, line, .. }) => {
+                assert_eq!((file.as_str(), line), ("tests/rules.wgsl", 6));
+            }
+            result => panic!("expected an error in the rules, got {:?}", result.err()),
+        }
+    }
+
+    #[test]
+    fn bindings_are_rejected_where_they_are_declared() {
+        let hooks = "fn steer(agent: Agent, ctx: SenseContext) -> vec2<f32> {\n    return agent.velocity;\n}\n\nfn deposit(agent: Agent) -> vec4<f32> {\n    return vec4<f32>(1.0);\n}\n";
+
+        // Mentioning a binding in a comment is fine
+        let commented = rules(&format!("// Can't use @binding here\n{}", hooks));
+        assert!(validate(&commented).is_ok());
+
+        let bound = rules(&format!(
+            "{}\n@group(0) @binding(15) var<uniform> extra: vec4<f32>;",
+            hooks
+        ));
+        match validate(&bound) {
+            Err(Error::ShaderSource { file, line, .. }) => {
+                assert_eq!((file.as_str(), line), ("tests/rules.wgsl", 9));
+            }
+            result => panic!("expected an error in the rules, got {:?}", result.err()),
+        }
+    }
+}
@@ -0,0 +1,693 @@
+//! Serialisable description of a piece.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{Error, Result},
+    palette::Gradient,
+    post::{self, Stage},
+    timeline::Timeline,
+    trail::TrailFormat,
+};
+
+/// Description of a piece, loaded from a RON file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Scene {
+    /// Number of agents being simulated
+    pub agents: u32,
+    /// Where new agents are placed
+    pub spawn: Spawn,
+    /// Seed of the spawn positions and headings, which differ every run when not set
+    pub seed: Option<u64>,
+    pub trail_format: TrailFormat,
+    pub behaviour: Behaviour,
+    /// WGSL file of agent rules replacing how agents steer and deposit, see
+    /// the `rules` module. Relative to the scene's file
+    pub rules: Option<PathBuf>,
+    /// Birth and death of agents, agents live forever when not set
+    pub lifecycle: Option<Lifecycle>,
+    pub environment: Environment,
+    pub boundary: Boundary,
+    pub palette: Palette,
+    /// Post-processing applied between the trail map and the screen
+    pub post: Vec<Stage>,
+    /// Agents drawn on top of the trail map, not drawn when not set
+    pub sprites: Option<Sprites>,
+    /// Parameters animated over the piece's lifetime
+    pub timeline: Timeline,
+}
+
+/// How agents sense and steer, and how quickly trails fade. Agents' genomes
+/// scale the sensor and turning parameters individually.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Behaviour {
+    /// Angle between the forward sensor and each side sensor, in radians
+    pub sensor_angle: f32,
+    /// Distance from an agent to its sensors, in pixels
+    pub sensor_distance: f32,
+    /// Angle an agent turns by each step towards the stronger side, in radians
+    pub turn_rate: f32,
+    /// Trail intensity lost per second
+    pub decay: f32,
+}
+
+/// What happens at the edges of the domain, to agents and to diffusing trails alike.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Boundary {
+    /// Opposite edges are connected
+    #[default]
+    Wrap,
+    /// Agents bounce off the edges, trails diffuse as if mirrored
+    Reflect,
+    /// Agents leaving the domain reappear somewhere random, trails fade at the edges
+    Respawn,
+    /// Like `Reflect` but within the largest circle that fits the domain
+    Circle,
+}
+
+/// How trails are coloured on screen.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Palette {
+    /// What is mapped through the gradient
+    pub source: ColorSource,
+    pub gradient: Gradient,
+    /// sRGB colour of areas without trail
+    pub background: [u8; 3],
+    /// Trail intensity at which the background is fully covered, and the
+    /// top of the gradient is reached when colouring by intensity
+    pub range: f32,
+}
+
+/// How agents are drawn when rendered directly. Colours are those the
+/// agents last deposited, added on top of what is below.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sprites {
+    pub shape: SpriteShape,
+    /// Width in screen pixels
+    pub size: f32,
+    pub opacity: f32,
+    /// Seconds of motion a line trails behind its agent
+    pub length: f32,
+    /// Draws the sprites over the background instead of the trail map
+    pub hide_trail: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpriteShape {
+    /// Round dot
+    #[default]
+    Point,
+    /// Square rotated to face the direction the agent heads in
+    Quad,
+    /// Streak along the agent's recent motion
+    Line,
+}
+
+/// Value looked up in the palette's gradient. All but `Intensity` are
+/// decided per agent and deposited into the trail.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColorSource {
+    /// Trail intensity of each pixel
+    Intensity,
+    /// Position of the agent in the agents buffer
+    #[default]
+    Index,
+    /// The first species at the start of the gradient, the second at its end
+    Species,
+    /// Agent speed, with the middle of the gradient at the base speed
+    Speed,
+    /// Direction agents head in, all the way around the gradient
+    Heading,
+    /// Age from birth to the lifecycle's maximum age
+    Age,
+    /// Energy from none to what the lifecycle needs for reproduction
+    Energy,
+    /// Trail intensity around the agent, up to the palette's range
+    Density,
+    /// Angle around the centre of the domain at which the agent, or the
+    /// first of its ancestors, was spawned
+    Spawn,
+}
+
+/// Energy budget that decides when agents die and reproduce. Rates are per second.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Lifecycle {
+    /// Energy lost by every agent
+    pub metabolism: f32,
+    /// Energy gained per unit of trail intensity under the agent
+    pub feeding: f32,
+    /// Energy an agent needs before it can reproduce, half of which goes to its offspring
+    pub reproduction_energy: f32,
+    /// Trail intensity ahead of an agent needed for it to reproduce
+    pub reproduction_trail: f32,
+    /// Age in seconds at which agents die regardless of energy
+    pub max_age: f32,
+    /// Largest relative change of each parameter passed on to offspring
+    pub mutation: f32,
+}
+
+/// Everything agents react to besides the trail map. Positions are
+/// fractions of the domain measured from its bottom left corner, sizes are
+/// relative to the shorter side of the domain.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Environment {
+    pub food: Vec<Food>,
+    pub obstacles: Vec<Obstacle>,
+    pub attractors: Vec<Attractor>,
+    pub fields: Vec<Field>,
+    pub mask: Option<Mask>,
+}
+
+/// Greyscale image whose brightness shapes how agents behave.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Mask {
+    /// PNG or JPEG file, relative to the scene file when loaded from one
+    pub path: PathBuf,
+    #[serde(default)]
+    pub mode: MaskMode,
+    #[serde(default)]
+    pub fit: MaskFit,
+    /// How much the mask affects agents, from 0 to 1
+    #[serde(default = "Mask::default_strength")]
+    pub strength: f32,
+    /// Swaps bright and dark
+    #[serde(default)]
+    pub invert: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MaskMode {
+    /// Agents sense brightness like trail
+    #[default]
+    Attractor,
+    /// Agents slow down in dark regions
+    Speed,
+    /// Agents deposit less trail in dark regions
+    Deposit,
+    /// Agents can't enter bright regions
+    Obstacle,
+}
+
+/// How the mask image is mapped onto the simulation domain.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MaskFit {
+    /// Covers the domain, ignoring the image's aspect ratio
+    #[default]
+    Stretch,
+    /// As large as possible while keeping the aspect ratio, centered
+    Fit,
+    /// Repeated at one pixel per simulated pixel
+    Tile,
+}
+
+/// Emits into the trail map around it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Food {
+    pub position: glam::Vec2,
+    pub radius: f32,
+    /// Trail intensity added per second at the centre
+    pub rate: f32,
+    /// Movement in domain fractions per second, wrapping around the edges
+    pub velocity: glam::Vec2,
+}
+
+/// Region agents can't enter and trails don't diffuse into.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Obstacle {
+    Circle { center: glam::Vec2, radius: f32 },
+    Rect { min: glam::Vec2, max: glam::Vec2 },
+}
+
+/// Point that agents sense as if it were trail, repelling them when the strength is negative.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Attractor {
+    pub position: glam::Vec2,
+    pub radius: f32,
+    pub strength: f32,
+}
+
+/// Vector field that carries agents along.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Field {
+    /// The same everywhere, in domain fractions per second
+    Wind { velocity: glam::Vec2 },
+    /// Rotating around a centre at `strength` radians per second, counter-clockwise when positive
+    Vortex { center: glam::Vec2, strength: f32 },
+}
+
+/// Distribution new agents are spawned with. Sizes are relative to the
+/// shorter side of the simulation domain.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Spawn {
+    /// Uniformly inside a disc at the centre, heading in random directions
+    Disc { radius: f32 },
+    /// On a circle at the centre, heading inwards
+    Ring { radius: f32 },
+    /// At the centre, heading outwards
+    Point,
+    /// Uniformly over the whole domain, heading in random directions
+    Uniform,
+}
+
+/// Trail format the adapter couldn't use, and the one used in its place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrailFallback {
+    pub requested: TrailFormat,
+    pub used: TrailFormat,
+}
+
+impl std::fmt::Display for TrailFallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is not supported by the adapter, using {}",
+            self.requested.name(),
+            self.used.name()
+        )
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            agents: 600000,
+            spawn: Spawn::Disc { radius: 0.33 },
+            seed: None,
+            trail_format: TrailFormat::Rgba32Float,
+            behaviour: Behaviour::default(),
+            rules: None,
+            lifecycle: None,
+            environment: Environment::default(),
+            boundary: Boundary::default(),
+            palette: Palette::default(),
+            post: post::default_chain(),
+            sprites: None,
+            timeline: Timeline::default(),
+        }
+    }
+}
+
+impl Default for Behaviour {
+    fn default() -> Self {
+        Self {
+            sensor_angle: 0.8,
+            sensor_distance: 24.0,
+            turn_rate: 0.01,
+            decay: 1.0,
+        }
+    }
+}
+
+impl Default for Sprites {
+    fn default() -> Self {
+        Self {
+            shape: SpriteShape::default(),
+            size: 2.0,
+            opacity: 0.5,
+            length: 0.5,
+            hide_trail: false,
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            source: ColorSource::default(),
+            gradient: Gradient::default(),
+            background: [0; 3],
+            range: 1.0,
+        }
+    }
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            metabolism: 0.1,
+            feeding: 0.4,
+            reproduction_energy: 2.0,
+            reproduction_trail: 0.5,
+            max_age: 60.0,
+            mutation: 0.05,
+        }
+    }
+}
+
+impl Default for Food {
+    fn default() -> Self {
+        Self {
+            position: glam::Vec2::splat(0.5),
+            radius: 0.05,
+            rate: 4.0,
+            velocity: glam::Vec2::ZERO,
+        }
+    }
+}
+
+impl Default for Attractor {
+    fn default() -> Self {
+        Self {
+            position: glam::Vec2::splat(0.5),
+            radius: 0.2,
+            strength: 1.0,
+        }
+    }
+}
+
+impl Scene {
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut scene: Scene = load_ron(path)?;
+
+        if let (Some(mask), Some(dir)) = (&mut scene.environment.mask, path.parent()) {
+            mask.path = dir.join(&mask.path);
+        }
+        if let (Some(rules), Some(dir)) = (&mut scene.rules, path.parent()) {
+            *rules = dir.join(&*rules);
+        }
+
+        Ok(scene)
+    }
+
+    /// Sets animated parameters to their values `time` seconds into the piece.
+    pub fn animate(&mut self, time: f32) {
+        let sample = self.timeline.sample(time);
+        for (parameter, value) in sample.values {
+            *parameter.value_mut(self) = value;
+        }
+
+        if let Some(gradient) = sample.gradient {
+            self.palette.gradient = gradient;
+        }
+    }
+
+    /// Replaces the trail format with the most precise supported one if the
+    /// adapter can't use it, and returns the replacement for the caller to
+    /// report.
+    pub fn resolve_trail_format(
+        &mut self,
+        supported: &[TrailFormat],
+    ) -> Result<Option<TrailFallback>> {
+        if supported.contains(&self.trail_format) {
+            return Ok(None);
+        }
+
+        let fallback = TrailFallback {
+            requested: self.trail_format,
+            used: *supported.first().ok_or(Error::NoTrailFormat)?,
+        };
+        self.trail_format = fallback.used;
+        Ok(Some(fallback))
+    }
+}
+
+impl Environment {
+    /// Removes the obstacle under `p`, or otherwise the closest point feature
+    /// within `max_distance`. Returns whether anything was removed.
+    pub fn remove_at(&mut self, p: glam::Vec2, aspect: glam::Vec2, max_distance: f32) -> bool {
+        if let Some(i) = self.obstacles.iter().rposition(|o| o.contains(p, aspect)) {
+            self.obstacles.remove(i);
+            return true;
+        }
+
+        enum Index {
+            Food(usize),
+            Attractor(usize),
+            Field(usize),
+        }
+
+        let food = self.food.iter().enumerate();
+        let attractors = self.attractors.iter().enumerate();
+        let vortices = self.fields.iter().enumerate().filter_map(|(i, f)| match f {
+            Field::Vortex { center, .. } => Some((Index::Field(i), *center)),
+            Field::Wind { .. } => None,
+        });
+
+        let closest = food
+            .map(|(i, f)| (Index::Food(i), f.position))
+            .chain(attractors.map(|(i, a)| (Index::Attractor(i), a.position)))
+            .chain(vortices)
+            .map(|(index, position)| (index, ((position - p) * aspect).length()))
+            .filter(|(_, distance)| *distance <= max_distance)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        match closest {
+            Some((Index::Food(i), _)) => {
+                self.food.remove(i);
+            }
+            Some((Index::Attractor(i), _)) => {
+                self.attractors.remove(i);
+            }
+            Some((Index::Field(i), _)) => {
+                self.fields.remove(i);
+            }
+            None => return false,
+        }
+
+        true
+    }
+}
+
+impl Palette {
+    /// Loads a palette saved on its own, to reuse it across scenes.
+    pub fn load(path: &Path) -> Result<Self> {
+        load_ron(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        save_ron(self, path)
+    }
+}
+
+impl ColorSource {
+    pub const ALL: [ColorSource; 9] = [
+        ColorSource::Intensity,
+        ColorSource::Index,
+        ColorSource::Species,
+        ColorSource::Speed,
+        ColorSource::Heading,
+        ColorSource::Age,
+        ColorSource::Energy,
+        ColorSource::Density,
+        ColorSource::Spawn,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorSource::Intensity => "Intensity",
+            ColorSource::Index => "Index",
+            ColorSource::Species => "Species",
+            ColorSource::Speed => "Speed",
+            ColorSource::Heading => "Heading",
+            ColorSource::Age => "Age",
+            ColorSource::Energy => "Energy",
+            ColorSource::Density => "Density",
+            ColorSource::Spawn => "Spawn position",
+        }
+    }
+}
+
+impl SpriteShape {
+    pub const ALL: [SpriteShape; 3] = [SpriteShape::Point, SpriteShape::Quad, SpriteShape::Line];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpriteShape::Point => "Point",
+            SpriteShape::Quad => "Quad",
+            SpriteShape::Line => "Line",
+        }
+    }
+}
+
+impl Boundary {
+    pub const ALL: [Boundary; 4] = [
+        Boundary::Wrap,
+        Boundary::Reflect,
+        Boundary::Respawn,
+        Boundary::Circle,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Boundary::Wrap => "Wrap",
+            Boundary::Reflect => "Reflect",
+            Boundary::Respawn => "Respawn",
+            Boundary::Circle => "Circle",
+        }
+    }
+}
+
+impl Mask {
+    fn default_strength() -> f32 {
+        1.0
+    }
+
+    /// Brightness of the image, inverted if asked, with its bottom row
+    /// first like the domain.
+    pub fn image(&self) -> Result<image::GrayImage> {
+        let image = image::open(&self.path).map_err(|source| Error::Image {
+            path: self.path.clone(),
+            source,
+        })?;
+
+        // Images are stored top down, the domain is bottom up
+        let mut luma = image::imageops::flip_vertical(&image.to_luma8());
+        if self.invert {
+            image::imageops::invert(&mut luma);
+        }
+
+        Ok(luma)
+    }
+}
+
+impl MaskMode {
+    pub const ALL: [MaskMode; 4] = [
+        MaskMode::Attractor,
+        MaskMode::Speed,
+        MaskMode::Deposit,
+        MaskMode::Obstacle,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MaskMode::Attractor => "Attractor",
+            MaskMode::Speed => "Speed",
+            MaskMode::Deposit => "Deposit",
+            MaskMode::Obstacle => "Obstacle",
+        }
+    }
+}
+
+impl MaskFit {
+    pub const ALL: [MaskFit; 3] = [MaskFit::Stretch, MaskFit::Fit, MaskFit::Tile];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MaskFit::Stretch => "Stretch",
+            MaskFit::Fit => "Fit",
+            MaskFit::Tile => "Tile",
+        }
+    }
+}
+
+impl Obstacle {
+    /// Whether the domain fraction `p` lies inside, on a domain with the given aspect ratio.
+    pub fn contains(&self, p: glam::Vec2, aspect: glam::Vec2) -> bool {
+        match *self {
+            Obstacle::Circle { center, radius } => ((p - center) * aspect).length() <= radius,
+            Obstacle::Rect { min, max } => p.cmpge(min).all() && p.cmple(max).all(),
+        }
+    }
+}
+
+impl Spawn {
+    pub const ALL: [Spawn; 4] = [
+        Spawn::Disc { radius: 0.33 },
+        Spawn::Ring { radius: 0.33 },
+        Spawn::Point,
+        Spawn::Uniform,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Spawn::Disc { .. } => "Disc",
+            Spawn::Ring { .. } => "Ring",
+            Spawn::Point => "Point",
+            Spawn::Uniform => "Uniform",
+        }
+    }
+}
+
+/// Reads a value from a RON file.
+pub(crate) fn load_ron<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
+    let source = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    ron::from_str(&source).map_err(|source| Error::Parse {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+/// Writes a value to a RON file, formatted to be edited by hand.
+pub(crate) fn save_ron<T: Serialize>(value: &T, path: &Path) -> Result<()> {
+    let source = ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())?;
+    fs::write(path, source).map_err(|e| Error::io(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn environment() -> Environment {
+        Environment {
+            food: vec![Food {
+                position: glam::vec2(0.2, 0.5),
+                ..Default::default()
+            }],
+            obstacles: vec![Obstacle::Rect {
+                min: glam::vec2(0.4, 0.4),
+                max: glam::vec2(0.6, 0.6),
+            }],
+            attractors: vec![Attractor {
+                position: glam::vec2(0.3, 0.5),
+                ..Default::default()
+            }],
+            fields: vec![
+                Field::Wind {
+                    velocity: glam::Vec2::X,
+                },
+                Field::Vortex {
+                    center: glam::vec2(0.8, 0.5),
+                    strength: 1.0,
+                },
+            ],
+            mask: None,
+        }
+    }
+
+    #[test]
+    fn obstacles_under_the_point_are_removed_first() {
+        let mut environment = environment();
+        assert!(environment.remove_at(glam::vec2(0.45, 0.5), glam::Vec2::ONE, 1.0));
+        assert!(environment.obstacles.is_empty());
+        assert_eq!(environment.food.len(), 1);
+        assert_eq!(environment.attractors.len(), 1);
+    }
+
+    #[test]
+    fn the_closest_point_feature_is_removed() {
+        let mut environment = environment();
+        assert!(environment.remove_at(glam::vec2(0.26, 0.5), glam::Vec2::ONE, 0.1));
+        assert_eq!(environment.food.len(), 1);
+        assert!(environment.attractors.is_empty());
+
+        assert!(environment.remove_at(glam::vec2(0.75, 0.5), glam::Vec2::ONE, 0.1));
+        assert_eq!(
+            environment.fields,
+            [Field::Wind {
+                velocity: glam::Vec2::X
+            }]
+        );
+    }
+
+    #[test]
+    fn features_further_than_the_distance_are_kept() {
+        let mut environment = environment();
+        assert!(!environment.remove_at(glam::vec2(0.2, 0.9), glam::Vec2::ONE, 0.1));
+        // Distances are measured in units of the shorter side
+        assert!(!environment.remove_at(glam::vec2(0.15, 0.5), glam::vec2(4.0, 1.0), 0.1));
+        assert_eq!(environment, self::environment());
+    }
+}
@@ -0,0 +1,111 @@
+//! Reading rendered images back from the GPU.
+
+use std::path::Path;
+
+use crate::{
+    camera::Camera,
+    error::{Error, Result},
+    renderer::Renderer,
+    simulation::Simulation,
+};
+
+/// Format screenshots are rendered in, renderers passed to [`capture`] must target it.
+pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Which part of the simulation a screenshot shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum View {
+    /// What the camera currently shows, at the window's size
+    Camera,
+    /// The whole domain, one pixel per simulated pixel
+    Domain,
+}
+
+impl View {
+    pub const ALL: [View; 2] = [View::Camera, View::Domain];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            View::Camera => "Camera",
+            View::Domain => "Full domain",
+        }
+    }
+}
+
+/// Renders the trail map into an image of `size`, waiting for the GPU to finish.
+pub fn capture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    renderer: &mut Renderer,
+    simulation: &Simulation,
+    camera: &Camera,
+    size: glam::UVec2,
+) -> Result<image::RgbaImage> {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Screenshot target"),
+        size: wgpu::Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    });
+
+    // Rows of a texture copy have to be aligned
+    let row_bytes = size.x * 4;
+    let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Screenshot readback"),
+        size: padded_row_bytes as u64 * size.y as u64,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Screenshot encoder"),
+    });
+
+    renderer.draw(device, &mut encoder, &texture, simulation, camera)?;
+
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes),
+                rows_per_image: None,
+            },
+        },
+        texture.size(),
+    );
+
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::Maintain::Wait);
+
+    let pixels: Vec<u8> = slice
+        .get_mapped_range()
+        .chunks(padded_row_bytes as usize)
+        .flat_map(|row| &row[..row_bytes as usize])
+        .copied()
+        .collect();
+    buffer.unmap();
+
+    image::RgbaImage::from_raw(size.x, size.y, pixels)
+        .ok_or_else(|| Error::Gpu("the screenshot read back is smaller than the image".to_owned()))
+}
+
+pub fn save(image: &image::RgbaImage, path: &Path) -> Result<()> {
+    image.save(path).map_err(|source| Error::Image {
+        path: path.to_owned(),
+        source,
+    })
+}
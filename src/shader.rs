@@ -0,0 +1,535 @@
+//! WGSL preprocessing of the shaders in `src/shader`, keeping track of where
+//! every line came from so that errors point at the original file and line.
+//!
+//! Lines starting with `#` are directives:
+//!
+//! - `#include "path"` inserts a file, once per shader however often it is
+//!   included. Paths are relative to `src/shader`.
+//! - `#define NAME value` replaces the identifier `NAME` with `value` in the
+//!   lines that follow, and `#undef NAME` stops replacing it. Rust code
+//!   passes further definitions when preprocessing.
+//! - `#ifdef NAME` and `#ifndef NAME`, with an optional `#else`, keep or
+//!   drop the lines up to the matching `#endif`.
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+
+use crate::{
+    error::{Error, Result},
+    pipeline::bindings::Bindings,
+    storage,
+};
+
+/// Built-in shader files, by their path relative to `src/shader`. Besides
+/// these, `storage.wgsl` declares the structs generated from `storage.rs`.
+const FILES: &[(&str, &str)] = &[
+    ("boundary.wgsl", include_str!("shader/boundary.wgsl")),
+    ("brush.wgsl", include_str!("shader/brush.wgsl")),
+    ("diffuse.wgsl", include_str!("shader/diffuse.wgsl")),
+    ("environment.wgsl", include_str!("shader/environment.wgsl")),
+    ("hooks.wgsl", include_str!("shader/hooks.wgsl")),
+    ("lifecycle.wgsl", include_str!("shader/lifecycle.wgsl")),
+    ("palette.wgsl", include_str!("shader/palette.wgsl")),
+    ("post.wgsl", include_str!("shader/post.wgsl")),
+    ("prelude.wgsl", include_str!("shader/prelude.wgsl")),
+    ("render.wgsl", include_str!("shader/render.wgsl")),
+    ("resize.wgsl", include_str!("shader/resize.wgsl")),
+    ("simulation.wgsl", include_str!("shader/simulation.wgsl")),
+    ("sprite.wgsl", include_str!("shader/sprite.wgsl")),
+    ("trail/color.wgsl", include_str!("shader/trail/color.wgsl")),
+    (
+        "trail/compute.wgsl",
+        include_str!("shader/trail/compute.wgsl"),
+    ),
+    (
+        "trail/intensity.wgsl",
+        include_str!("shader/trail/intensity.wgsl"),
+    ),
+    (
+        "trail/intensity_color.wgsl",
+        include_str!("shader/trail/intensity_color.wgsl"),
+    ),
+    (
+        "trail/ping_pong.wgsl",
+        include_str!("shader/trail/ping_pong.wgsl"),
+    ),
+    (
+        "trail/read_write.wgsl",
+        include_str!("shader/trail/read_write.wgsl"),
+    ),
+    ("trail/rgba.wgsl", include_str!("shader/trail/rgba.wgsl")),
+    (
+        "trail/rgba_color.wgsl",
+        include_str!("shader/trail/rgba_color.wgsl"),
+    ),
+    (
+        "trail/species.wgsl",
+        include_str!("shader/trail/species.wgsl"),
+    ),
+    (
+        "trail/species_color.wgsl",
+        include_str!("shader/trail/species_color.wgsl"),
+    ),
+];
+
+/// Names defined before preprocessing, and what they are replaced with.
+/// Flags only tested by `#ifdef` are defined as empty.
+pub(crate) type Defines<'a> = [(&'a str, &'a str)];
+
+/// File and line a line of preprocessed source came from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Origin {
+    pub file: String,
+    /// 1-based
+    pub line: usize,
+}
+
+/// Preprocessed WGSL, with the origin of every line.
+#[derive(Clone, Debug)]
+pub(crate) struct Shader {
+    /// File preprocessing started at
+    path: String,
+    source: String,
+    origins: Vec<Origin>,
+}
+
+/// A shader module on the device, with the bindings reflected from it.
+pub(crate) struct Module {
+    pub module: wgpu::ShaderModule,
+    pub bindings: Bindings,
+}
+
+impl Origin {
+    fn error(&self, message: impl Into<String>) -> Error {
+        Error::ShaderSource {
+            file: self.file.clone(),
+            line: self.line,
+            message: message.into(),
+        }
+    }
+}
+
+impl Shader {
+    /// Preprocesses the built-in shader at `path`.
+    pub fn builtin(path: &str, defines: &Defines) -> Result<Self> {
+        let source = builtin_file(path)
+            .ok_or_else(|| Error::Shader(format!("{} is not a built-in shader", path)))?;
+        Self::preprocess(path, &source, defines, &builtin_file)
+    }
+
+    /// Preprocesses `source`, read from `path`, looking up the files it
+    /// includes with `read`.
+    pub fn preprocess(
+        path: &str,
+        source: &str,
+        defines: &Defines,
+        read: &dyn Fn(&str) -> Option<Cow<'static, str>>,
+    ) -> Result<Self> {
+        let mut preprocessor = Preprocessor {
+            read,
+            defines: defines
+                .iter()
+                .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+                .collect(),
+            included: HashSet::from([path.to_owned()]),
+            shader: Shader {
+                path: path.to_owned(),
+                source: String::new(),
+                origins: Vec::new(),
+            },
+        };
+
+        preprocessor.file(path, source)?;
+        Ok(preprocessor.shader)
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Where the 1-based `line` of the preprocessed source came from.
+    pub fn origin(&self, line: usize) -> Option<&Origin> {
+        self.origins.get(line.checked_sub(1)?)
+    }
+
+    /// Parses and validates the shader with naga, reporting errors at the
+    /// line they are on before preprocessing, and checks that the structs
+    /// shared with Rust are laid out alike.
+    pub fn validate(&self) -> Result<(naga::Module, naga::valid::ModuleInfo)> {
+        let module = naga::front::wgsl::parse_str(&self.source).map_err(|error| {
+            let mut message = error.message().to_owned();
+            if let Some((_, label)) = error.labels().next().filter(|(_, l)| !l.is_empty()) {
+                message = format!("{}: {}", message, label);
+            }
+
+            self.error(error.location(&self.source), message)
+        })?;
+
+        let info = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::all(),
+        )
+        .validate(&module)
+        .map_err(|error| {
+            let location = error.location(&self.source);
+            let error = error.into_inner();
+
+            // Validation errors nest, down to the expression at fault
+            let mut message = error.to_string();
+            let mut source = std::error::Error::source(&error);
+            while let Some(cause) = source {
+                message = format!("{}: {}", message, cause);
+                source = cause.source();
+            }
+
+            self.error(location, message)
+        })?;
+
+        storage::check_layouts(&self.path, &module)?;
+        Ok((module, info))
+    }
+
+    /// Validates the shader and creates a module of it on `device`, along
+    /// with the layout of its bindings.
+    pub fn create_module(&self, device: &wgpu::Device) -> Result<Module> {
+        let (module, info) = self.validate()?;
+        let bindings = Bindings::new(device, &self.path, &module, &info)?;
+
+        Ok(Module {
+            module: device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(&self.path),
+                source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(self.source())),
+            }),
+            bindings,
+        })
+    }
+
+    /// Error at the original line of `location` in the preprocessed source.
+    fn error(&self, location: Option<naga::SourceLocation>, message: String) -> Error {
+        match location.and_then(|location| self.origin(location.line_number as usize)) {
+            Some(origin) => origin.error(message),
+            None => Error::Shader(format!("{}: {}", self.path, message)),
+        }
+    }
+}
+
+/// Shader module of the built-in shader at `path`. Built-in shaders are
+/// checked by the tests for every set of definitions they are used with,
+/// so an error here is a bug, reported with the line to fix.
+pub(crate) fn module(device: &wgpu::Device, path: &str, defines: &Defines) -> Result<Module> {
+    Shader::builtin(path, defines).and_then(|shader| shader.create_module(device))
+}
+
+/// Source of the built-in file at `path`, for looking up includes.
+pub(crate) fn builtin_file(path: &str) -> Option<Cow<'static, str>> {
+    if path == "storage.wgsl" {
+        return Some(Cow::Owned(storage::declarations()));
+    }
+
+    FILES
+        .iter()
+        .find(|(name, _)| *name == path)
+        .map(|(_, source)| Cow::Borrowed(*source))
+}
+
+struct Preprocessor<'a> {
+    read: &'a dyn Fn(&str) -> Option<Cow<'static, str>>,
+    defines: HashMap<String, String>,
+    /// Files already inserted, which are skipped when included again
+    included: HashSet<String>,
+    shader: Shader,
+}
+
+/// An `#ifdef` or `#ifndef` that hasn't been closed yet.
+struct Conditional {
+    /// Whether lines are kept, flipped by `#else`
+    keep: bool,
+    seen_else: bool,
+    origin: Origin,
+}
+
+impl Preprocessor<'_> {
+    fn file(&mut self, path: &str, source: &str) -> Result<()> {
+        let mut conditionals: Vec<Conditional> = Vec::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let origin = Origin {
+                file: path.to_owned(),
+                line: index + 1,
+            };
+            let keep = conditionals.iter().all(|c| c.keep);
+
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if keep {
+                    let line = substitute(line, &self.defines);
+                    self.shader.source.push_str(&line);
+                    self.shader.source.push('\n');
+                    self.shader.origins.push(origin);
+                }
+                continue;
+            };
+
+            let (name, argument) = directive
+                .split_once(char::is_whitespace)
+                .map_or((directive, ""), |(name, argument)| (name, argument.trim()));
+
+            match name {
+                "ifdef" | "ifndef" => {
+                    let defined = self.defines.contains_key(identifier(argument, &origin)?);
+                    conditionals.push(Conditional {
+                        keep: defined == (name == "ifdef"),
+                        seen_else: false,
+                        origin,
+                    });
+                }
+                "else" => {
+                    let conditional = conditionals
+                        .last_mut()
+                        .ok_or_else(|| origin.error("#else without #ifdef"))?;
+                    if conditional.seen_else {
+                        return Err(origin.error("second #else for the same #ifdef"));
+                    }
+
+                    conditional.keep = !conditional.keep;
+                    conditional.seen_else = true;
+                }
+                "endif" => {
+                    conditionals
+                        .pop()
+                        .ok_or_else(|| origin.error("#endif without #ifdef"))?;
+                }
+                // Other directives only apply to lines that are kept
+                _ if !keep => {}
+                "include" => {
+                    let target = argument
+                        .strip_prefix('"')
+                        .and_then(|a| a.strip_suffix('"'))
+                        .ok_or_else(|| origin.error("expected a quoted path after #include"))?;
+                    if self.included.insert(target.to_owned()) {
+                        let source = (self.read)(target)
+                            .ok_or_else(|| origin.error(format!("no shader named {}", target)))?;
+                        self.file(target, &source)?;
+                    }
+                }
+                "define" => {
+                    let (name, value) = argument
+                        .split_once(char::is_whitespace)
+                        .map_or((argument, ""), |(name, value)| (name, value.trim()));
+                    let name = identifier(name, &origin)?;
+                    self.defines.insert(name.to_owned(), value.to_owned());
+                }
+                "undef" => {
+                    self.defines.remove(identifier(argument, &origin)?);
+                }
+                _ => return Err(origin.error(format!("unknown directive #{}", name))),
+            }
+        }
+
+        match conditionals.first() {
+            Some(conditional) => Err(conditional.origin.error("#ifdef without #endif")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `argument` if it is a single identifier.
+fn identifier<'a>(argument: &'a str, origin: &Origin) -> Result<&'a str> {
+    let mut chars = argument.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(origin.error(format!("expected a name, found \"{}\"", argument)));
+    }
+
+    Ok(argument)
+}
+
+/// `line` with every defined identifier replaced by its value.
+fn substitute<'a>(line: &'a str, defines: &HashMap<String, String>) -> Cow<'a, str> {
+    if defines.is_empty() {
+        return Cow::Borrowed(line);
+    }
+
+    let mut result = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        let word = &rest[..end];
+        result.push_str(defines.get(word).map_or(word, String::as_str));
+        rest = &rest[end..];
+    }
+    result.push_str(rest);
+
+    Cow::Owned(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trail::{TrailAccess, TrailFormat};
+
+    fn files(
+        files: &'static [(&'static str, &'static str)],
+    ) -> impl Fn(&str) -> Option<Cow<'static, str>> {
+        move |path| {
+            files
+                .iter()
+                .find(|(name, _)| *name == path)
+                .map(|(_, source)| Cow::Borrowed(*source))
+        }
+    }
+
+    fn origin(file: &str, line: usize) -> Origin {
+        Origin {
+            file: file.to_owned(),
+            line,
+        }
+    }
+
+    #[test]
+    fn includes_are_inserted_once() {
+        let read = files(&[
+            ("a.wgsl", "const A = 1;\n#include \"b.wgsl\""),
+            ("b.wgsl", "#include \"a.wgsl\"\nconst B = 2;"),
+        ]);
+        let source = "#include \"a.wgsl\"\n#include \"b.wgsl\"\nconst C = 3;";
+        let shader = Shader::preprocess("main.wgsl", source, &[], &read).unwrap();
+
+        assert_eq!(
+            shader.source(),
+            "const A = 1;\nconst B = 2;\nconst C = 3;\n"
+        );
+        assert_eq!(shader.origin(1), Some(&origin("a.wgsl", 1)));
+        assert_eq!(shader.origin(2), Some(&origin("b.wgsl", 2)));
+        assert_eq!(shader.origin(3), Some(&origin("main.wgsl", 3)));
+    }
+
+    #[test]
+    fn defines_replace_whole_identifiers() {
+        let source = "#define SIZE 8u\nvar<private> a: array<f32, SIZE>;\nconst SIZE_X = SIZE;\n#undef SIZE\nconst B = SIZE;";
+        let shader =
+            Shader::preprocess("main.wgsl", source, &[("FORMAT", "r32float")], &files(&[]))
+                .unwrap();
+
+        assert_eq!(
+            shader.source(),
+            "var<private> a: array<f32, 8u>;\nconst SIZE_X = 8u;\nconst B = SIZE;\n"
+        );
+
+        let shader = Shader::preprocess(
+            "main.wgsl",
+            "FORMAT",
+            &[("FORMAT", "r32float")],
+            &files(&[]),
+        )
+        .unwrap();
+        assert_eq!(shader.source(), "r32float\n");
+    }
+
+    #[test]
+    fn conditionals_keep_one_branch() {
+        let source = "#ifdef A\na\n#ifndef B\nnot b\n#else\nb\n#endif\n#else\nnot a\n#include \"missing.wgsl\"\n#endif";
+        let run = |defines: &Defines| {
+            Shader::preprocess("main.wgsl", source, defines, &files(&[]))
+                .unwrap()
+                .source()
+                .to_owned()
+        };
+
+        assert_eq!(run(&[("A", "")]), "a\nnot b\n");
+        assert_eq!(run(&[("A", ""), ("B", "")]), "a\nb\n");
+        assert!(matches!(
+            Shader::preprocess("main.wgsl", source, &[], &files(&[])),
+            Err(Error::ShaderSource { line: 10, .. })
+        ));
+    }
+
+    #[test]
+    fn errors_point_at_the_original_line() {
+        let error = |source| Shader::preprocess("main.wgsl", source, &[], &files(&[])).unwrap_err();
+        let at = |error: Error| match error {
+            Error::ShaderSource { file, line, .. } => Some((file, line)),
+            _ => None,
+        };
+
+        assert_eq!(at(error("\n#ifdef A\n")), Some(("main.wgsl".to_owned(), 2)));
+        assert_eq!(at(error("#endif")), Some(("main.wgsl".to_owned(), 1)));
+        assert_eq!(
+            at(error("\n\n#pragma once")),
+            Some(("main.wgsl".to_owned(), 3))
+        );
+
+        let read = files(&[(
+            "util.wgsl",
+            "fn f() -> f32 {\n    return 1.0;\n}\n\nfn g() -> f32 {\n    return h();\n}",
+        )]);
+        let shader = Shader::preprocess(
+            "main.wgsl",
+            "const A = 1;\n#include \"util.wgsl\"",
+            &[],
+            &read,
+        )
+        .unwrap();
+        assert_eq!(
+            at(shader.validate().unwrap_err()),
+            Some(("util.wgsl".to_owned(), 6))
+        );
+    }
+
+    #[test]
+    fn layouts_are_checked_against_rust() {
+        let check = |source| {
+            Shader::preprocess("main.wgsl", source, &[], &builtin_file)
+                .and_then(|shader| shader.validate())
+                .map(|_| ())
+        };
+
+        assert!(check("#include \"storage.wgsl\"").is_ok());
+        assert!(matches!(
+            check("struct Camera {\n    scale: vec2<f32>,\n    offset: vec2<f32>,\n}"),
+            Err(Error::Layout { name, .. }) if name == "Camera"
+        ));
+        assert!(matches!(
+            check("struct Palette {\n    background: vec3<f32>,\n    source: u32,\n    range: f32,\n}"),
+            Err(Error::Layout { name, .. }) if name == "Palette"
+        ));
+    }
+
+    #[test]
+    fn builtin_shaders_validate() {
+        let mut shaders = vec![
+            ("lifecycle.wgsl", Vec::new()),
+            ("post.wgsl", Vec::new()),
+            ("resize.wgsl", Vec::new()),
+            ("sprite.wgsl", Vec::new()),
+        ];
+        for format in TrailFormat::ALL {
+            shaders.push(("render.wgsl", format.defines().to_vec()));
+            for access in [TrailAccess::ReadWrite, TrailAccess::PingPong] {
+                let defines = [&format.defines()[..], &[access.define()]].concat();
+                for path in ["diffuse.wgsl", "simulation.wgsl", "brush.wgsl"] {
+                    shaders.push((path, defines.clone()));
+                }
+            }
+        }
+
+        let errors: Vec<String> = shaders
+            .into_iter()
+            .filter_map(|(path, defines)| {
+                let error = Shader::builtin(path, &defines)
+                    .and_then(|s| s.validate())
+                    .err()?;
+                Some(format!("{} with {:?}: {}", path, defines, error))
+            })
+            .collect();
+        assert!(errors.is_empty(), "{}", errors.join("\n"));
+    }
+}
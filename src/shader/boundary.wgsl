@@ -0,0 +1,41 @@
+// ====================== Boundaries ======================
+//
+// How the edges of the domain behave, shared by agent motion and diffusion.
+
+const BOUNDARY_WRAP = 0u;
+const BOUNDARY_REFLECT = 1u;
+const BOUNDARY_RESPAWN = 2u;
+const BOUNDARY_CIRCLE = 3u;
+
+struct BoundaryPoint {
+    p: vec2<f32>,
+    // False for points outside a domain that doesn't wrap or reflect
+    inside: bool,
+}
+
+// Maps a point onto the domain
+fn boundary_point(p: vec2<f32>, size: vec2<f32>, mode: u32) -> BoundaryPoint {
+    if mode == BOUNDARY_WRAP {
+        return BoundaryPoint(p - floor(p / size) * size, true);
+    }
+
+    if mode == BOUNDARY_REFLECT {
+        let m = p - floor(p / (2.0 * size)) * 2.0 * size;
+        // A point on the far edge reflects onto it, so it is moved to the
+        // largest value below the edge to stay inside
+        let below = bitcast<vec2<f32>>(bitcast<vec2<u32>>(size) - 1u);
+        return BoundaryPoint(min(select(m, 2.0 * size - m, m >= size), below), true);
+    }
+
+    if mode == BOUNDARY_CIRCLE {
+        let radius = 0.5 * min(size.x, size.y);
+        return BoundaryPoint(p, length(p - 0.5 * size) <= radius);
+    }
+
+    return BoundaryPoint(p, all(p >= vec2<f32>(0.0)) && all(p < size));
+}
+
+// Texel of a point mapped onto the domain
+fn boundary_texel(p: BoundaryPoint, size: vec2<f32>) -> vec2<u32> {
+    return min(vec2<u32>(max(p.p, vec2<f32>(0.0))), vec2<u32>(size) - 1u);
+}
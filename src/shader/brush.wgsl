@@ -0,0 +1,92 @@
+#include "prelude.wgsl"
+#include "trail/compute.wgsl"
+
+// ======================== Structs =======================
+
+// ========================= Utils ========================
+
+// Brush kinds, must match `brush.rs`
+const BRUSH_PAINT = 0u;
+const BRUSH_ERASE = 1u;
+const BRUSH_PUSH = 2u;
+const BRUSH_PULL = 3u;
+
+// Trail intensity painted per second at full strength
+const PAINT_RATE = 4.0;
+
+// Brush radii agents are pushed per second at full strength
+const PUSH_RATE = 2.0;
+
+// Strength at a point, fading out over the outer `falloff` fraction of the radius
+fn brush_weight(p: vec2<f32>) -> f32 {
+    let distance = length(p - brush.position) / brush.radius;
+    if distance >= 1.0 {
+        return 0.0;
+    }
+
+    return brush.strength * (1.0 - smoothstep(1.0 - brush.falloff, 1.0, distance));
+}
+
+// ========================= Main =========================
+
+@group(0) @binding(0)
+var<uniform> brush: Brush;
+
+@group(0) @binding(1)
+var<storage, read_write> agents_buffer: AgentBuffer;
+
+// Paints into or erases from the trail, over the bounding box of the brush
+@compute
+@workgroup_size(8, 8, 1)
+fn paint(
+    @builtin(global_invocation_id) g_invocation_id: vec3<u32>
+) {
+    let p = brush.origin + g_invocation_id.xy;
+    if any(p >= trail_dimensions()) {
+        return;
+    }
+
+    let amount = brush_weight(vec2<f32>(p) + 0.5) * brush.dt * PAINT_RATE;
+    if amount <= 0.0 {
+        return;
+    }
+
+    var value = trail_load(p);
+    if brush.kind == BRUSH_ERASE {
+        value *= max(0.0, 1.0 - amount);
+    } else {
+        value += vec4<f32>(amount);
+    }
+
+    trail_store(p, value);
+}
+
+// Moves live agents away from or towards the brush
+@compute
+@workgroup_size(64, 1, 1)
+fn push(
+    @builtin(global_invocation_id) g_invocation_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    let slot = g_invocation_id.x + g_invocation_id.y * num_workgroups.x * 64u;
+    if slot >= arrayLength(&agents_buffer.agents) {
+        return;
+    }
+
+    let agent = &agents_buffer.agents[slot];
+    if (*agent).alive == 0u {
+        return;
+    }
+
+    let offset = (*agent).position - brush.position;
+    let weight = brush_weight((*agent).position);
+    if weight <= 0.0 || length(offset) == 0.0 {
+        return;
+    }
+
+    let direction = normalize(offset) * select(-1.0, 1.0, brush.kind == BRUSH_PUSH);
+    let step = direction * weight * brush.radius * PUSH_RATE * brush.dt;
+
+    // Don't overshoot the centre when pulling
+    (*agent).position += select(step, -offset, brush.kind == BRUSH_PULL && length(step) > length(offset));
+}
@@ -1,24 +1,20 @@
 
-// ======================== Structs =======================
-
-struct Globals {
-    dt: f32,
-}
+#include "prelude.wgsl"
+#include "trail/compute.wgsl"
+#include "boundary.wgsl"
+#include "environment.wgsl"
 
 // ========================= Main =========================
 
 @group(0) @binding(0)
 var<uniform> globals: Globals;
 
-@group(0) @binding(2)
-var tex: texture_storage_2d<rgba32float, read_write>;
-
 @compute
 @workgroup_size(1, 1, 1)
 fn main(
     @builtin(global_invocation_id) g_invocation_id: vec3<u32>
 ) {
-    let dimensions = textureDimensions(tex);
+    let dimensions = trail_dimensions();
     let aspect_ratio = vec2<f32>(f32(dimensions.x) / f32(dimensions.y), 1.0);
     
     let uv = vec2<f32>(
@@ -26,33 +22,54 @@ fn main(
         f32(g_invocation_id.y) / f32(dimensions.y)
     );
 
-    var color = textureLoad(tex, g_invocation_id.xy);
+    let size = vec2<f32>(dimensions);
+
+    // Nothing diffuses into obstacles or outside the arena
+    let center = boundary_point(vec2<f32>(g_invocation_id.xy) + 0.5, size, globals.boundary);
+    if environment_blocked(g_invocation_id.xy) || !center.inside {
+        trail_store(g_invocation_id.xy, vec4<f32>(0.0));
+        return;
+    }
+
+    var color = trail_load(g_invocation_id.xy);
 
     // Diffuse by averaging nearby pixels
 
     var diffuse = vec4<f32>(0.0);
+    var blocked = 0.0;
     let diffuse_radius = 2;
     for (var i = -diffuse_radius; i <= diffuse_radius; i = i + 1) {
         for (var j = -diffuse_radius; j <= diffuse_radius; j = j + 1) {
-            var sample = vec2<i32>(g_invocation_id.xy) + vec2<i32>(i, j);
-            if (sample.x < 0 || sample.x >= i32(dimensions.x) || sample.y < 0 || sample.y >= i32(dimensions.y)) {
+            let offset = vec2<f32>(f32(i), f32(j));
+            let point = boundary_point(center.p + offset, size, globals.boundary);
+            if !point.inside {
                 continue;
             }
 
-            diffuse += textureLoad(tex, sample);
+            let sample = boundary_texel(point, size);
+            if environment_blocked(sample) {
+                blocked += 1.0;
+                continue;
+            }
+
+            diffuse += trail_load(sample);
         }
     }
 
     let diffuse_dimension = 2.0 * f32(diffuse_radius) + 1.0;
-    diffuse /= (diffuse_dimension * diffuse_dimension);
+    diffuse /= (diffuse_dimension * diffuse_dimension - blocked);
 
     color = mix(color, diffuse, globals.dt * 48.0);
 
     // Apply dimming
 
     if globals.dt > 0.0 {
-        color = max(vec4<f32>(0.0), color - globals.dt * 1.0);
+        color = max(vec4<f32>(0.0), color - globals.dt * globals.decay);
     }
 
-    textureStore(tex, g_invocation_id.xy, color);
+    // Food sources emit after dimming so they're never fully dimmed out
+    let food = environment_food(vec2<f32>(g_invocation_id.xy) + 0.5, globals.time);
+    color += vec4<f32>(food * globals.dt);
+
+    trail_store(g_invocation_id.xy, color);
 }
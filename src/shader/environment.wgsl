@@ -0,0 +1,82 @@
+// ===================== Environment ======================
+//
+// Food sources, attractors and flow fields in pixels, plus a mask of
+// obstacles. Features are kept in a single array and told apart by kind.
+
+const FEATURE_FOOD = 0u;
+const FEATURE_ATTRACTOR = 1u;
+const FEATURE_WIND = 2u;
+const FEATURE_VORTEX = 3u;
+
+// What agents sense inside obstacles, low enough to always turn away
+const OBSTACLE_SENSE = -1000.0;
+
+@group(0) @binding(8)
+var<storage, read> environment: FeatureBuffer;
+
+@group(0) @binding(9)
+var obstacles: texture_2d<f32>;
+
+fn environment_blocked(p: vec2<u32>) -> bool {
+    let dimensions = textureDimensions(obstacles);
+    return textureLoad(obstacles, min(p, dimensions - 1u), 0).x > 0.5;
+}
+
+// Position of a feature after moving for `time` seconds, wrapping around the edges
+fn feature_position(feature: Feature, time: f32) -> vec2<f32> {
+    let dimensions = vec2<f32>(textureDimensions(obstacles));
+    let p = feature.position + feature.velocity * time;
+    return p - floor(p / dimensions) * dimensions;
+}
+
+// Linear falloff from 1 at the centre of a feature to 0 at its radius
+fn feature_falloff(feature: Feature, p: vec2<f32>, time: f32) -> f32 {
+    let distance = length(p - feature_position(feature, time));
+    return saturate(1.0 - distance / max(feature.radius, 1.0));
+}
+
+// Trail intensity emitted by food sources per second
+fn environment_food(p: vec2<f32>, time: f32) -> f32 {
+    var food = 0.0;
+    for (var i = 0u; i < environment.count; i++) {
+        let feature = environment.features[i];
+        if feature.kind == FEATURE_FOOD {
+            food += feature.strength * feature_falloff(feature, p, time);
+        }
+    }
+
+    return food;
+}
+
+// Added to the trail that agents sense
+fn environment_sense(p: vec2<f32>, time: f32) -> f32 {
+    if environment_blocked(vec2<u32>(max(p, vec2<f32>(0.0)))) {
+        return OBSTACLE_SENSE;
+    }
+
+    var sense = 0.0;
+    for (var i = 0u; i < environment.count; i++) {
+        let feature = environment.features[i];
+        if feature.kind == FEATURE_ATTRACTOR {
+            sense += feature.strength * feature_falloff(feature, p, time);
+        }
+    }
+
+    return sense;
+}
+
+// Velocity in pixels per second that agents are carried along with
+fn environment_flow(p: vec2<f32>) -> vec2<f32> {
+    var flow = vec2<f32>(0.0);
+    for (var i = 0u; i < environment.count; i++) {
+        let feature = environment.features[i];
+        if feature.kind == FEATURE_WIND {
+            flow += feature.velocity;
+        } else if feature.kind == FEATURE_VORTEX {
+            let offset = p - feature.position;
+            flow += vec2<f32>(-offset.y, offset.x) * feature.strength;
+        }
+    }
+
+    return flow;
+}
@@ -0,0 +1,101 @@
+// ========================= Hooks ========================
+//
+// Agent rules written in WGSL replace the built-in steering and deposits of
+// the simulation pass. A rules file defines both hooks, which are called for
+// every live agent each step:
+//
+//     fn steer(agent: Agent, ctx: SenseContext) -> vec2<f32>
+//
+// returns the agent's velocity after turning, before the mask, flow fields
+// and boundary apply. Its length is the agent's speed, so rotating
+// `agent.velocity` keeps the speed the agent was spawned with.
+//
+//     fn deposit(agent: Agent) -> vec4<f32>
+//
+// returns the linear colour the agent deposits in rgb and how much trail in
+// a, 1.0 being what agents deposit without rules. It is called after the
+// agent has moved, and the colour is also what sprites are drawn in.
+//
+// Rules can use the helpers below, `sense` and `gradient` from the
+// simulation pass, and `rotate`, `hash` and `random` from the prelude.
+// `globals` holds the scene's parameters, such as `globals.time` and
+// `globals.dt` in seconds. Rules can't declare bindings of their own.
+
+// What an agent sensed this step
+struct SenseContext {
+    // Trail plus environment at the sensors, see `sense`
+    left: f32,
+    forward: f32,
+    right: f32,
+    // Offset from the agent to its forward sensor, in pixels
+    sensor: vec2<f32>,
+    // Radians between the forward sensor and each side sensor, and turned
+    // per step, both scaled by the agent's genome
+    sensor_angle: f32,
+    turn_rate: f32,
+    // Slot of the agent in the agents buffer
+    index: u32,
+    // Differs for every agent and step, copy it into a `var` for `random`
+    seed: u32,
+}
+
+// Trail of `species` at a point, 0 outside a domain that doesn't wrap or
+// reflect. The environment and mask aren't included, unlike `sense`.
+fn sample(p: vec2<f32>, species: u32) -> f32 {
+    let point = domain_point(p);
+    if !point.inside {
+        return 0.0;
+    }
+
+    return trail_sense(boundary_texel(point, vec2<f32>(trail_dimensions())), species);
+}
+
+// Average trail of `species` in a disc of `radius` pixels around a point
+fn sample_area(p: vec2<f32>, radius: f32, species: u32) -> f32 {
+    let samples = i32(radius);
+    var sum = 0.0;
+    var num_samples = 0u;
+    for (var i = -samples; i <= samples; i = i + 1) {
+        for (var j = -samples; j <= samples; j = j + 1) {
+            let offset = vec2<f32>(f32(i), f32(j));
+            let sample_uv = p + offset;
+            if i * i + j * j > samples * samples {
+                continue;
+            }
+
+            sum += sample(sample_uv, species);
+            num_samples = num_samples + 1u;
+        }
+    }
+
+    if num_samples == 0u {
+        return sample(p, species);
+    }
+
+    return sum / f32(num_samples);
+}
+
+// A point moved onto the domain, around its edges when the boundary wraps
+// and to the nearest pixel inside it otherwise
+fn warp_clamp(p: vec2<f32>) -> vec2<f32> {
+    let size = vec2<f32>(trail_dimensions());
+    if globals.boundary == BOUNDARY_WRAP {
+        return p - floor(p / size) * size;
+    }
+
+    return clamp(p, vec2<f32>(0.0), size - 0.5);
+}
+
+// Steering without rules, turning towards the stronger side unless the
+// trail is strongest ahead
+fn builtin_steer(agent: Agent, ctx: SenseContext) -> vec2<f32> {
+    if ctx.forward >= ctx.left && ctx.forward >= ctx.right {
+        return agent.velocity;
+    }
+
+    if ctx.left > ctx.right {
+        return rotate(agent.velocity, ctx.turn_rate);
+    }
+
+    return rotate(agent.velocity, -ctx.turn_rate);
+}
@@ -0,0 +1,63 @@
+#include "prelude.wgsl"
+
+// ======================== Structs =======================
+
+struct DispatchArgs {
+    x: u32,
+    y: u32,
+    z: u32,
+}
+
+// ========================= Main =========================
+
+@group(0) @binding(1)
+var<storage, read> agents_buffer: AgentBuffer;
+
+@group(0) @binding(4)
+var<storage, read_write> population: Population;
+
+@group(0) @binding(5)
+var<storage, read_write> live_indices: array<u32>;
+
+@group(0) @binding(6)
+var<storage, read_write> free_indices: array<u32>;
+
+@group(0) @binding(7)
+var<storage, read_write> dispatch: DispatchArgs;
+
+// Agents updated by each workgroup of the simulation pass
+const AGENT_WORKGROUP_SIZE = 64u;
+
+// Smallest max_compute_workgroups_per_dimension every adapter supports
+const MAX_WORKGROUPS = 65535u;
+
+// Sorts every slot of the agents buffer into the live or the free list
+@compute
+@workgroup_size(64, 1, 1)
+fn compact(
+    @builtin(global_invocation_id) g_invocation_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    let slot = g_invocation_id.x + g_invocation_id.y * num_workgroups.x * 64u;
+    if slot >= arrayLength(&agents_buffer.agents) {
+        return;
+    }
+
+    if agents_buffer.agents[slot].alive != 0u {
+        live_indices[atomicAdd(&population.live, 1u)] = slot;
+    } else {
+        free_indices[atomicAdd(&population.free, 1)] = slot;
+    }
+}
+
+// Sizes the simulation pass to the number of live agents
+@compute
+@workgroup_size(1, 1, 1)
+fn prepare_dispatch() {
+    let work_groups = (atomicLoad(&population.live) + AGENT_WORKGROUP_SIZE - 1u) / AGENT_WORKGROUP_SIZE;
+    let x = min(work_groups, MAX_WORKGROUPS);
+
+    dispatch.x = x;
+    dispatch.y = select(0u, (work_groups + x - 1u) / x, x > 0u);
+    dispatch.z = 1u;
+}
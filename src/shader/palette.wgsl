@@ -0,0 +1,24 @@
+// ======================= Palette ========================
+//
+// Gradient lookup shared by the simulation pass, which deposits colours
+// picked from it, and the render pass, which maps trail intensity through it.
+
+const COLOR_INTENSITY = 0u;
+const COLOR_INDEX = 1u;
+const COLOR_SPECIES = 2u;
+const COLOR_SPEED = 3u;
+const COLOR_HEADING = 4u;
+const COLOR_AGE = 5u;
+const COLOR_ENERGY = 6u;
+const COLOR_DENSITY = 7u;
+const COLOR_SPAWN = 8u;
+
+@group(0) @binding(11)
+var gradient_tex: texture_2d<f32>;
+
+// Linear colour of the gradient at `t`, clamped to its ends
+fn gradient(t: f32) -> vec3<f32> {
+    let last = textureDimensions(gradient_tex).x - 1u;
+    let texel = u32(saturate(t) * f32(last) + 0.5);
+    return textureLoad(gradient_tex, vec2<u32>(texel, 0u), 0).rgb;
+}
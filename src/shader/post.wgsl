@@ -0,0 +1,174 @@
+// Post-processing stages, each drawn as a fullscreen triangle reading the
+// previous stage's output. Colours are linear and unbounded until tone mapped.
+
+#include "prelude.wgsl"
+
+@group(0) @binding(0)
+var input: texture_2d<f32>;
+
+@group(0) @binding(1)
+var<uniform> params: PostParams;
+
+// Blurred highlights, only read by the bloom composite
+@group(0) @binding(2)
+var bloom_tex: texture_2d<f32>;
+
+@group(0) @binding(3)
+var bloom_sampler: sampler;
+
+const TONE_MAP_REINHARD = 0u;
+const TONE_MAP_ACES = 1u;
+const TONE_MAP_AGX = 2u;
+
+// Most texels on each side of a blur tap
+const MAX_BLUR_RADIUS = 64;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+fn load(p: vec2<i32>) -> vec3<f32> {
+    let max_texel = vec2<i32>(textureDimensions(input)) - 1;
+    return textureLoad(input, clamp(p, vec2<i32>(0), max_texel), 0).rgb;
+}
+
+fn luminance(c: vec3<f32>) -> f32 {
+    return dot(c, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+@fragment
+fn fs_copy(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return vec4<f32>(load(vec2<i32>(position.xy)), 1.0);
+}
+
+// values.x: exposure in stops
+@fragment
+fn fs_exposure(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    return vec4<f32>(load(vec2<i32>(position.xy)) * exp2(params.values.x), 1.0);
+}
+
+// Minimal AgX, see https://iolite-engine.com/blog_posts/minimal_agx_implementation
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104,
+    );
+    let outset = mat3x3<f32>(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116,
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var x = clamp(log2(max(inset * color, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    x = (x - min_ev) / (max_ev - min_ev);
+
+    // Sigmoid contrast curve
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    x = 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+
+    // Back to linear from the curve's display encoding
+    return pow(max(outset * x, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+// values.x: tone mapping operator
+@fragment
+fn fs_tone_map(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let c = load(vec2<i32>(position.xy));
+    let mapping = u32(params.values.x);
+
+    if mapping == TONE_MAP_REINHARD {
+        return vec4<f32>(c / (1.0 + c), 1.0);
+    }
+
+    if mapping == TONE_MAP_ACES {
+        // Narkowicz's fit of the ACES filmic curve
+        let mapped = (c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14);
+        return vec4<f32>(saturate(mapped), 1.0);
+    }
+
+    return vec4<f32>(agx(c), 1.0);
+}
+
+// Downsamples to half resolution, keeping what is brighter than the threshold.
+// values.x: threshold
+@fragment
+fn fs_bright(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let p = vec2<i32>(position.xy) * 2;
+    let c = 0.25 * (load(p) + load(p + vec2<i32>(1, 0)) + load(p + vec2<i32>(0, 1)) + load(p + 1));
+
+    let brightness = luminance(c);
+    let excess = max(brightness - params.values.x, 0.0);
+    return vec4<f32>(c * excess / max(brightness, 1e-5), 1.0);
+}
+
+// Separable gaussian blur.
+// values.xy: direction, values.z: standard deviation in texels
+@fragment
+fn fs_blur(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let p = vec2<i32>(position.xy);
+    let direction = vec2<i32>(params.values.xy);
+    let sigma = max(params.values.z, 0.5);
+    let radius = min(i32(ceil(2.5 * sigma)), MAX_BLUR_RADIUS);
+
+    var sum = vec3<f32>(0.0);
+    var weights = 0.0;
+    for (var i = -radius; i <= radius; i = i + 1) {
+        let x = f32(i);
+        let weight = exp(-x * x / (2.0 * sigma * sigma));
+        sum += load(p + direction * i) * weight;
+        weights += weight;
+    }
+
+    return vec4<f32>(sum / weights, 1.0);
+}
+
+// Adds the blurred highlights back.
+// values.x: intensity
+@fragment
+fn fs_bloom(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let uv = position.xy / vec2<f32>(textureDimensions(input));
+    let bloom = textureSampleLevel(bloom_tex, bloom_sampler, uv, 0.0).rgb;
+    return vec4<f32>(load(vec2<i32>(position.xy)) + bloom * params.values.x, 1.0);
+}
+
+// values.x: strength, values.y: radius the darkening starts at
+@fragment
+fn fs_vignette(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(input));
+    let offset = (position.xy / size - 0.5) * size / min(size.x, size.y);
+
+    // 1 at the corners of a square image
+    let distance = length(offset) * sqrt(2.0);
+    let darkening = smoothstep(params.values.y, 1.0 + params.values.y, distance);
+    return vec4<f32>(load(vec2<i32>(position.xy)) * (1.0 - params.values.x * darkening), 1.0);
+}
+
+// values.x: strength
+@fragment
+fn fs_grain(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let p = vec2<u32>(position.xy);
+    let noise = f32(hash(p.x ^ hash(p.y ^ hash(params.frame)))) / 4294967295.0 - 0.5;
+
+    // Stronger in the shadows, like film
+    let c = load(vec2<i32>(p));
+    let amount = params.values.x * noise * (1.0 - 0.5 * saturate(luminance(c)));
+    return vec4<f32>(max(c + amount, vec3<f32>(0.0)), 1.0);
+}
+
+// values.x: strength
+@fragment
+fn fs_sharpen(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let p = vec2<i32>(position.xy);
+    let neighbours = load(p + vec2<i32>(1, 0)) + load(p - vec2<i32>(1, 0))
+        + load(p + vec2<i32>(0, 1)) + load(p - vec2<i32>(0, 1));
+
+    let c = load(p);
+    let sharpened = c + params.values.x * (4.0 * c - neighbours);
+    return vec4<f32>(max(sharpened, vec3<f32>(0.0)), 1.0);
+}
@@ -0,0 +1,30 @@
+// ======================== Prelude =======================
+//
+// Structs and functions shared by every shader. The structs are declared
+// in `storage.wgsl`, which is generated from the types in `storage.rs`.
+
+#include "storage.wgsl"
+
+// Multiplier of agent velocities, before genome and mask
+const SPEED_SCALE = 4.0;
+
+fn rotate(v: vec2<f32>, angle: f32) -> vec2<f32> {
+    let c = cos(angle);
+    let s = sin(angle);
+    return vec2<f32>(
+        v.x * c - v.y * s,
+        v.x * s + v.y * c
+    );
+}
+
+// PCG hash, see https://www.jcgt.org/published/0009/03/02/
+fn hash(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random(seed: ptr<function, u32>) -> f32 {
+    *seed = hash(*seed);
+    return f32(*seed) / 4294967295.0;
+}
@@ -1,3 +1,7 @@
+#include "prelude.wgsl"
+#include "palette.wgsl"
+#include "trail/color.wgsl"
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) uv: vec2<f32>,
@@ -8,6 +12,12 @@ struct VertexOutput {
     @location(0) uv: vec2<f32>,
 };
 
+@group(0) @binding(2)
+var<uniform> camera: Camera;
+
+@group(0) @binding(3)
+var<uniform> palette: Palette;
+
 @vertex
 fn vs_main(in: VertexInput) -> VertexOutput {
     var out: VertexOutput;
@@ -16,17 +26,33 @@ fn vs_main(in: VertexInput) -> VertexOutput {
     return out;
 }
 
-@group(0) @binding(0)
-var tex: texture_storage_2d<rgba32float, read>;
+// Colour of the texel at `p`, clamped to the edges of the trail map
+fn texel_color(p: vec2<f32>) -> vec4<f32> {
+    let max_texel = vec2<i32>(textureDimensions(tex)) - 1;
+    let texel = vec2<u32>(clamp(vec2<i32>(p), vec2<i32>(0), max_texel));
+
+    // How much of the background the trail covers
+    let coverage = saturate(trail_intensity(texel) / palette.range);
+    if palette.source == COLOR_INTENSITY {
+        return vec4<f32>(mix(palette.background, gradient(coverage), coverage), 1.0);
+    }
+
+    let color = trail_color(texel);
+    return vec4<f32>(color.rgb + palette.background * (1.0 - coverage), color.a);
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let dimension = textureDimensions(tex);
-    let tex_coords = vec2<u32>(
-        u32(in.uv.x * f32(dimension.x) / 1.0),
-        u32(in.uv.y * f32(dimension.y) / 1.0),
-    );
-
-    let color = textureLoad(tex, tex_coords);
-    return color;
+    let dimension = vec2<f32>(textureDimensions(tex));
+    let uv = camera.offset + in.uv * camera.scale;
+
+    // Bilinear filtering by hand, since trail maps can't be sampled with filtering.
+    // This keeps texels from turning into blocks when zoomed in past 1:1.
+    let p = uv * dimension - 0.5;
+    let base = floor(p);
+    let t = p - base;
+
+    let bottom = mix(texel_color(base), texel_color(base + vec2<f32>(1.0, 0.0)), t.x);
+    let top = mix(texel_color(base + vec2<f32>(0.0, 1.0)), texel_color(base + 1.0), t.x);
+    return mix(bottom, top, t.y);
 }
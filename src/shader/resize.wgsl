@@ -0,0 +1,68 @@
+#include "prelude.wgsl"
+
+// ======================== Structs =======================
+
+struct Resize {
+    // Agents waiting in `spawned`
+    spawned: u32,
+    // Live agents to keep, the others are removed
+    keep: u32,
+}
+
+// ========================= Main =========================
+//
+// Run between compacting the agents buffer and simulating it, so agents are
+// only spawned into free slots and never replace live ones.
+
+@group(0) @binding(1)
+var<storage, read_write> agents_buffer: AgentBuffer;
+
+@group(0) @binding(4)
+var<storage, read_write> population: Population;
+
+@group(0) @binding(5)
+var<storage, read> live_indices: array<u32>;
+
+@group(0) @binding(6)
+var<storage, read> free_indices: array<u32>;
+
+@group(0) @binding(8)
+var<uniform> resize: Resize;
+
+@group(0) @binding(9)
+var<storage, read> spawned: array<Agent>;
+
+// Removes the live agents past the first `keep`
+@compute
+@workgroup_size(64, 1, 1)
+fn remove(
+    @builtin(global_invocation_id) g_invocation_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    let live_idx = g_invocation_id.x + g_invocation_id.y * num_workgroups.x * 64u;
+    if live_idx < resize.keep || live_idx >= atomicLoad(&population.live) {
+        return;
+    }
+
+    agents_buffer.agents[live_indices[live_idx]].alive = 0u;
+}
+
+// Places each spawned agent in a free slot, giving up once they have all been taken
+@compute
+@workgroup_size(64, 1, 1)
+fn spawn(
+    @builtin(global_invocation_id) g_invocation_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
+) {
+    let spawned_idx = g_invocation_id.x + g_invocation_id.y * num_workgroups.x * 64u;
+    if spawned_idx >= resize.spawned {
+        return;
+    }
+
+    let free = atomicSub(&population.free, 1) - 1;
+    if free < 0 {
+        return;
+    }
+
+    agents_buffer.agents[free_indices[free]] = spawned[spawned_idx];
+}
@@ -1,26 +1,19 @@
 
-// ======================== Structs =======================
+#include "prelude.wgsl"
+#include "trail/compute.wgsl"
+#include "boundary.wgsl"
+#include "environment.wgsl"
+#include "palette.wgsl"
+#include "hooks.wgsl"
 
-struct Globals {
-    dt: f32,
-    time: f32,
-    work_group_size: u32,
-}
-
-struct Agent {
-    position: vec2<f32>,
-    velocity: vec2<f32>,
-}
-
-struct AgentBuffer {
-    count: u32,
-    agents: array<Agent>,
-}
+#ifdef USER_RULES
+#include "rules.wgsl"
+#endif
 
 // ========================= Utils ========================
 
 fn debug_point(p: vec2<f32>) {
-    let dimensions = textureDimensions(tex);
+    let dimensions = trail_dimensions();
     let ppos = vec2<u32>(
         u32(clamp(p.x, 0.0, f32(dimensions.x))),
         u32(clamp(p.y, 0.0, f32(dimensions.y))),
@@ -29,92 +22,248 @@ fn debug_point(p: vec2<f32>) {
     // textureStore(tex, ppos, vec4<f32>(0.0, 1.0, 0.0, 1.0));
 }
 
-fn warp_clamp(point: vec2<f32>) -> vec2<f32> {
-    var p = point;
-    let dimensions = textureDimensions(tex);
-    p.x = select(p.x, 0.0, p.x >= f32(dimensions.x));
-    p.x = select(p.x, f32(dimensions.x), p.x < 0.0);
-    p.y = select(p.y, 0.0, p.y >= f32(dimensions.y));
-    p.y = select(p.y, f32(dimensions.y), p.y < 0.0);
-    return p;
+fn domain_point(p: vec2<f32>) -> BoundaryPoint {
+    return boundary_point(p, vec2<f32>(trail_dimensions()), globals.boundary);
 }
 
-fn sample(p: vec2<f32>) -> f32 {
-    var p2 = warp_clamp(p);
-    var uv = vec2<u32>(u32(p2.x), u32(p2.y));
-    return textureLoad(tex, uv).w;
-}
+// ========================= Mask =========================
+
+const MASK_ATTRACTOR = 1u;
+const MASK_SPEED = 2u;
+const MASK_DEPOSIT = 3u;
+const MASK_OBSTACLE = 4u;
+
+const MASK_FIT = 1u;
+const MASK_TILE = 2u;
 
-fn sample_area(p: vec2<f32>, radius: f32) -> f32 {
-    let samples = i32(radius);
-    var sum = 0.0;
-    var num_samples = 0u;
-    for (var i = -samples; i <= samples; i = i + 1) {
-        for (var j = -samples; j <= samples; j = j + 1) {
-            let offset = vec2<f32>(f32(i), f32(j));
-            let sample_uv = p + offset;
-            if i * i + j * j > samples * samples {
-                continue;
-            }
-
-            sum += sample(sample_uv);
-            num_samples = num_samples + 1u;
+// Brightness of the mask at a point of the domain, 0 outside a fitted mask
+fn mask(p: vec2<f32>) -> f32 {
+    let domain = vec2<f32>(trail_dimensions());
+    let size = vec2<f32>(textureDimensions(mask_tex));
+
+    var uv = p / domain * size;
+    if globals.mask_fit == MASK_FIT {
+        let scale = min(domain.x / size.x, domain.y / size.y);
+        uv = (p - 0.5 * (domain - size * scale)) / scale;
+        if any(uv < vec2<f32>(0.0)) || any(uv >= size) {
+            return 0.0;
         }
+    } else if globals.mask_fit == MASK_TILE {
+        uv = p - floor(p / size) * size;
     }
 
-    if num_samples == 0u {
-        return sample(p);
+    let texel = min(vec2<u32>(max(uv, vec2<f32>(0.0))), vec2<u32>(size) - 1u);
+    return textureLoad(mask_tex, texel, 0).x;
+}
+
+// Whether agents are kept out of a point by the mask
+fn mask_blocked(p: vec2<f32>) -> bool {
+    return globals.mask_mode == MASK_OBSTACLE && mask(p) * globals.mask_strength > 0.5;
+}
+
+// Multiplier of agent speed or deposits, for the matching mask mode
+fn mask_multiplier(p: vec2<f32>, mode: u32) -> f32 {
+    if globals.mask_mode != mode {
+        return 1.0;
     }
 
-    return sum / f32(num_samples);
+    return mix(1.0, mask(p), globals.mask_strength);
 }
 
-fn rotate(v: vec2<f32>, angle: f32) -> vec2<f32> {
-    let c = cos(angle);
-    let s = sin(angle);
-    return vec2<f32>(
-        v.x * c - v.y * s,
-        v.x * s + v.y * c
-    );
+// ======================== Sensing =======================
+
+// Trail around a sensor plus whatever the environment adds there
+fn sense(p: vec2<f32>, species: u32) -> f32 {
+    let point = domain_point(p).p;
+    if mask_blocked(point) {
+        return OBSTACLE_SENSE;
+    }
+
+    var value = sample_area(p, 1.0, species) + environment_sense(point, globals.time);
+    if globals.mask_mode == MASK_ATTRACTOR {
+        value += mask(point) * globals.mask_strength;
+    }
+
+    return value;
 }
 
-fn hsv2rgb(c: vec3<f32>) -> vec3<f32> {
-    let k = vec4<f32>(1.0, 2.0 / 3.0, 1.0 / 3.0, 3.0);
-    let p = abs(fract(c.xxx + k.xyz) * 6.0 - k.www);
-    return c.z * mix(k.xxx, saturate(p - k.xxx), c.y);
+// Radius in pixels trail density is measured over
+const DENSITY_RADIUS = 2.0;
+
+// Position along the gradient of the colour an agent deposits
+fn color_position(agent_idx: u32) -> f32 {
+    let agent = &agents_buffer.agents[agent_idx];
+    let source = globals.color_source;
+    let position = (*agent).position;
+    let velocity = (*agent).velocity;
+
+    if source == COLOR_SPECIES {
+        return f32((*agent).species);
+    }
+
+    if source == COLOR_SPEED {
+        // Relative to the speed without genome, mask or flow
+        let speed = SPEED_SCALE * (*agent).genome.z * mask_multiplier(position, MASK_SPEED);
+        let step = velocity * speed + environment_flow(position);
+        return 0.5 * length(step) / max(SPEED_SCALE * length(velocity), 1e-5);
+    }
+
+    if source == COLOR_HEADING {
+        return atan2(velocity.y, velocity.x) / 6.2831853 + 0.5;
+    }
+
+    if source == COLOR_AGE {
+        return (*agent).age / globals.max_age;
+    }
+
+    if source == COLOR_ENERGY {
+        return (*agent).energy / globals.reproduction_energy;
+    }
+
+    if source == COLOR_DENSITY {
+        return sample_area(position, DENSITY_RADIUS, (*agent).species) / globals.color_range;
+    }
+
+    if source == COLOR_SPAWN {
+        // Angle around the centre of the domain
+        let offset = (*agent).origin - 0.5 * vec2<f32>(trail_dimensions());
+        return atan2(offset.y, offset.x) / 6.2831853 + 0.5;
+    }
+
+    return f32(agent_idx) / f32(globals.num_agents);
 }
 
-// ======================== Update ========================
+// ======================= Boundary =======================
 
-fn update(agent_idx: u32) {
+struct Motion {
+    position: vec2<f32>,
+    velocity: vec2<f32>,
+}
+
+// Moves an agent by `step`, applying the boundary when it leaves the domain
+fn move_agent(agent_idx: u32, position: vec2<f32>, velocity: vec2<f32>, step: vec2<f32>) -> Motion {
+    let size = vec2<f32>(trail_dimensions());
+    let next = position + step;
+    let point = domain_point(next);
+
+    if globals.boundary == BOUNDARY_REFLECT {
+        let outside = (next < vec2<f32>(0.0)) | (next >= size);
+        return Motion(point.p, select(velocity, -velocity, outside));
+    }
+
+    if point.inside {
+        return Motion(point.p, velocity);
+    }
+
+    if globals.boundary == BOUNDARY_CIRCLE {
+        // Bounce off the wall, staying where the agent was
+        let normal = normalize(next - 0.5 * size);
+        return Motion(position, reflect(velocity, normal));
+    }
 
-    let dimensions = textureDimensions(tex);
+    // Respawn somewhere random, heading in a random direction
+    var seed = hash(agent_idx ^ hash(globals.frame));
+    let respawn = vec2<f32>(random(&seed), random(&seed)) * size;
+    let heading = 6.2831853 * random(&seed);
+    return Motion(respawn, vec2<f32>(cos(heading), sin(heading)) * length(velocity));
+}
 
+// ======================== Update ========================
+
+fn update(agent_idx: u32) {
     let agent = &agents_buffer.agents[agent_idx];
+    let species = (*agent).species;
+    let genome = (*agent).genome;
     var position = (*agent).position;
     var velocity = (*agent).velocity;
 
-    let angle = 0.8;
-
-    let left = sample_area(position + rotate(velocity, angle), 1.0);
-    let right = sample_area(position + rotate(velocity, -angle), 1.0);
-    let forward = sample_area(position + velocity, 1.0);
+    let angle = globals.sensor_angle * genome.x;
+    let turn = globals.turn_rate * genome.y;
+    let sensor = normalize(velocity) * globals.sensor_distance * genome.w;
+
+    let ctx = SenseContext(
+        sense(position + rotate(sensor, angle), species),
+        sense(position + sensor, species),
+        sense(position + rotate(sensor, -angle), species),
+        sensor,
+        angle,
+        turn,
+        agent_idx,
+        hash(agent_idx ^ hash(globals.frame + 1u)),
+    );
 
-    if forward >= left && forward >= right {
-        // Do nothing
-    } else if left > right {
-        velocity = rotate(velocity, 0.01);
+#ifdef USER_RULES
+    velocity = steer(*agent, ctx);
+#else
+    velocity = builtin_steer(*agent, ctx);
+#endif
+
+    // Update agent position, turning around at obstacles
+    let speed = SPEED_SCALE * genome.z * mask_multiplier(position, MASK_SPEED);
+    let step = velocity * speed + environment_flow(position);
+    let moved = move_agent(agent_idx, position, velocity, step * globals.dt);
+    velocity = moved.velocity;
+    let next = moved.position;
+    if environment_blocked(vec2<u32>(next)) || mask_blocked(next) {
+        velocity = -velocity;
     } else {
-        velocity = rotate(velocity, -0.01);
+        position = next;
     }
 
-    // Update agent position
-    position += velocity * globals.dt * 4.0;
-    position = warp_clamp(position);
-
     // Store new agent position and velocity
     (*agent).velocity = velocity;
     (*agent).position = position;
+    (*agent).age += globals.dt;
+
+    if globals.lifecycle != 0u {
+        live(agent_idx, sample(position, species), ctx.forward);
+    }
+}
+
+// Feeds the agent from the trail under it, then lets it die or reproduce
+fn live(agent_idx: u32, food: f32, ahead: f32) {
+    let agent = &agents_buffer.agents[agent_idx];
+
+    (*agent).energy += (food * globals.feeding - globals.metabolism) * globals.dt;
+
+    if (*agent).energy <= 0.0 || (*agent).age >= globals.max_age {
+        (*agent).alive = 0u;
+        return;
+    }
+
+    if (*agent).energy < globals.reproduction_energy || ahead < globals.reproduction_trail {
+        return;
+    }
+
+    // Claim a free slot, giving up once they have all been taken this frame
+    let free = atomicSub(&population.free, 1) - 1;
+    if free < 0 {
+        return;
+    }
+
+    var seed = hash(agent_idx ^ hash(globals.frame));
+    var genome = (*agent).genome;
+    genome.x *= 1.0 + globals.mutation * (2.0 * random(&seed) - 1.0);
+    genome.y *= 1.0 + globals.mutation * (2.0 * random(&seed) - 1.0);
+    genome.z *= 1.0 + globals.mutation * (2.0 * random(&seed) - 1.0);
+    genome.w *= 1.0 + globals.mutation * (2.0 * random(&seed) - 1.0);
+
+    let energy = (*agent).energy * 0.5;
+    (*agent).energy = energy;
+
+    let heading = 6.2831853 * random(&seed);
+    let speed = length((*agent).velocity);
+    agents_buffer.agents[free_indices[free]] = Agent(
+        (*agent).position,
+        vec2<f32>(cos(heading), sin(heading)) * speed,
+        genome,
+        energy,
+        0.0,
+        1u,
+        (*agent).species,
+        (*agent).origin,
+        (*agent).color,
+    );
 }
 
 // ========================= Main =========================
@@ -125,32 +274,58 @@ var<uniform> globals: Globals;
 @group(0) @binding(1)
 var<storage, read_write> agents_buffer: AgentBuffer;
 
-@group(0) @binding(2)
-var tex: texture_storage_2d<rgba32float, read_write>;
+@group(0) @binding(4)
+var<storage, read_write> population: Population;
+
+@group(0) @binding(10)
+var mask_tex: texture_2d<f32>;
+
+@group(0) @binding(5)
+var<storage, read> live_indices: array<u32>;
+
+@group(0) @binding(6)
+var<storage, read> free_indices: array<u32>;
 
 @compute
-@workgroup_size(1, 1, 1)
+@workgroup_size(64, 1, 1)
 fn main(
-    @builtin(global_invocation_id) g_invocation_id: vec3<u32>
+    @builtin(global_invocation_id) g_invocation_id: vec3<u32>,
+    @builtin(num_workgroups) num_workgroups: vec3<u32>,
 ) {
-    let dimensions = textureDimensions(tex);
-    let num_agents = globals.work_group_size * globals.work_group_size;
-    let agent_idx = g_invocation_id.x + g_invocation_id.y * globals.work_group_size;
+    let dimensions = trail_dimensions();
+    let live_idx = g_invocation_id.x + g_invocation_id.y * num_workgroups.x * 64u;
+    if live_idx >= atomicLoad(&population.live) {
+        return;
+    }
+
+    let agent_idx = live_indices[live_idx];
 
     // Update the agent
     update(agent_idx);
 
     let agent = &agents_buffer.agents[agent_idx];
+    if (*agent).alive == 0u {
+        return;
+    }
 
     let pixel_position = vec2<u32>(
         u32((*agent).position.x),
         u32((*agent).position.y)
     );
 
-    let hue = 0.2 * f32(agent_idx) / f32(num_agents) + 0.3;
-    let hsv = vec3<f32>(hue, 1.0, 0.8);
-    let color = hsv2rgb(hsv);
+#ifdef USER_RULES
+    let deposited = deposit(*agent);
+    let color = deposited.rgb;
+    let strength = deposited.a;
+#else
+    let color = gradient(color_position(agent_idx));
+    let strength = 1.0;
+#endif
+    (*agent).color = pack4x8unorm(vec4<f32>(color, 1.0));
 
     // Write the agent to the texture
-    textureStore(tex, pixel_position, vec4<f32>(color, 1.0));
+    let amount = strength * mask_multiplier((*agent).position, MASK_DEPOSIT);
+    if amount > 0.0 {
+        trail_deposit(pixel_position, color, amount, (*agent).species);
+    }
 }
@@ -0,0 +1,84 @@
+// Agents drawn directly, one instanced quad per slot of the agents buffer.
+// Colours are added on top of the trail map or background.
+
+#include "prelude.wgsl"
+
+struct Instance {
+    @location(0) position: vec2<f32>,
+    @location(1) velocity: vec2<f32>,
+    @location(2) alive: u32,
+    @location(3) color: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // From -1 to 1 across the sprite, x pointing where the agent heads
+    @location(0) local: vec2<f32>,
+    @location(1) color: vec3<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(0) @binding(1)
+var<uniform> sprites: Sprites;
+
+const SHAPE_POINT = 0u;
+const SHAPE_QUAD = 1u;
+const SHAPE_LINE = 2u;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, agent: Instance) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+    );
+    let corner = corners[index];
+
+    var out: VertexOutput;
+    if agent.alive == 0u {
+        // Degenerate triangles are dropped before rasterisation
+        out.clip_position = vec4<f32>(0.0);
+        return out;
+    }
+
+    // Screen pixels per domain pixel
+    let zoom = sprites.viewport / (sprites.domain * camera.scale);
+    let motion = agent.velocity * SPEED_SCALE * zoom;
+    let heading = select(vec2<f32>(1.0, 0.0), normalize(motion), length(motion) > 0.0);
+    let side = vec2<f32>(-heading.y, heading.x);
+
+    // Offset from the agent in screen pixels
+    var offset = corner * 0.5 * sprites.size;
+    if sprites.shape == SHAPE_QUAD {
+        offset = heading * offset.x + side * offset.y;
+    } else if sprites.shape == SHAPE_LINE {
+        // From the agent back along the path it took, with half a width past each end
+        let tail = length(motion) * sprites.length;
+        let along = (0.5 * corner.x - 0.5) * tail + offset.x;
+        offset = heading * along + side * offset.y;
+    }
+
+    let center = (agent.position / sprites.domain - camera.offset) / camera.scale * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(center + offset * 2.0 / sprites.viewport, 0.0, 1.0);
+    out.local = corner;
+    out.color = agent.color.rgb;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var alpha = sprites.opacity;
+    if sprites.shape == SHAPE_POINT {
+        alpha *= 1.0 - smoothstep(0.5, 1.0, length(in.local));
+    } else if sprites.shape == SHAPE_LINE {
+        // Fading towards the tail
+        alpha *= 0.5 + 0.5 * in.local.x;
+    }
+
+    return vec4<f32>(in.color * alpha, alpha);
+}
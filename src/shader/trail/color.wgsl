@@ -0,0 +1,14 @@
+// ===================== Trail: Colour ====================
+//
+// Colour and intensity lookup of the render pass, picked by what the
+// trail map stores.
+
+#ifdef TRAIL_RGBA
+#include "trail/rgba_color.wgsl"
+#endif
+#ifdef TRAIL_INTENSITY
+#include "trail/intensity_color.wgsl"
+#endif
+#ifdef TRAIL_SPECIES
+#include "trail/species_color.wgsl"
+#endif
@@ -0,0 +1,21 @@
+// ==================== Trail: Compute ====================
+//
+// Functions compute passes read and write the trail map with, picked by
+// how it is accessed and what it stores.
+
+#ifdef TRAIL_READ_WRITE
+#include "trail/read_write.wgsl"
+#endif
+#ifdef TRAIL_PING_PONG
+#include "trail/ping_pong.wgsl"
+#endif
+
+#ifdef TRAIL_RGBA
+#include "trail/rgba.wgsl"
+#endif
+#ifdef TRAIL_INTENSITY
+#include "trail/intensity.wgsl"
+#endif
+#ifdef TRAIL_SPECIES
+#include "trail/species.wgsl"
+#endif
@@ -0,0 +1,16 @@
+// =================== Trail: Intensity ===================
+//
+// Single channel intensity, with the colour of the most recent
+// deposit kept in a separate buffer of packed rgba8 values.
+
+@group(0) @binding(3)
+var<storage, read_write> trail_colors: array<u32>;
+
+fn trail_sense(p: vec2<u32>, species: u32) -> f32 {
+    return trail_load(p).x;
+}
+
+fn trail_deposit(p: vec2<u32>, color: vec3<f32>, amount: f32, species: u32) {
+    trail_store(p, vec4<f32>(amount));
+    trail_colors[p.x + p.y * trail_dimensions().x] = pack4x8unorm(vec4<f32>(color, 1.0));
+}
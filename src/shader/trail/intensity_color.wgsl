@@ -0,0 +1,15 @@
+@group(0) @binding(0)
+var tex: texture_2d<f32>;
+
+@group(0) @binding(1)
+var<storage, read> trail_colors: array<u32>;
+
+fn trail_intensity(p: vec2<u32>) -> f32 {
+    return textureLoad(tex, p, 0).x;
+}
+
+fn trail_color(p: vec2<u32>) -> vec4<f32> {
+    let intensity = textureLoad(tex, p, 0).x;
+    let color = unpack4x8unorm(trail_colors[p.x + p.y * textureDimensions(tex).x]);
+    return vec4<f32>(color.rgb * intensity, 1.0);
+}
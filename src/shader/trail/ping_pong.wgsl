@@ -0,0 +1,23 @@
+// ================ Trail access: Ping-pong =================
+//
+// The last frame's trail map is read and the next one written to a second
+// texture. Every pass writes to the same texture, so agents sense the trail
+// from before this frame's diffusion.
+
+@group(0) @binding(2)
+var tex: texture_2d<f32>;
+
+@group(0) @binding(12)
+var tex_next: texture_storage_2d<TRAIL_FORMAT, write>;
+
+fn trail_dimensions() -> vec2<u32> {
+    return textureDimensions(tex);
+}
+
+fn trail_load(p: vec2<u32>) -> vec4<f32> {
+    return textureLoad(tex, p, 0);
+}
+
+fn trail_store(p: vec2<u32>, value: vec4<f32>) {
+    textureStore(tex_next, p, value);
+}
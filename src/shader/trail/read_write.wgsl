@@ -0,0 +1,18 @@
+// ================ Trail access: Read-write ================
+//
+// A single texture updated in place.
+
+@group(0) @binding(2)
+var tex: texture_storage_2d<TRAIL_FORMAT, read_write>;
+
+fn trail_dimensions() -> vec2<u32> {
+    return textureDimensions(tex);
+}
+
+fn trail_load(p: vec2<u32>) -> vec4<f32> {
+    return textureLoad(tex, p);
+}
+
+fn trail_store(p: vec2<u32>, value: vec4<f32>) {
+    textureStore(tex, p, value);
+}
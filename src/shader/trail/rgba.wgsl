@@ -0,0 +1,11 @@
+// ===================== Trail: RGBA ======================
+//
+// Colour in rgb and intensity in alpha, stored per pixel.
+
+fn trail_sense(p: vec2<u32>, species: u32) -> f32 {
+    return trail_load(p).w;
+}
+
+fn trail_deposit(p: vec2<u32>, color: vec3<f32>, amount: f32, species: u32) {
+    trail_store(p, vec4<f32>(color * amount, amount));
+}
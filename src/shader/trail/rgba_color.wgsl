@@ -0,0 +1,10 @@
+@group(0) @binding(0)
+var tex: texture_2d<f32>;
+
+fn trail_intensity(p: vec2<u32>) -> f32 {
+    return textureLoad(tex, p, 0).w;
+}
+
+fn trail_color(p: vec2<u32>) -> vec4<f32> {
+    return textureLoad(tex, p, 0);
+}
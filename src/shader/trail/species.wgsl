@@ -0,0 +1,19 @@
+// ==================== Trail: Species ====================
+//
+// One intensity channel per species, agents only sense their own.
+
+fn trail_sense(p: vec2<u32>, species: u32) -> f32 {
+    let value = trail_load(p);
+    return select(value.x, value.y, species == 1u);
+}
+
+fn trail_deposit(p: vec2<u32>, color: vec3<f32>, amount: f32, species: u32) {
+    var value = trail_load(p);
+    if species == 1u {
+        value.y = amount;
+    } else {
+        value.x = amount;
+    }
+
+    trail_store(p, value);
+}
@@ -0,0 +1,20 @@
+@group(0) @binding(0)
+var tex: texture_2d<f32>;
+
+const species_a = vec3<f32>(0.16, 0.8, 0.0);
+const species_b = vec3<f32>(0.8, 0.0, 0.48);
+
+fn trail_intensity(p: vec2<u32>) -> f32 {
+    let intensity = textureLoad(tex, p, 0);
+    return intensity.x + intensity.y;
+}
+
+// No colours are stored, so species take the ends of the gradient when coloured by species
+fn trail_color(p: vec2<u32>) -> vec4<f32> {
+    let intensity = textureLoad(tex, p, 0);
+    if palette.source == COLOR_SPECIES {
+        return vec4<f32>(gradient(0.0) * intensity.x + gradient(1.0) * intensity.y, 1.0);
+    }
+
+    return vec4<f32>(species_a * intensity.x + species_b * intensity.y, 1.0);
+}
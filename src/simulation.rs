@@ -0,0 +1,858 @@
+//! Agents, trail map and environment on the GPU.
+
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use wgpu::util::DeviceExt;
+
+use crate::{
+    brush::{Brush, BrushKind},
+    environment::Environment,
+    error::{self, Error, Result},
+    palette::Palette,
+    pipeline::{self, compute},
+    population::Population,
+    profiler::{Pass, Profiler},
+    rules::{self, Rules},
+    scene::{Scene, Spawn},
+    storage::{self, Agent, Storable},
+    trail::{Trail, TrailAccess, TrailFormat, TrailSupport},
+};
+
+/// How much larger than needed the agents buffer is made when it has to grow.
+const AGENT_CAPACITY_HEADROOM: f32 = 1.5;
+
+const AGENT_SPEED: f32 = 24.0;
+
+/// Energy agents are spawned with.
+const AGENT_ENERGY: f32 = 1.0;
+
+/// How often the agent rules file is checked for changes.
+const RULES_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Clock of a simulation, advanced by a fixed timestep or by wall-clock time.
+#[derive(Clone, Debug)]
+pub struct Timing {
+    pub time: Instant,
+    pub time_since_last_frame: Duration,
+    pub start_time: Instant,
+    pub frame: usize,
+    pub fixed_dt: Option<Duration>,
+}
+
+/// State shared by every pass. Editing the scene takes effect on the next step.
+pub struct Globals {
+    pub timing: Timing,
+    pub scene: Scene,
+}
+
+pub(crate) struct Pipelines {
+    diffuse: pipeline::compute::ComputePipeline,
+    simulation: pipeline::compute::ComputePipeline,
+    paint: pipeline::compute::ComputePipeline,
+    push: pipeline::compute::ComputePipeline,
+}
+
+/// Owns the GPU resources of a running scene. Each [`Simulation::step`]
+/// records one frame into the caller's command encoder.
+pub struct Simulation {
+    pub globals: Globals,
+    domain: glam::UVec2,
+    pipelines: Pipelines,
+    globals_buffer: wgpu::Buffer,
+    agents_buffer: wgpu::Buffer,
+    /// Number of agents the agents buffer has room for
+    agents_capacity: u32,
+    /// Number of agents currently written to the agents buffer
+    num_agents: u32,
+    population: Population,
+    environment: Environment,
+    palette: Palette,
+    trail: Trail,
+    trail_support: TrailSupport,
+    /// Agent rules the simulation pass was built with
+    rules: Option<Rules>,
+    /// Rules file and when it was modified, as last loaded or tried
+    rules_version: Option<(PathBuf, Option<SystemTime>)>,
+    /// When the rules file was last checked for changes
+    rules_checked: Instant,
+    /// Brush to apply during the next step
+    brush: Option<Brush>,
+    /// Errors recovered from while stepping, until they are taken
+    errors: Vec<Error>,
+    /// Source of spawn positions and headings, seeded by the scene if it has a seed
+    rng: StdRng,
+}
+
+impl Simulation {
+    /// Sets up `scene` on `device`, accessing the trail map the way
+    /// `trail_support` allows for the scene's trail format.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        domain: glam::UVec2,
+        timing: Timing,
+        mut scene: Scene,
+        trail_support: TrailSupport,
+    ) -> Result<Self> {
+        let format = scene.trail_format;
+        let access = trail_support
+            .access(format)
+            .ok_or(Error::UnsupportedTrailFormat(format))?;
+        check_domain(device, format, domain)?;
+        let rules = scene.rules.as_deref().map(Rules::load).transpose()?;
+        let pipelines = error::compile(device, || {
+            Pipelines::new(device, format, access, rules.as_ref())
+        })??;
+        let rules_version = rules_version(&scene);
+
+        scene.agents = scene.agents.min(max_agents(device));
+
+        let mut rng = spawn_rng(scene.seed);
+        let agents: Vec<storage::Agent> = (0..scene.agents)
+            .map(|i| spawn_agent(scene.spawn, domain, i, &mut rng))
+            .collect();
+
+        let agents_capacity = agent_capacity(device, scene.agents);
+        let agents_buffer = create_agents_buffer(device, agents_capacity);
+        queue.write_buffer(&agents_buffer, 0, &storage::Buffer(&agents).to_bytes()?);
+
+        let globals = Globals { timing, scene };
+        let globals_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Globals buffer"),
+            contents: {
+                let storage: storage::Globals = (&globals).into();
+                &storage::Uniform(&storage).to_bytes()?
+            },
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Ok(Self {
+            domain,
+            pipelines,
+            globals_buffer,
+            agents_buffer,
+            agents_capacity,
+            num_agents: globals.scene.agents,
+            population: Population::new(device, agents_capacity)?,
+            environment: Environment::new(device, queue, &globals.scene.environment, domain)?,
+            palette: Palette::new(device, queue, &globals.scene.palette.gradient),
+            trail: Trail::new(device, format, access, domain),
+            trail_support,
+            rules,
+            rules_version,
+            rules_checked: Instant::now(),
+            brush: None,
+            errors: Vec::new(),
+            rng,
+            globals,
+        })
+    }
+
+    /// Size of the simulation domain in pixels.
+    pub fn domain(&self) -> glam::UVec2 {
+        self.domain
+    }
+
+    pub fn trail(&self) -> &Trail {
+        &self.trail
+    }
+
+    pub(crate) fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    /// Buffer holding every agent slot, and how many slots it has room for.
+    pub(crate) fn agents(&self) -> (&wgpu::Buffer, u32) {
+        (&self.agents_buffer, self.agents_capacity)
+    }
+
+    /// Number of live agents, once it has been read back from the GPU.
+    pub fn live_agents(&self) -> Option<u32> {
+        self.population.live()
+    }
+
+    /// Applies `brush` during the next step only.
+    pub fn set_brush(&mut self, brush: Option<Brush>) {
+        self.brush = brush;
+    }
+
+    /// Errors the simulation recovered from since the last call, such as
+    /// agent rules or a mask image that failed to load and left the
+    /// previous ones in place.
+    pub fn take_errors(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Must be called once the commands recorded by [`Simulation::step`] have been submitted.
+    pub fn after_submit(&mut self) {
+        self.population.after_submit();
+    }
+
+    /// Collects results read back from the GPU without blocking.
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Poll);
+        self.population.poll();
+    }
+
+    /// Rebuilds the simulation pass once the scene points at another rules
+    /// file or the file changes. Rules that fail to load or compile are
+    /// kept as an error and the previous ones stay in place.
+    fn reload_rules(&mut self, device: &wgpu::Device) {
+        if self.rules_checked.elapsed() < RULES_CHECK_INTERVAL {
+            return;
+        }
+        self.rules_checked = Instant::now();
+
+        let version = rules_version(&self.globals.scene);
+        if version == self.rules_version {
+            return;
+        }
+        self.rules_version = version;
+
+        let (format, access) = (self.trail.format(), self.trail.access());
+        let reloaded = self
+            .globals
+            .scene
+            .rules
+            .as_deref()
+            .map(Rules::load)
+            .transpose()
+            .and_then(|rules| {
+                let pipelines = error::compile(device, || {
+                    Pipelines::new(device, format, access, rules.as_ref())
+                })??;
+                Ok((rules, pipelines))
+            });
+        match reloaded {
+            Ok((rules, pipelines)) => {
+                self.rules = rules;
+                self.pipelines = pipelines;
+            }
+            Err(e) => self.errors.push(e),
+        }
+    }
+
+    /// Starts over with an empty trail map in `format`, once the passes
+    /// using it are built. Rules that fail to compile for `format` are kept
+    /// as an error and the built-in ones used instead.
+    fn switch_trail(&mut self, device: &wgpu::Device, format: TrailFormat) -> Result<()> {
+        let access = self
+            .trail_support
+            .access(format)
+            .ok_or(Error::UnsupportedTrailFormat(format))?;
+        check_domain(device, format, self.domain)?;
+        let pipelines = match error::compile(device, || {
+            Pipelines::new(device, format, access, self.rules.as_ref())
+        })
+        .and_then(|pipelines| pipelines)
+        {
+            Ok(pipelines) => pipelines,
+            Err(e) => {
+                let pipelines =
+                    error::compile(device, || Pipelines::builtin(device, format, access))??;
+                self.errors.push(e);
+                self.rules = None;
+                pipelines
+            }
+        };
+
+        self.trail = Trail::new(device, format, access, self.domain);
+        self.pipelines = pipelines;
+        Ok(())
+    }
+
+    /// Records the diffuse and simulation passes for one frame. Errors it
+    /// recovers from are kept for [`Simulation::take_errors`].
+    pub fn step(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        profiler: &mut Profiler,
+    ) -> Result<()> {
+        let time = self.globals.timing.elapsed();
+        self.globals.scene.animate(time);
+
+        // Formats that can't be switched to are reported and the old one kept
+        let trail_format = self.globals.scene.trail_format;
+        if trail_format != self.trail.format() {
+            if let Err(e) = self.switch_trail(device, trail_format) {
+                self.errors.push(e);
+                self.globals.scene.trail_format = self.trail.format();
+            }
+        }
+        self.reload_rules(device);
+
+        self.resize_agents(device, encoder);
+        if let Err(e) = self
+            .environment
+            .update(device, queue, &self.globals.scene.environment)
+        {
+            self.errors.push(e);
+        }
+        self.palette
+            .update(queue, &self.globals.scene.palette.gradient);
+
+        // Copy globals to GPU
+        {
+            let bytes = {
+                let storage: storage::Globals = (&self.globals).into();
+                storage::Uniform(&storage).to_bytes()?
+            };
+
+            let globals_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: &bytes,
+                usage: wgpu::BufferUsages::COPY_SRC,
+            });
+
+            encoder.copy_buffer_to_buffer(
+                &globals_buffer,
+                0,
+                &self.globals_buffer,
+                0,
+                bytes.len() as wgpu::BufferAddress,
+            );
+        }
+
+        profiler.begin_pass(encoder, Pass::Diffuse);
+        let diffused = self.diffuse(device, encoder);
+        profiler.end_pass(encoder, Pass::Diffuse);
+        diffused?;
+
+        // Brushes apply after diffusion, which writes every pixel of the next
+        // trail map when it is ping-ponged
+        self.apply_brush(device, encoder)?;
+
+        profiler.begin_pass(encoder, Pass::Simulation);
+        let simulated = self.simulate(device, encoder);
+        profiler.end_pass(encoder, Pass::Simulation);
+        simulated?;
+
+        self.trail.swap();
+        Ok(())
+    }
+
+    /// Records the diffuse pass, spreading and fading the trail map.
+    fn diffuse(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) -> Result<()> {
+        let bind_group = self.pipelines.diffuse.bindings.bind_group(
+            device,
+            [
+                vec![("globals", self.globals_buffer.as_entire_binding())],
+                self.trail.resources(),
+                self.environment.resources(),
+            ]
+            .concat(),
+        )?;
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Diffuse pass"),
+        });
+
+        compute_pass.set_pipeline(&self.pipelines.diffuse.pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups(self.domain.x, self.domain.y, 1);
+        Ok(())
+    }
+
+    /// Records the simulation pass, only run for live agents.
+    fn simulate(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<()> {
+        self.population
+            .update(device, encoder, &self.agents_buffer)?;
+
+        let bind_group = self.pipelines.simulation.bindings.bind_group(
+            device,
+            [
+                vec![
+                    ("globals", self.globals_buffer.as_entire_binding()),
+                    ("agents_buffer", self.agents_buffer.as_entire_binding()),
+                    self.environment.mask_resource(),
+                    self.palette.resource(),
+                ],
+                self.population.resources(),
+                self.trail.resources(),
+                self.environment.resources(),
+            ]
+            .concat(),
+        )?;
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Simulation pass"),
+        });
+
+        compute_pass.set_pipeline(&self.pipelines.simulation.pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch_workgroups_indirect(self.population.dispatch(), 0);
+        Ok(())
+    }
+
+    /// Grows or shrinks the population to match the scene. New agents are
+    /// spawned into free slots and live ones are only removed when shrinking,
+    /// offspring included.
+    fn resize_agents(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        let agents = self.globals.scene.agents.min(max_agents(device));
+        self.globals.scene.agents = agents;
+        if agents == self.num_agents {
+            return;
+        }
+
+        if agents > self.num_agents {
+            let scene = &self.globals.scene;
+            let spawned: Vec<Agent> = (self.num_agents..agents)
+                .map(|i| spawn_agent(scene.spawn, self.domain, i, &mut self.rng))
+                .collect();
+            self.spawn_agents(device, encoder, spawned);
+        } else {
+            self.population.keep(agents);
+        }
+
+        self.num_agents = agents;
+    }
+
+    /// Spawns `agents` into free slots with the next step, growing the
+    /// agents buffer if the live agents might leave too few.
+    fn spawn_agents(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        agents: Vec<Agent>,
+    ) {
+        // Offspring may have taken slots beyond the scene's agents
+        let live = self.population.live().unwrap_or(0).max(self.num_agents);
+        let needed = live
+            .saturating_add(self.population.spawning())
+            .saturating_add(agents.len() as u32);
+        self.reserve_agents(device, encoder, needed.min(max_agents(device)));
+        self.population.spawn(agents);
+    }
+
+    /// Grows the agents buffer, keeping its contents, if it can't hold `agents` agents.
+    fn reserve_agents(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        agents: u32,
+    ) {
+        if agents <= self.agents_capacity {
+            return;
+        }
+
+        let capacity = agent_capacity(device, agents);
+        let buffer = create_agents_buffer(device, capacity);
+        encoder.copy_buffer_to_buffer(
+            &self.agents_buffer,
+            0,
+            &buffer,
+            0,
+            self.agents_buffer.size(),
+        );
+
+        self.agents_buffer = buffer;
+        self.agents_capacity = capacity;
+        self.population.resize(device, capacity);
+    }
+
+    fn apply_brush(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Result<()> {
+        let Some(brush) = self.brush.take() else {
+            return Ok(());
+        };
+
+        let dt = self.globals.timing.dt();
+
+        if brush.kind == BrushKind::Spawn {
+            let positions = brush.spawn_positions(self.domain, dt, &mut self.rng);
+            if positions.is_empty() {
+                return Ok(());
+            }
+
+            let start = self.num_agents;
+            let end = (start + positions.len() as u32).min(max_agents(device));
+            let agents: Vec<Agent> = positions
+                .into_iter()
+                .zip(start..end)
+                .map(|(position, i)| {
+                    new_agent(position, random_unit_circle(&mut self.rng) * AGENT_SPEED, i)
+                })
+                .collect();
+            self.spawn_agents(device, encoder, agents);
+
+            self.num_agents = end;
+            self.globals.scene.agents = end;
+            return Ok(());
+        }
+
+        let (uniform, size) = brush.uniform(self.domain, dt);
+        let brush_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Brush buffer"),
+            contents: &storage::Uniform(&uniform).to_bytes()?,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let pipeline = match brush.kind {
+            BrushKind::Paint | BrushKind::Erase => &self.pipelines.paint,
+            _ => &self.pipelines.push,
+        };
+
+        let bind_group = pipeline.bindings.bind_group(
+            device,
+            [
+                vec![
+                    ("brush", brush_buffer.as_entire_binding()),
+                    ("agents_buffer", self.agents_buffer.as_entire_binding()),
+                ],
+                self.trail.resources(),
+            ]
+            .concat(),
+        )?;
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Brush pass"),
+        });
+
+        compute_pass.set_pipeline(&pipeline.pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        match brush.kind {
+            BrushKind::Paint | BrushKind::Erase => {
+                compute_pass.dispatch_workgroups(size.x.div_ceil(8), size.y.div_ceil(8), 1)
+            }
+            _ => compute::dispatch_linear(&mut compute_pass, device, self.agents_capacity, 64),
+        }
+
+        Ok(())
+    }
+}
+
+impl Pipelines {
+    fn new(
+        device: &wgpu::Device,
+        trail_format: TrailFormat,
+        access: TrailAccess,
+        rules: Option<&Rules>,
+    ) -> Result<Self> {
+        Ok(Self {
+            diffuse: compute::ComputePipeline::diffuse(device, trail_format, access)?,
+            simulation: compute::ComputePipeline::simulation(device, trail_format, access, rules)?,
+            paint: compute::ComputePipeline::paint(device, trail_format, access)?,
+            push: compute::ComputePipeline::push(device, trail_format, access)?,
+        })
+    }
+
+    /// Pipelines without agent rules, which only fail on bugs.
+    fn builtin(
+        device: &wgpu::Device,
+        trail_format: TrailFormat,
+        access: TrailAccess,
+    ) -> Result<Self> {
+        Self::new(device, trail_format, access, None)
+    }
+}
+
+/// Rules file of `scene` and when it was modified.
+fn rules_version(scene: &Scene) -> Option<(PathBuf, Option<SystemTime>)> {
+    let path = scene.rules.clone()?;
+    let modified = rules::modified(&path);
+    Some((path, modified))
+}
+
+/// Largest number of agents that fit in a single storage buffer binding.
+fn max_agents(device: &wgpu::Device) -> u32 {
+    let limits = device.limits();
+    let max_size = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+    let stride = storage::Buffer::<Agent>::offset(1) - storage::Buffer::<Agent>::offset(0);
+    ((max_size - storage::Buffer::<Agent>::offset(0)) / stride) as u32
+}
+
+/// Checks that the trail map of `domain` fits within the device's limits.
+fn check_domain(device: &wgpu::Device, format: TrailFormat, domain: glam::UVec2) -> Result<()> {
+    let limits = device.limits();
+    let side = domain.max_element();
+    if side > limits.max_texture_dimension_2d {
+        return Err(Error::Limit {
+            what: "Domain side",
+            requested: side as u64,
+            limit: limits.max_texture_dimension_2d as u64,
+        });
+    }
+
+    if format.has_color_buffer() {
+        let bytes = domain.x as u64 * domain.y as u64 * std::mem::size_of::<u32>() as u64;
+        let limit = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+        if bytes > limit {
+            return Err(Error::Limit {
+                what: "Trail colour buffer size",
+                requested: bytes,
+                limit,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn agent_capacity(device: &wgpu::Device, num_agents: u32) -> u32 {
+    let capacity = (num_agents as f32 * AGENT_CAPACITY_HEADROOM).ceil() as u32;
+    capacity.clamp(1, max_agents(device).max(1))
+}
+
+fn create_agents_buffer(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Agents buffer"),
+        size: storage::Buffer::<Agent>::offset(capacity as usize),
+        usage: wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::VERTEX
+            | wgpu::BufferUsages::COPY_DST
+            | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
+
+/// Random numbers to spawn agents with, the same every run for a given `seed`.
+pub(crate) fn spawn_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+pub(crate) fn spawn_agent(
+    spawn: Spawn,
+    domain: glam::UVec2,
+    index: u32,
+    rng: &mut impl Rng,
+) -> Agent {
+    let center = domain.as_vec2() * 0.5;
+    let scale = domain.min_element() as f32;
+    let (position, velocity) = match spawn {
+        Spawn::Disc { radius } => (
+            center + random_in_unit_circle(rng) * radius * scale,
+            random_unit_circle(rng) * AGENT_SPEED,
+        ),
+        Spawn::Ring { radius } => {
+            let direction = random_unit_circle(rng);
+            (
+                center + direction * radius * scale,
+                -direction * AGENT_SPEED,
+            )
+        }
+        Spawn::Point => (center, random_unit_circle(rng) * AGENT_SPEED),
+        Spawn::Uniform => (
+            glam::f32::vec2(
+                rng.gen_range(0.0..domain.x as f32),
+                rng.gen_range(0.0..domain.y as f32),
+            ),
+            random_unit_circle(rng) * AGENT_SPEED,
+        ),
+    };
+
+    new_agent(position, velocity, index)
+}
+
+/// Newly born agent, the species alternates with `index`.
+fn new_agent(position: glam::f32::Vec2, velocity: glam::f32::Vec2, index: u32) -> Agent {
+    Agent {
+        position,
+        velocity,
+        genome: glam::f32::Vec4::ONE,
+        energy: AGENT_ENERGY,
+        age: 0.0,
+        alive: 1,
+        species: index % 2,
+        origin: position,
+        color: 0,
+    }
+}
+
+fn random_unit_circle(rng: &mut impl Rng) -> glam::f32::Vec2 {
+    let theta = rng.gen_range(0.0..std::f32::consts::TAU);
+    glam::f32::vec2(theta.cos(), theta.sin())
+}
+
+fn random_in_unit_circle(rng: &mut impl Rng) -> glam::f32::Vec2 {
+    let theta = rng.gen_range(0.0..std::f32::consts::TAU);
+    let r = rng.gen_range(0.0..1.0);
+    glam::f32::vec2(r * theta.cos(), r * theta.sin())
+}
+
+impl Timing {
+    pub fn new(fixed_dt: Option<Duration>) -> Self {
+        let now = Instant::now();
+        Self {
+            time: now,
+            start_time: now,
+            time_since_last_frame: Duration::ZERO,
+            frame: 0,
+            fixed_dt,
+        }
+    }
+
+    /// Advances to the next frame, either by the fixed timestep or by wall-clock time.
+    pub fn tick(&mut self) {
+        let prev_time = self.time;
+        self.time = match self.fixed_dt {
+            Some(dt) => prev_time + dt,
+            None => Instant::now(),
+        };
+        self.time_since_last_frame = self.time - prev_time;
+        self.frame += 1;
+    }
+
+    pub fn dt(&self) -> f32 {
+        self.time_since_last_frame.as_secs_f32()
+    }
+
+    /// Time in seconds since the simulation started.
+    pub fn elapsed(&self) -> f32 {
+        (self.time - self.start_time).as_secs_f32()
+    }
+}
+
+impl From<&Globals> for storage::Globals {
+    fn from(globals: &Globals) -> Self {
+        let lifecycle = globals.scene.lifecycle.unwrap_or_default();
+        let mask = globals.scene.environment.mask.as_ref();
+        Self {
+            dt: globals.timing.dt(),
+            time: globals.timing.elapsed(),
+            boundary: globals.scene.boundary as u32,
+            decay: globals.scene.behaviour.decay,
+            num_agents: globals.scene.agents,
+            frame: globals.timing.frame as u32,
+            lifecycle: globals.scene.lifecycle.is_some() as u32,
+            metabolism: lifecycle.metabolism,
+            feeding: lifecycle.feeding,
+            reproduction_energy: lifecycle.reproduction_energy,
+            reproduction_trail: lifecycle.reproduction_trail,
+            max_age: lifecycle.max_age,
+            mutation: lifecycle.mutation,
+            mask_mode: mask.map_or(0, |mask| mask.mode as u32 + 1),
+            mask_fit: mask.map_or(0, |mask| mask.fit as u32),
+            mask_strength: mask.map_or(0.0, |mask| mask.strength),
+            color_source: globals.scene.palette.source as u32,
+            color_range: globals.scene.palette.range.max(f32::EPSILON),
+            sensor_angle: globals.scene.behaviour.sensor_angle,
+            sensor_distance: globals.scene.behaviour.sensor_distance,
+            turn_rate: globals.scene.behaviour.turn_rate,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        adapter::{self, AdapterOptions},
+        readback,
+        scene::Lifecycle,
+    };
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn device() -> Option<(wgpu::Device, wgpu::Queue, TrailSupport)> {
+        let options = AdapterOptions::default();
+        let instance = options.instance();
+        let Ok(adapter) = pollster::block_on(options.select(&instance, None)) else {
+            eprintln!("No adapter, skipping the simulation test");
+            return None;
+        };
+        let (device, queue, support) =
+            pollster::block_on(adapter::request_device(&adapter, wgpu::Limits::default()))
+                .expect("Failed to open the device");
+        Some((device, queue, support))
+    }
+
+    fn read_u32s(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> Vec<u32> {
+        readback::read_buffer(device, queue, buffer, buffer.size())
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+            .collect()
+    }
+
+    #[test]
+    fn lifecycle_keeps_the_live_list_compact() {
+        let Some((device, queue, support)) = device() else {
+            return;
+        };
+
+        // Agents reproduce once a little trail is ahead and die within half
+        // the run, so only offspring are left at the end
+        let max_age = 0.5;
+        let scene = Scene {
+            agents: 256,
+            seed: Some(1),
+            lifecycle: Some(Lifecycle {
+                reproduction_energy: 0.4,
+                reproduction_trail: 0.1,
+                max_age,
+                ..Lifecycle::default()
+            }),
+            ..Scene::default()
+        };
+        let mut simulation = Simulation::new(
+            &device,
+            &queue,
+            glam::uvec2(64, 64),
+            Timing::new(Some(Duration::from_secs_f32(DT))),
+            scene,
+            support,
+        )
+        .expect("Failed to create the simulation");
+
+        let mut profiler = Profiler::new(&device, &queue);
+        for _ in 0..60 {
+            simulation.globals.timing.tick();
+            let mut encoder = device.create_command_encoder(&Default::default());
+            simulation
+                .step(&device, &queue, &mut encoder, &mut profiler)
+                .expect("Failed to step the simulation");
+            queue.submit(Some(encoder.finish()));
+            simulation.after_submit();
+            simulation.poll(&device);
+        }
+
+        // Compact the agents as the last step left them
+        let mut encoder = device.create_command_encoder(&Default::default());
+        simulation
+            .population
+            .update(&device, &mut encoder, &simulation.agents_buffer)
+            .unwrap();
+        queue.submit(Some(encoder.finish()));
+
+        let agents = storage::Buffer::<Agent>::read(&readback::read_buffer(
+            &device,
+            &queue,
+            &simulation.agents_buffer,
+            simulation.agents_buffer.size(),
+        ));
+        let alive: Vec<u32> = (0..)
+            .zip(&agents)
+            .filter(|(_, agent)| agent.alive != 0)
+            .map(|(slot, _)| slot)
+            .collect();
+        assert!(!alive.is_empty(), "No offspring survived");
+        assert!(agents
+            .iter()
+            .filter(|agent| agent.alive != 0)
+            .all(|agent| agent.age < max_age));
+
+        let (counters, live_indices) = simulation.population.live_list();
+        let live = read_u32s(&device, &queue, counters)[0];
+        assert_eq!(live as usize, alive.len());
+
+        let mut listed = read_u32s(&device, &queue, live_indices);
+        listed.truncate(live as usize);
+        listed.sort_unstable();
+        assert_eq!(listed, alive);
+    }
+}
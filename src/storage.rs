@@ -1,11 +1,14 @@
 use encase::{
-    private::WriteInto, ArrayLength, ShaderSize, ShaderType, StorageBuffer, UniformBuffer,
+    private::{CreateFrom, WriteInto},
+    ShaderSize, ShaderType, StorageBuffer, UniformBuffer,
 };
 
 use glam::f32;
 
+use crate::error::{Error, Result};
+
 pub trait Storable {
-    fn into_bytes(&self) -> Vec<u8>;
+    fn to_bytes(&self) -> Result<Vec<u8>>;
 }
 
 pub struct Uniform<'a, T>(pub &'a T)
@@ -16,10 +19,10 @@ impl<T> Storable for Uniform<'_, T>
 where
     T: ShaderType + ShaderSize + WriteInto,
 {
-    fn into_bytes(&self) -> Vec<u8> {
-        let mut buffer = UniformBuffer::new(Vec::new());
-        buffer.write(self.0).expect("Unable to write uniform");
-        buffer.into_inner()
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buffer = UniformBuffer::new(vec![0; self.0.size().get() as usize]);
+        buffer.write(self.0)?;
+        Ok(buffer.into_inner())
     }
 }
 
@@ -27,56 +30,292 @@ pub struct Buffer<'a, T>(pub &'a [T])
 where
     T: ShaderSize;
 
-#[derive(ShaderType)]
-struct SizedBuffer<'a, T: ShaderSize + 'a> {
-    length: ArrayLength,
+impl<T> Storable for Buffer<'_, T>
+where
+    T: ShaderSize + WriteInto,
+{
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let data = SizedBuffer::new(self.0);
+        let mut buffer = StorageBuffer::new(vec![0; data.size().get() as usize]);
+        buffer.write(&data)?;
 
-    #[size(runtime)]
-    buffer: &'a [T],
+        Ok(buffer.into_inner())
+    }
 }
 
-impl<'a, T> SizedBuffer<'a, T>
+impl<T> Buffer<'_, T>
 where
-    T: ShaderSize + 'a,
+    T: ShaderSize + WriteInto,
 {
-    fn new(buffer: &'a [T]) -> Self {
-        Self {
-            length: ArrayLength,
-            buffer,
-        }
+    /// Offset in bytes of the element at `index` in the bytes written by a [`Buffer`].
+    pub fn offset(index: usize) -> u64 {
+        let header = SizedBuffer::<T>::min_size().get() - T::SHADER_SIZE.get();
+        header + index as u64 * T::SHADER_SIZE.get()
     }
 }
 
-impl<T> Storable for Buffer<'_, T>
+impl<T> Buffer<'_, T>
 where
-    T: ShaderSize + WriteInto,
+    T: ShaderSize + WriteInto + CreateFrom,
 {
-    fn into_bytes(&self) -> Vec<u8> {
-        let data = SizedBuffer::new(self.0);
-        let mut buffer = StorageBuffer::new(Vec::new());
-        buffer.write(&data).expect("Unable to write buffer");
+    /// Elements of a [`Buffer`] read back from the GPU, ignoring its length
+    /// header and any trailing bytes too short for an element.
+    pub fn read(bytes: &[u8]) -> Vec<T> {
+        let start = (Self::offset(0) as usize).min(bytes.len());
+        bytes[start..]
+            .chunks_exact(T::SHADER_SIZE.get() as usize)
+            .filter_map(|element| StorageBuffer::new(element).create().ok())
+            .collect()
+    }
+}
+
+/// A slice of elements without the length header written by [`Buffer`],
+/// for updating part of an existing buffer.
+pub struct Elements<'a, T>(pub &'a [T])
+where
+    T: ShaderSize;
 
-        buffer.into_inner()
+impl<T> Storable for Elements<'_, T>
+where
+    T: ShaderSize + WriteInto,
+{
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let header = Buffer::<T>::offset(0) as usize;
+        Ok(Buffer(self.0).to_bytes()?.split_off(header))
     }
 }
 
 /*
-    Types
+    WGSL
 */
 
-#[derive(ShaderType)]
-pub struct Globals {
-    pub dt: f32,
-    pub time: f32,
-    pub work_group_size: u32,
+/// A field type and how WGSL spells it.
+pub trait WgslType {
+    const WGSL: &'static str;
 }
 
-#[derive(ShaderType)]
-pub struct Agent {
-    pub position: glam::f32::Vec2,
-    pub velocity: glam::f32::Vec2,
+macro_rules! wgsl_type {
+    ($($ty:ty => $wgsl:literal),* $(,)?) => {
+        $(impl WgslType for $ty {
+            const WGSL: &'static str = $wgsl;
+        })*
+    };
+}
+
+wgsl_type! {
+    f32 => "f32",
+    u32 => "u32",
+    i32 => "i32",
+    glam::f32::Vec2 => "vec2<f32>",
+    glam::f32::Vec3 => "vec3<f32>",
+    glam::f32::Vec4 => "vec4<f32>",
+    glam::UVec2 => "vec2<u32>",
+}
+
+/// A struct whose WGSL declaration is generated from its Rust definition.
+pub trait WgslStruct: ShaderType {
+    fn layout() -> Layout;
+}
+
+/// Fields of a struct shared with the shaders, at the offsets encase
+/// writes them at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layout {
+    pub name: &'static str,
+    pub fields: Vec<Field>,
+    /// Size in bytes, `None` when the struct ends in a runtime-sized array
+    pub size: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub name: &'static str,
+    pub wgsl: String,
+    pub offset: u64,
+}
+
+impl Layout {
+    pub fn declaration(&self) -> String {
+        let mut declaration = format!("struct {} {{\n", self.name);
+        for field in &self.fields {
+            declaration += &format!("    {}: {},\n", field.name, field.wgsl);
+        }
+        declaration + "}\n"
+    }
+
+    /// How `ty`, the struct of the same name in a shader, differs from
+    /// this layout, if it does.
+    fn difference(&self, ty: &naga::TypeInner) -> Option<String> {
+        let naga::TypeInner::Struct { members, span } = ty else {
+            return Some("it isn't a struct".to_owned());
+        };
+
+        let names: Vec<_> = members
+            .iter()
+            .map(|member| member.name.as_deref().unwrap_or("_"))
+            .collect();
+        let expected: Vec<_> = self.fields.iter().map(|field| field.name).collect();
+        if names != expected {
+            return Some(format!(
+                "its fields are {} and should be {}",
+                names.join(", "),
+                expected.join(", ")
+            ));
+        }
+
+        for (member, field) in members.iter().zip(&self.fields) {
+            if member.offset as u64 != field.offset {
+                return Some(format!(
+                    "`{}` is at byte {} and should be at byte {}",
+                    field.name, member.offset, field.offset
+                ));
+            }
+        }
+
+        match self.size {
+            Some(size) if *span as u64 != size => {
+                Some(format!("it is {} bytes and should be {} bytes", span, size))
+            }
+            _ => None,
+        }
+    }
 }
 
+/// Declares a struct shared with the shaders and implements [`WgslStruct`]
+/// for it. A field can name its WGSL type after `as`, for atomics.
+macro_rules! wgsl_struct {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                pub $field:ident: $ty:ty $(as $wgsl:literal)?,
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        pub struct $name {
+            $(
+                $(#[$field_meta])*
+                pub $field: $ty,
+            )*
+        }
+
+        impl WgslStruct for $name {
+            fn layout() -> Layout {
+                // Encase keeps the offsets it chose in its hidden metadata,
+                // which isn't covered by semver, hence the exact version in
+                // Cargo.toml
+                let offsets = <Self as ShaderType>::METADATA.extra.offsets;
+                let wgsl = [$(wgsl_struct!(@type $ty $(as $wgsl)?)),*];
+                let names = [$(stringify!($field)),*];
+
+                Layout {
+                    name: stringify!($name),
+                    fields: names
+                        .into_iter()
+                        .zip(wgsl)
+                        .zip(offsets)
+                        .map(|((name, wgsl), offset)| Field {
+                            name,
+                            wgsl: wgsl.to_owned(),
+                            offset,
+                        })
+                        .collect(),
+                    size: Some(Self::min_size().get()),
+                }
+            }
+        }
+    };
+    (@type $ty:ty) => { <$ty as WgslType>::WGSL };
+    (@type $ty:ty as $wgsl:literal) => { $wgsl };
+}
+
+impl<T> Buffer<'_, T>
+where
+    T: WgslStruct + ShaderSize + WriteInto,
+{
+    /// Layout of the struct a [`Buffer`] of `T` is read as, its length
+    /// followed by a runtime-sized array named `elements`.
+    pub fn layout(name: &'static str, elements: &'static str) -> Layout {
+        Layout {
+            name,
+            fields: vec![
+                Field {
+                    name: "count",
+                    wgsl: u32::WGSL.to_owned(),
+                    offset: 0,
+                },
+                Field {
+                    name: elements,
+                    wgsl: format!("array<{}>", T::layout().name),
+                    offset: Self::offset(0),
+                },
+            ],
+            size: None,
+        }
+    }
+}
+
+/// Structs declared in every shader by including `storage.wgsl`.
+pub fn layouts() -> Vec<Layout> {
+    vec![
+        Globals::layout(),
+        Agent::layout(),
+        Buffer::<Agent>::layout("AgentBuffer", "agents"),
+        Feature::layout(),
+        Buffer::<Feature>::layout("FeatureBuffer", "features"),
+        Population::layout(),
+        Camera::layout(),
+        Palette::layout(),
+        Brush::layout(),
+        Sprites::layout(),
+        PostParams::layout(),
+    ]
+}
+
+/// Contents of `storage.wgsl`, the WGSL declarations of [`layouts`].
+pub fn declarations() -> String {
+    let mut source = "// Generated from the structs in `storage.rs`\n".to_owned();
+    for layout in layouts() {
+        source += "\n";
+        source += &layout.declaration();
+    }
+    source
+}
+
+/// Checks that the structs of `module` that are shared with Rust are laid
+/// out as encase writes them, naming the first one that isn't.
+pub fn check_layouts(shader: &str, module: &naga::Module) -> Result<()> {
+    for layout in layouts() {
+        let ty = module
+            .types
+            .iter()
+            .find(|(_, ty)| ty.name.as_deref() == Some(layout.name));
+        if let Some(difference) = ty.and_then(|(_, ty)| layout.difference(&ty.inner)) {
+            return Err(Error::Layout {
+                shader: shader.to_owned(),
+                name: layout.name.to_owned(),
+                difference,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/*
+    Types
+*/
+
+// The `ShaderType` derive emits field assertions next to each struct that are
+// never called, so the derived structs live in their own module allowing them
+#[allow(dead_code)]
+mod types;
+
+use types::SizedBuffer;
+pub use types::{Agent, Brush, Camera, Feature, Globals, Palette, Population, PostParams, Sprites};
+
 #[repr(C)]
 #[derive(Copy, Clone)]
 pub struct Vertex {
@@ -101,3 +340,59 @@ impl Vertex {
         }
     }
 }
+
+impl Agent {
+    /// Fields read by the sprite pass, at the offsets `ShaderType` lays them
+    /// out at. The tests check them against [`Agent::layout`].
+    const INSTANCE_ATTRIBS: [wgpu::VertexAttribute; 4] = [
+        wgpu::VertexAttribute {
+            format: wgpu::VertexFormat::Float32x2,
+            offset: 0,
+            shader_location: 0,
+        },
+        wgpu::VertexAttribute {
+            format: wgpu::VertexFormat::Float32x2,
+            offset: 8,
+            shader_location: 1,
+        },
+        wgpu::VertexAttribute {
+            format: wgpu::VertexFormat::Uint32,
+            offset: 40,
+            shader_location: 2,
+        },
+        wgpu::VertexAttribute {
+            format: wgpu::VertexFormat::Unorm8x4,
+            offset: 56,
+            shader_location: 3,
+        },
+    ];
+
+    /// Layout of the agents buffer used as a per-instance vertex buffer.
+    pub fn instance_desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: Self::SHADER_SIZE.get(),
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::INSTANCE_ATTRIBS,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instance_attributes_match_the_agent_layout() {
+        let layout = Agent::layout();
+        let fields = ["position", "velocity", "alive", "color"];
+        for (attribute, name) in Agent::INSTANCE_ATTRIBS.iter().zip(fields) {
+            let field = layout.fields.iter().find(|field| field.name == name);
+            assert_eq!(
+                field.map(|field| field.offset),
+                Some(attribute.offset),
+                "{}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Structs shared with the shaders, laid out by the `ShaderType` derive.
+
+use encase::{ArrayLength, ShaderSize, ShaderType};
+
+use super::{Field, Layout, WgslStruct, WgslType};
+
+#[derive(ShaderType)]
+pub(super) struct SizedBuffer<'a, T: ShaderSize + 'a> {
+    length: ArrayLength,
+
+    #[size(runtime)]
+    buffer: &'a [T],
+}
+
+impl<'a, T> SizedBuffer<'a, T>
+where
+    T: ShaderSize + 'a,
+{
+    pub(super) fn new(buffer: &'a [T]) -> Self {
+        Self {
+            length: ArrayLength,
+            buffer,
+        }
+    }
+}
+
+wgsl_struct! {
+    #[derive(ShaderType)]
+    pub struct Globals {
+        pub dt: f32,
+        pub time: f32,
+        pub boundary: u32,
+        /// Trail intensity lost per second
+        pub decay: f32,
+        pub num_agents: u32,
+        pub frame: u32,
+        /// Whether agents die and reproduce, the lifecycle fields below are unused otherwise
+        pub lifecycle: u32,
+        pub metabolism: f32,
+        pub feeding: f32,
+        pub reproduction_energy: f32,
+        pub reproduction_trail: f32,
+        pub max_age: f32,
+        pub mutation: f32,
+        /// Effect of the mask, 0 without one and otherwise one more than its `MaskMode`
+        pub mask_mode: u32,
+        pub mask_fit: u32,
+        pub mask_strength: f32,
+        pub color_source: u32,
+        pub color_range: f32,
+        pub sensor_angle: f32,
+        pub sensor_distance: f32,
+        pub turn_rate: f32,
+    }
+}
+
+wgsl_struct! {
+    #[derive(ShaderType, Clone, Copy, Debug, PartialEq)]
+    pub struct Agent {
+        pub position: glam::f32::Vec2,
+        pub velocity: glam::f32::Vec2,
+        /// Multipliers of sensor angle, turn rate, speed and sensor distance
+        pub genome: glam::f32::Vec4,
+        pub energy: f32,
+        pub age: f32,
+        pub alive: u32,
+        pub species: u32,
+        /// Where the agent, or the first of its ancestors, was spawned
+        pub origin: glam::f32::Vec2,
+        /// Packed rgba8 colour of its last deposit
+        pub color: u32,
+    }
+}
+
+wgsl_struct! {
+    /// Food source, attractor or field of the environment, in pixels.
+    #[derive(ShaderType)]
+    pub struct Feature {
+        pub kind: u32,
+        pub strength: f32,
+        pub radius: f32,
+        pub position: glam::f32::Vec2,
+        pub velocity: glam::f32::Vec2,
+    }
+}
+
+wgsl_struct! {
+    #[derive(ShaderType)]
+    pub struct Camera {
+        pub offset: glam::f32::Vec2,
+        pub scale: glam::f32::Vec2,
+    }
+}
+
+wgsl_struct! {
+    #[derive(ShaderType)]
+    pub struct PostParams {
+        /// Meaning depends on the stage, see `post.rs`
+        pub values: glam::f32::Vec4,
+        pub frame: u32,
+    }
+}
+
+wgsl_struct! {
+    #[derive(ShaderType)]
+    pub struct Sprites {
+        pub domain: glam::f32::Vec2,
+        /// Size of the render target in pixels
+        pub viewport: glam::f32::Vec2,
+        pub size: f32,
+        pub opacity: f32,
+        pub length: f32,
+        pub shape: u32,
+    }
+}
+
+wgsl_struct! {
+    #[derive(ShaderType)]
+    pub struct Palette {
+        /// Linear background colour
+        pub background: glam::f32::Vec3,
+        pub range: f32,
+        pub source: u32,
+    }
+}
+
+wgsl_struct! {
+    #[derive(ShaderType)]
+    pub struct Brush {
+        pub position: glam::f32::Vec2,
+        /// First pixel of the painted area
+        pub origin: glam::UVec2,
+        pub radius: f32,
+        pub strength: f32,
+        pub falloff: f32,
+        pub dt: f32,
+        pub kind: u32,
+    }
+}
+
+wgsl_struct! {
+    /// Counters written by the compaction pass of the agent lifecycle.
+    #[derive(ShaderType)]
+    pub struct Population {
+        pub live: u32 as "atomic<u32>",
+        pub free: i32 as "atomic<i32>",
+    }
+}
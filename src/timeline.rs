@@ -0,0 +1,303 @@
+//! Keyframed animation of scene parameters.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Result, palette::Gradient, scene::Scene};
+
+/// Stops gradients are sampled at while cross-fading between keyframes.
+const BLEND_STOPS: usize = 16;
+
+/// Keyframed parameters, driven by simulation time so that headless runs
+/// with a fixed timestep reproduce the animation exactly.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Timeline {
+    pub tracks: Vec<Track>,
+    /// Palette gradients cross-faded between keyframes
+    pub gradient: Vec<Keyframe<Gradient>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Track {
+    pub parameter: Parameter,
+    pub keyframes: Vec<Keyframe<f32>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe<T> {
+    /// Seconds since the simulation started
+    pub time: f32,
+    pub value: T,
+    /// How the value changes on the way to the next keyframe
+    #[serde(default)]
+    pub curve: Curve,
+}
+
+/// Interpolation from one keyframe to the next.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Curve {
+    #[default]
+    Linear,
+    /// Slow at both ends
+    Ease,
+    /// Holds the value until the next keyframe
+    Step,
+    /// Cubic Bézier from (0, 0) to (1, 1) like CSS's `cubic-bezier`, with
+    /// time along x and progress along y
+    Bezier { x1: f32, y1: f32, x2: f32, y2: f32 },
+}
+
+/// Scene value a track animates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Parameter {
+    SensorAngle,
+    SensorDistance,
+    TurnRate,
+    Decay,
+    /// The palette's range
+    ColorRange,
+}
+
+/// Values of every animated parameter at one point in time.
+pub struct Sample {
+    pub values: Vec<(Parameter, f32)>,
+    pub gradient: Option<Gradient>,
+}
+
+impl Timeline {
+    /// Loads a timeline saved on its own, to reuse it across scenes.
+    pub fn load(path: &Path) -> Result<Self> {
+        crate::scene::load_ron(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        crate::scene::save_ron(self, path)
+    }
+
+    /// Values at `time`, leaving out tracks without keyframes.
+    pub fn sample(&self, time: f32) -> Sample {
+        let values = self
+            .tracks
+            .iter()
+            .filter_map(|track| Some((track.parameter, track.value(time)?)))
+            .collect();
+
+        let gradient = interpolate(&self.gradient, time, |a, b, t| a.blend(b, t, BLEND_STOPS));
+
+        Sample { values, gradient }
+    }
+
+    /// Time of the last keyframe of any track.
+    pub fn duration(&self) -> f32 {
+        let times = self
+            .tracks
+            .iter()
+            .flat_map(|track| track.keyframes.iter().map(|keyframe| keyframe.time));
+        let gradient_times = self.gradient.iter().map(|keyframe| keyframe.time);
+
+        times.chain(gradient_times).fold(0.0, f32::max)
+    }
+}
+
+impl Track {
+    pub fn new(parameter: Parameter) -> Self {
+        Self {
+            parameter,
+            keyframes: Vec::new(),
+        }
+    }
+
+    /// Value at `time`, if the track has any keyframes.
+    pub fn value(&self, time: f32) -> Option<f32> {
+        interpolate(&self.keyframes, time, |a, b, t| a + (b - a) * t)
+    }
+}
+
+impl<T> Keyframe<T> {
+    pub fn new(time: f32, value: T) -> Self {
+        Self {
+            time,
+            value,
+            curve: Curve::default(),
+        }
+    }
+}
+
+impl Curve {
+    pub const ALL: [Curve; 4] = [
+        Curve::Linear,
+        Curve::Ease,
+        Curve::Step,
+        Curve::Bezier {
+            x1: 0.25,
+            y1: 0.1,
+            x2: 0.25,
+            y2: 1.0,
+        },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Curve::Linear => "Linear",
+            Curve::Ease => "Ease",
+            Curve::Step => "Step",
+            Curve::Bezier { .. } => "Bezier",
+        }
+    }
+
+    /// Progress towards the next keyframe after a fraction `t` of the time between them.
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match *self {
+            Curve::Linear => t,
+            Curve::Ease => t * t * (3.0 - 2.0 * t),
+            Curve::Step => 0.0,
+            Curve::Bezier { x1, y1, x2, y2 } => {
+                // Control points outside 0 to 1 in time would make the curve go back in time
+                let (x1, x2) = (x1.clamp(0.0, 1.0), x2.clamp(0.0, 1.0));
+                let s = solve_bezier(x1, x2, t);
+                bezier(y1, y2, s)
+            }
+        }
+    }
+}
+
+impl Parameter {
+    pub const ALL: [Parameter; 5] = [
+        Parameter::SensorAngle,
+        Parameter::SensorDistance,
+        Parameter::TurnRate,
+        Parameter::Decay,
+        Parameter::ColorRange,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Parameter::SensorAngle => "Sensor angle",
+            Parameter::SensorDistance => "Sensor distance",
+            Parameter::TurnRate => "Turn rate",
+            Parameter::Decay => "Decay",
+            Parameter::ColorRange => "Colour range",
+        }
+    }
+
+    pub fn value(self, scene: &Scene) -> f32 {
+        match self {
+            Parameter::SensorAngle => scene.behaviour.sensor_angle,
+            Parameter::SensorDistance => scene.behaviour.sensor_distance,
+            Parameter::TurnRate => scene.behaviour.turn_rate,
+            Parameter::Decay => scene.behaviour.decay,
+            Parameter::ColorRange => scene.palette.range,
+        }
+    }
+
+    pub fn value_mut(self, scene: &mut Scene) -> &mut f32 {
+        match self {
+            Parameter::SensorAngle => &mut scene.behaviour.sensor_angle,
+            Parameter::SensorDistance => &mut scene.behaviour.sensor_distance,
+            Parameter::TurnRate => &mut scene.behaviour.turn_rate,
+            Parameter::Decay => &mut scene.behaviour.decay,
+            Parameter::ColorRange => &mut scene.palette.range,
+        }
+    }
+}
+
+/// Value at `time` between the keyframes around it, holding the first and
+/// last values outside of them.
+fn interpolate<T: Clone>(
+    keyframes: &[Keyframe<T>],
+    time: f32,
+    mix: impl Fn(&T, &T, f32) -> T,
+) -> Option<T> {
+    let mut sorted: Vec<&Keyframe<T>> = keyframes.iter().collect();
+    sorted.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let after = sorted.iter().position(|keyframe| keyframe.time > time);
+    match after {
+        None => sorted.last().map(|keyframe| keyframe.value.clone()),
+        Some(0) => Some(sorted[0].value.clone()),
+        Some(i) => {
+            let (a, b) = (sorted[i - 1], sorted[i]);
+            let t = (time - a.time) / (b.time - a.time).max(f32::EPSILON);
+            Some(mix(&a.value, &b.value, a.curve.apply(t)))
+        }
+    }
+}
+
+/// One coordinate of a cubic Bézier from 0 to 1 with control points `p1` and `p2`.
+fn bezier(p1: f32, p2: f32, s: f32) -> f32 {
+    let r = 1.0 - s;
+    3.0 * r * r * s * p1 + 3.0 * r * s * s * p2 + s * s * s
+}
+
+/// Parameter at which the curve's x coordinate reaches `x`, by bisection
+/// since x only grows with control points between 0 and 1.
+fn solve_bezier(x1: f32, x2: f32, x: f32) -> f32 {
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..24 {
+        let mid = 0.5 * (low + high);
+        if bezier(x1, x2, mid) < x {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+
+    0.5 * (low + high)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(keyframes: Vec<Keyframe<f32>>) -> Track {
+        Track {
+            parameter: Parameter::Decay,
+            keyframes,
+        }
+    }
+
+    #[test]
+    fn curves_start_at_zero_and_end_at_one() {
+        for curve in Curve::ALL {
+            assert!(curve.apply(0.0).abs() < 1e-4, "{}", curve.name());
+            if curve != Curve::Step {
+                assert!((curve.apply(1.0) - 1.0).abs() < 1e-4, "{}", curve.name());
+            }
+        }
+    }
+
+    #[test]
+    fn curves_never_go_back() {
+        for curve in Curve::ALL {
+            let progress: Vec<f32> = (0..=100).map(|i| curve.apply(i as f32 / 100.0)).collect();
+            assert!(
+                progress.windows(2).all(|pair| pair[0] <= pair[1]),
+                "{}",
+                curve.name()
+            );
+        }
+    }
+
+    #[test]
+    fn first_and_last_values_hold_outside_the_keyframes() {
+        let track = track(vec![Keyframe::new(3.0, 6.0), Keyframe::new(1.0, 2.0)]);
+        assert_eq!(track.value(0.0), Some(2.0));
+        assert_eq!(track.value(1.0), Some(2.0));
+        assert_eq!(track.value(2.0), Some(4.0));
+        assert_eq!(track.value(3.0), Some(6.0));
+        assert_eq!(track.value(10.0), Some(6.0));
+        assert_eq!(self::track(Vec::new()).value(1.0), None);
+    }
+
+    #[test]
+    fn steps_hold_until_the_next_keyframe() {
+        let mut first = Keyframe::new(0.0, 1.0);
+        first.curve = Curve::Step;
+        let track = track(vec![first, Keyframe::new(1.0, 5.0)]);
+        assert_eq!(track.value(0.99), Some(1.0));
+        assert_eq!(track.value(1.0), Some(5.0));
+    }
+}
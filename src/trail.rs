@@ -0,0 +1,425 @@
+//! Storage formats of the trail map.
+
+use serde::{Deserialize, Serialize};
+
+use crate::pipeline::bindings::Resource;
+
+/// Lets storage textures be read and written in the same pass.
+const ADAPTER_FORMAT_FEATURES: wgpu::Features =
+    wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES;
+
+/// Storage layout of the trail map that agents sense and deposit into.
+// Variants are named after the texture format they use
+#[allow(clippy::enum_variant_names)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    Hash,
+    PartialOrd,
+    Ord,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+)]
+pub enum TrailFormat {
+    /// Full precision colour and intensity per pixel
+    Rgba32Float,
+    /// Half precision colour and intensity per pixel
+    Rgba16Float,
+    /// Single channel intensity plus a packed colour buffer
+    R32Float,
+    /// Half precision intensity for each of two species
+    Rg16Float,
+}
+
+/// How compute passes read and write the trail map.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TrailAccess {
+    /// A single texture updated in place, which needs read-write storage
+    /// textures and so adapter-specific format features
+    ReadWrite,
+    /// Two textures taking turns, each frame reading the one written by the
+    /// last and writing the other through write-only storage
+    PingPong,
+}
+
+/// Trail formats an adapter can use, and how each is accessed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrailSupport {
+    formats: Vec<(TrailFormat, TrailAccess)>,
+}
+
+/// GPU resources backing the trail map.
+pub struct Trail {
+    format: TrailFormat,
+    access: TrailAccess,
+    /// The texture holding the latest trail map first, then the one written
+    /// next for ping-pong access
+    textures: Vec<(wgpu::Texture, wgpu::TextureView)>,
+    colors: Option<wgpu::Buffer>,
+}
+
+impl TrailFormat {
+    pub const ALL: [TrailFormat; 4] = [
+        TrailFormat::Rgba32Float,
+        TrailFormat::Rgba16Float,
+        TrailFormat::R32Float,
+        TrailFormat::Rg16Float,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrailFormat::Rgba32Float => "Rgba32Float",
+            TrailFormat::Rgba16Float => "Rgba16Float",
+            TrailFormat::R32Float => "R32Float + colour",
+            TrailFormat::Rg16Float => "Rg16Float species",
+        }
+    }
+
+    pub fn texture_format(&self) -> wgpu::TextureFormat {
+        match self {
+            TrailFormat::Rgba32Float => wgpu::TextureFormat::Rgba32Float,
+            TrailFormat::Rgba16Float => wgpu::TextureFormat::Rgba16Float,
+            TrailFormat::R32Float => wgpu::TextureFormat::R32Float,
+            TrailFormat::Rg16Float => wgpu::TextureFormat::Rg16Float,
+        }
+    }
+
+    fn wgsl_format(&self) -> &'static str {
+        match self {
+            TrailFormat::Rgba32Float => "rgba32float",
+            TrailFormat::Rgba16Float => "rgba16float",
+            TrailFormat::R32Float => "r32float",
+            TrailFormat::Rg16Float => "rg16float",
+        }
+    }
+
+    /// Memory used per simulated pixel, including any colour buffer.
+    pub fn bytes_per_pixel(&self) -> u32 {
+        match self {
+            TrailFormat::Rgba32Float => 16,
+            TrailFormat::Rgba16Float => 8,
+            TrailFormat::R32Float => 4 + 4,
+            TrailFormat::Rg16Float => 4,
+        }
+    }
+
+    /// Bits of mantissa precision of the stored intensity.
+    pub fn precision_bits(&self) -> u32 {
+        match self {
+            TrailFormat::Rgba32Float | TrailFormat::R32Float => 23,
+            TrailFormat::Rgba16Float | TrailFormat::Rg16Float => 10,
+        }
+    }
+
+    pub fn has_color_buffer(&self) -> bool {
+        *self == TrailFormat::R32Float
+    }
+
+    /// How the trail map can be accessed in this format on a device with
+    /// `features` opened on `adapter`, preferring in-place updates.
+    pub fn access(&self, adapter: &wgpu::Adapter, features: wgpu::Features) -> Option<TrailAccess> {
+        let format = self.texture_format();
+        let format_features = if features.contains(ADAPTER_FORMAT_FEATURES) {
+            adapter.get_texture_format_features(format)
+        } else {
+            format.guaranteed_format_features(features)
+        };
+
+        self.access_with(format_features, adapter.get_info().backend)
+    }
+
+    /// How the trail map can be accessed given the `features` of its
+    /// texture format on `backend`.
+    fn access_with(
+        &self,
+        features: wgpu::TextureFormatFeatures,
+        backend: wgpu::Backend,
+    ) -> Option<TrailAccess> {
+        // GLES only allows read-write access to single channel 32-bit images,
+        // regardless of what the adapter reports
+        let gles = backend == wgpu::Backend::Gl;
+        let read_write = features
+            .flags
+            .contains(wgpu::TextureFormatFeatureFlags::STORAGE_READ_WRITE);
+        if read_write && (!gles || *self == TrailFormat::R32Float) {
+            return Some(TrailAccess::ReadWrite);
+        }
+
+        features
+            .allowed_usages
+            .contains(wgpu::TextureUsages::STORAGE_BINDING)
+            .then_some(TrailAccess::PingPong)
+    }
+
+    /// Preprocessor definitions picking the trail functions of this format,
+    /// see `trail/compute.wgsl` and `trail/color.wgsl`.
+    pub(crate) fn defines(&self) -> [(&'static str, &'static str); 2] {
+        let kind = match self {
+            TrailFormat::Rgba32Float | TrailFormat::Rgba16Float => "TRAIL_RGBA",
+            TrailFormat::R32Float => "TRAIL_INTENSITY",
+            TrailFormat::Rg16Float => "TRAIL_SPECIES",
+        };
+
+        [("TRAIL_FORMAT", self.wgsl_format()), (kind, "")]
+    }
+}
+
+impl TrailAccess {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrailAccess::ReadWrite => "Read-write",
+            TrailAccess::PingPong => "Ping-pong",
+        }
+    }
+
+    /// Preprocessor definition picking the trail access functions.
+    pub(crate) fn define(&self) -> (&'static str, &'static str) {
+        match self {
+            TrailAccess::ReadWrite => ("TRAIL_READ_WRITE", ""),
+            TrailAccess::PingPong => ("TRAIL_PING_PONG", ""),
+        }
+    }
+
+    /// Number of textures the trail map is kept in.
+    fn textures(&self) -> usize {
+        match self {
+            TrailAccess::ReadWrite => 1,
+            TrailAccess::PingPong => 2,
+        }
+    }
+}
+
+impl TrailSupport {
+    /// Formats usable on a device with `features` opened on `adapter`.
+    pub fn new(adapter: &wgpu::Adapter, features: wgpu::Features) -> Self {
+        Self {
+            formats: TrailFormat::ALL
+                .into_iter()
+                .filter_map(|format| Some((format, format.access(adapter, features)?)))
+                .collect(),
+        }
+    }
+
+    /// Usable formats in order of preference.
+    pub fn formats(&self) -> Vec<TrailFormat> {
+        self.formats.iter().map(|(format, _)| *format).collect()
+    }
+
+    pub fn access(&self, format: TrailFormat) -> Option<TrailAccess> {
+        self.formats
+            .iter()
+            .find(|(supported, _)| *supported == format)
+            .map(|(_, access)| *access)
+    }
+
+    /// The same formats, all accessed by ping-pong. Updating in place races
+    /// between pixels, while ping-ponging gives the same result every run.
+    pub fn ping_pong(&self) -> Self {
+        Self {
+            formats: self
+                .formats
+                .iter()
+                .map(|(format, _)| (*format, TrailAccess::PingPong))
+                .collect(),
+        }
+    }
+
+    /// Support for a single format accessed one way, to test either access
+    /// whatever the adapter prefers.
+    #[cfg(test)]
+    pub(crate) fn only(format: TrailFormat, access: TrailAccess) -> Self {
+        Self {
+            formats: vec![(format, access)],
+        }
+    }
+}
+
+/// Features that allow read-write trail maps, requested when the adapter has them.
+pub fn optional_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+    adapter.features() & ADAPTER_FORMAT_FEATURES
+}
+
+impl Trail {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        format: TrailFormat,
+        access: TrailAccess,
+        size: glam::UVec2,
+    ) -> Self {
+        let textures = (0..access.textures())
+            .map(|_| {
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Trail texture"),
+                    size: wgpu::Extent3d {
+                        width: size.x,
+                        height: size.y,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: format.texture_format(),
+                    usage: wgpu::TextureUsages::STORAGE_BINDING
+                        | wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::COPY_SRC,
+                    view_formats: &[format.texture_format()],
+                });
+
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                (texture, view)
+            })
+            .collect();
+
+        let colors = format.has_color_buffer().then(|| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Trail colour buffer"),
+                size: size.x as u64 * size.y as u64 * std::mem::size_of::<u32>() as u64,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            })
+        });
+
+        Self {
+            format,
+            access,
+            textures,
+            colors,
+        }
+    }
+
+    pub fn format(&self) -> TrailFormat {
+        self.format
+    }
+
+    pub fn access(&self) -> TrailAccess {
+        self.access
+    }
+
+    /// The latest trail map.
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.textures[0].1
+    }
+
+    pub(crate) fn texture(&self) -> &wgpu::Texture {
+        &self.textures[0].0
+    }
+
+    pub fn colors(&self) -> Option<&wgpu::Buffer> {
+        self.colors.as_ref()
+    }
+
+    /// Resources of the compute passes, reading the latest trail map and,
+    /// for ping-pong access, writing the other one.
+    pub(crate) fn resources(&self) -> Vec<Resource<'_>> {
+        let mut resources: Vec<_> = self
+            .textures
+            .iter()
+            .zip(["tex", "tex_next"])
+            .map(|((_, view), name)| (name, wgpu::BindingResource::TextureView(view)))
+            .collect();
+
+        if let Some(colors) = &self.colors {
+            resources.push(("trail_colors", colors.as_entire_binding()));
+        }
+
+        resources
+    }
+
+    /// Makes the texture written by the last frame the latest trail map.
+    pub(crate) fn swap(&mut self) {
+        self.textures.rotate_left(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features(
+        usages: wgpu::TextureUsages,
+        flags: wgpu::TextureFormatFeatureFlags,
+    ) -> wgpu::TextureFormatFeatures {
+        wgpu::TextureFormatFeatures {
+            allowed_usages: usages,
+            flags,
+        }
+    }
+
+    #[test]
+    fn read_write_is_preferred() {
+        let read_write = features(
+            wgpu::TextureUsages::STORAGE_BINDING,
+            wgpu::TextureFormatFeatureFlags::STORAGE_READ_WRITE,
+        );
+        let write_only = features(
+            wgpu::TextureUsages::STORAGE_BINDING,
+            wgpu::TextureFormatFeatureFlags::empty(),
+        );
+        let sampled = features(
+            wgpu::TextureUsages::TEXTURE_BINDING,
+            wgpu::TextureFormatFeatureFlags::empty(),
+        );
+
+        for format in TrailFormat::ALL {
+            let access = |features| format.access_with(features, wgpu::Backend::Vulkan);
+            assert_eq!(access(read_write), Some(TrailAccess::ReadWrite));
+            assert_eq!(access(write_only), Some(TrailAccess::PingPong));
+            assert_eq!(access(sampled), None);
+        }
+    }
+
+    #[test]
+    fn gles_reads_and_writes_only_single_channel_32_bit_images() {
+        let read_write = features(
+            wgpu::TextureUsages::STORAGE_BINDING,
+            wgpu::TextureFormatFeatureFlags::STORAGE_READ_WRITE,
+        );
+
+        for format in TrailFormat::ALL {
+            let expected = match format {
+                TrailFormat::R32Float => TrailAccess::ReadWrite,
+                _ => TrailAccess::PingPong,
+            };
+            assert_eq!(
+                format.access_with(read_write, wgpu::Backend::Gl),
+                Some(expected),
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn support_looks_up_formats() {
+        let support = TrailSupport {
+            formats: vec![
+                (TrailFormat::Rgba32Float, TrailAccess::ReadWrite),
+                (TrailFormat::R32Float, TrailAccess::PingPong),
+            ],
+        };
+
+        assert_eq!(
+            support.formats(),
+            [TrailFormat::Rgba32Float, TrailFormat::R32Float]
+        );
+        assert_eq!(
+            support.access(TrailFormat::Rgba32Float),
+            Some(TrailAccess::ReadWrite)
+        );
+        assert_eq!(
+            support.access(TrailFormat::R32Float),
+            Some(TrailAccess::PingPong)
+        );
+        assert_eq!(support.access(TrailFormat::Rg16Float), None);
+
+        let ping_pong = support.ping_pong();
+        assert_eq!(ping_pong.formats(), support.formats());
+        assert!(ping_pong
+            .formats()
+            .into_iter()
+            .all(|format| ping_pong.access(format) == Some(TrailAccess::PingPong)));
+    }
+}
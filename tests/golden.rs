@@ -0,0 +1,41 @@
+//! Renders the golden image cases and compares them with their goldens. Run
+//! `cargo run -- golden --bless` to update the goldens after an intended
+//! change to how scenes look.
+
+use std::path::Path;
+
+use blossim::{golden, Error};
+
+#[test]
+fn renders_match_goldens() {
+    let options = golden::Options {
+        cases: Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/cases.ron"),
+        output: Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden"),
+        bless: false,
+        adapter: Default::default(),
+    };
+
+    let reports = match pollster::block_on(golden::run(&options)) {
+        Ok(reports) => reports,
+        Err(Error::NoAdapter) => {
+            eprintln!("No graphics adapter was found, skipping the golden images");
+            return;
+        }
+        Err(error) => panic!("{error}"),
+    };
+
+    for report in &reports {
+        println!("{report}");
+    }
+
+    let failed: Vec<_> = reports
+        .iter()
+        .filter(|report| !report.outcome.passed())
+        .map(|report| report.name.as_str())
+        .collect();
+    assert!(
+        failed.is_empty(),
+        "{failed:?} differ from their goldens, see {}",
+        options.output.display()
+    );
+}
@@ -0,0 +1,14 @@
+// Agents that wander at random where the trail around them is thin, and
+// deposit less the more crowded it gets
+
+fn steer(agent: Agent, ctx: SenseContext) -> vec2<f32> {
+    let crowd = sample_area(warp_clamp(agent.position + ctx.sensor), 3.0, agent.species);
+    var seed = ctx.seed;
+    let wander = (random(&seed) - 0.5) * ctx.turn_rate / (1.0 + crowd);
+    return rotate(agent.velocity, wander + ctx.turn_rate * sign(ctx.left - ctx.right));
+}
+
+fn deposit(agent: Agent) -> vec4<f32> {
+    let crowd = sample(agent.position, agent.species);
+    return vec4<f32>(gradient(agent.energy * 0.5), 1.0 / (1.0 + crowd));
+}